@@ -7,11 +7,26 @@ use services::state::*;
 use services::{config::*, file::*};
 use sqlx::PgPool;
 
-use crate::services::automation::AutomationService;
-use crate::services::email::{get_emails, list_emails, save_click, send_email};
+use crate::services::automation::{
+    create_automation, debug_automation, delete_automation, delete_dead_letter, get_automation,
+    list_automations, list_dead_letters, pause_automation, resume_automation, retry_dead_letter,
+    run_automation, run_webhook, update_automation, AutomationService,
+};
+use crate::services::campaign::{
+    create_campaign, follow_link, get_campaign_details, get_campaign_stats, list_campaigns,
+    send_campaign_now,
+};
+use crate::services::email::Mailer;
+use crate::services::email::{
+    delete_email, get_attachment, get_email, get_emails, list_emails, list_folders, move_email,
+    save_click, send_email, set_email_flags,
+};
+use crate::services::email_template::render_email_template;
+use crate::services::imap_pool::ImapPool;
 use crate::services::llm::{chat, chat_stream};
 use crate::services::llm_local::chat_completions_local;
 use crate::services::llm_provider::chat_completions;
+use crate::services::outbox::{cancel_scheduled_email, list_outbox, schedule_email, OutboxSender};
 use crate::services::web_automation::{initialize_browser_pool, BrowserPool};
 
 mod models;
@@ -20,9 +35,37 @@ mod services;
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
+
+    // gbserver test-scripts [dir]: runs the *.test.toml script tests and exits
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("test-scripts") {
+        let dir = args.get(2).map(String::as_str).unwrap_or("src/prompts");
+        std::process::exit(services::script_test::test_scripts_command(dir));
+    }
+
+    // gbserver fmt [--check] [paths] / gbserver lint [paths]: formats or
+    // checks the .bas scripts and exits
+    if args.get(1).map(String::as_str) == Some("fmt") {
+        std::process::exit(services::script_fmt::fmt_command(&args[2..]));
+    }
+    if args.get(1).map(String::as_str) == Some("lint") {
+        std::process::exit(services::script_lint::lint_command(&args[2..]));
+    }
+
+    // gbserver lsp: language server for .bas scripts over stdin/stdout
+    if args.get(1).map(String::as_str) == Some("lsp") {
+        services::script_lsp::serve_stdio().await;
+        return Ok(());
+    }
+
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
     let config = AppConfig::from_env();
+    if config.email.tracking_secret.is_none() {
+        log::warn!(
+            "EMAIL_TRACKING_SECRET is not set: campaigns are disabled and opens are not counted"
+        );
+    }
     let db_url = config.database_url();
     let db_custom_url = config.database_custom_url();
     let db = PgPool::connect(&db_url).await.unwrap();
@@ -51,19 +94,30 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to initialize browser pool");
 
+    let imap = Arc::new(ImapPool::new());
+    let _imap_keep_alive = imap.clone().spawn_keep_alive();
+    let mailer = Arc::new(Mailer::new(&config.email).expect("Failed to set up SMTP transport"));
+
     let app_state = web::Data::new(AppState {
         db: db.into(),
         db_custom: db_custom.into(),
         config: Some(config.clone()),
         minio_client: minio_client.into(),
         browser_pool: browser_pool.clone(),
+        imap,
+        mailer,
     });
 
     // Start automation service in background
     let automation_state = app_state.get_ref().clone(); // This gets the Arc<AppState>
 
-    let automation = AutomationService::new(automation_state, "src/prompts");
-    let _automation_handle = automation.spawn();
+    let automation = Arc::new(AutomationService::new(automation_state, "src/prompts", 5));
+    let _automation_handle = automation.clone().spawn();
+    let automation_data = web::Data::from(automation);
+
+    // Send the scheduled and queued emails in background
+    let outbox = Arc::new(OutboxSender::new(app_state.get_ref().clone()));
+    let _outbox_handle = outbox.spawn();
 
     // Start HTTP server
     HttpServer::new(move || {
@@ -79,16 +133,47 @@ async fn main() -> std::io::Result<()> {
             .wrap(Logger::default())
             .wrap(Logger::new("%a %{User-Agent}i"))
             .app_data(app_state.clone())
+            .app_data(automation_data.clone())
             .service(upload_file)
+            .service(file_events)
             .service(list_file)
             .service(save_click)
             .service(get_emails)
+            .service(create_campaign)
+            .service(list_campaigns)
+            .service(get_campaign_details)
+            .service(send_campaign_now)
+            .service(get_campaign_stats)
+            .service(follow_link)
             .service(list_emails)
+            .service(list_folders)
+            .service(get_email)
+            .service(get_attachment)
+            .service(set_email_flags)
+            .service(move_email)
+            .service(delete_email)
             .service(send_email)
+            .service(render_email_template)
+            .service(schedule_email)
+            .service(cancel_scheduled_email)
+            .service(list_outbox)
             .service(chat_stream)
             .service(chat_completions)
             .service(chat_completions_local)
             .service(chat)
+            .service(list_automations)
+            .service(create_automation)
+            .service(get_automation)
+            .service(update_automation)
+            .service(delete_automation)
+            .service(pause_automation)
+            .service(resume_automation)
+            .service(run_automation)
+            .service(debug_automation)
+            .service(list_dead_letters)
+            .service(retry_dead_letter)
+            .service(delete_dead_letter)
+            .service(run_webhook)
     })
     .bind((config.server.host.clone(), config.server.port))?
     .run()
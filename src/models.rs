@@ -1 +1,3 @@
 pub mod automation_model;
+pub mod campaign_model;
+pub mod outbox_model;
@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
@@ -9,6 +9,9 @@ pub enum TriggerKind {
     TableUpdate = 1,
     TableInsert = 2,
     TableDelete = 3,
+    Webhook = 4,
+    EmailReceived = 5,
+    FileUploaded = 6,
 }
 
 impl TriggerKind {
@@ -18,12 +21,32 @@ impl TriggerKind {
             1 => Some(Self::TableUpdate),
             2 => Some(Self::TableInsert),
             3 => Some(Self::TableDelete),
+            4 => Some(Self::Webhook),
+            5 => Some(Self::EmailReceived),
+            6 => Some(Self::FileUploaded),
             _ => None,
         }
     }
+
+    /// Maps the operation reported by the table notify trigger
+    pub fn from_operation(operation: &str) -> Option<Self> {
+        match operation {
+            "INSERT" => Some(Self::TableInsert),
+            "UPDATE" => Some(Self::TableUpdate),
+            "DELETE" => Some(Self::TableDelete),
+            _ => None,
+        }
+    }
+
+    pub fn is_table_trigger(&self) -> bool {
+        matches!(
+            self,
+            Self::TableUpdate | Self::TableInsert | Self::TableDelete
+        )
+    }
 }
 
-#[derive(Debug, FromRow, Serialize, Deserialize)]
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct Automation {
     pub id: Uuid,
     pub kind: i32, // Using number for trigger type
@@ -32,4 +55,190 @@ pub struct Automation {
     pub param: String,
     pub is_active: bool,
     pub last_triggered: Option<DateTime<Utc>>,
+    pub options: serde_json::Value, // Trigger-specific settings
+    pub timeout_secs: i32,
+    pub max_retries: i32,
+    pub last_error: Option<String>, // Error of the last failed run, cleared on success
+    #[serde(skip_serializing)]
+    pub webhook_secret: Option<String>, // HMAC key for webhook signatures
+}
+
+impl Automation {
+    pub fn table_options(&self) -> TableTriggerOptions {
+        serde_json::from_value(self.options.clone()).unwrap_or_default()
+    }
+
+    pub fn webhook_options(&self) -> WebhookOptions {
+        serde_json::from_value(self.options.clone()).unwrap_or_default()
+    }
+
+    pub fn email_options(&self) -> EmailTriggerOptions {
+        serde_json::from_value(self.options.clone()).unwrap_or_default()
+    }
+
+    pub fn file_options(&self) -> FileTriggerOptions {
+        serde_json::from_value(self.options.clone()).unwrap_or_default()
+    }
+}
+
+/// Settings for table triggers, stored in `options`
+#[derive(Debug, Deserialize)]
+pub struct TableTriggerOptions {
+    /// Run the script once for each changed row instead of once per batch
+    #[serde(default)]
+    pub per_row: bool,
+    #[serde(default = "default_max_rows")]
+    pub max_rows: i64,
+    /// Column set when a row is soft-deleted, used to detect TableDelete
+    #[serde(default = "default_soft_delete_column")]
+    pub soft_delete_column: String,
+}
+
+impl Default for TableTriggerOptions {
+    fn default() -> Self {
+        Self {
+            per_row: false,
+            max_rows: default_max_rows(),
+            soft_delete_column: default_soft_delete_column(),
+        }
+    }
+}
+
+fn default_max_rows() -> i64 {
+    100
+}
+
+fn default_soft_delete_column() -> String {
+    "deleted_at".to_string()
+}
+
+/// Settings for webhook triggers, stored in `options`
+#[derive(Debug, Deserialize)]
+pub struct WebhookOptions {
+    /// Header carrying the hex HMAC-SHA256 of the body, optionally prefixed with `sha256=`
+    #[serde(default = "default_signature_header")]
+    pub signature_header: String,
+}
+
+impl Default for WebhookOptions {
+    fn default() -> Self {
+        Self {
+            signature_header: default_signature_header(),
+        }
+    }
+}
+
+fn default_signature_header() -> String {
+    "X-Signature-256".to_string()
+}
+
+/// Filters for email-received triggers, stored in `options`; the folder is the `target`
+#[derive(Debug, Default, Deserialize)]
+pub struct EmailTriggerOptions {
+    /// Case-insensitive substring of the sender address
+    pub from: Option<String>,
+    /// Case-insensitive substring of the subject
+    pub subject: Option<String>,
+}
+
+impl EmailTriggerOptions {
+    pub fn matches(&self, sender: &str, subject: &str) -> bool {
+        let contains = |filter: &Option<String>, value: &str| {
+            filter.as_ref().map_or(true, |filter| {
+                value.to_lowercase().contains(&filter.to_lowercase())
+            })
+        };
+        contains(&self.from, sender) && contains(&self.subject, subject)
+    }
+}
+
+/// Settings for file-upload triggers, stored in `options`; the folder is the `target`
+#[derive(Debug, Default, Deserialize)]
+pub struct FileTriggerOptions {
+    /// Fire from bucket notifications, which also cover objects written outside
+    /// of `/files/upload`, instead of from the upload endpoint
+    #[serde(default)]
+    pub from_notifications: bool,
+}
+
+/// An object stored in the drive, passed to file-upload automations
+#[derive(Debug, Serialize)]
+pub struct FileUpload {
+    pub key: String,
+    pub size: i64,
+    pub content_type: Option<String>,
+    pub uploader: Option<String>,
+}
+
+/// Payload sent by the `gb_notify_table_change` trigger
+#[derive(Debug, Deserialize)]
+pub struct TableChange {
+    pub table: String,
+    pub operation: String,
+    pub pk_column: String,
+    pub pk: serde_json::Value,
+    /// The automation whose trigger reported the change
+    #[serde(default)]
+    pub automation: Option<Uuid>,
+}
+
+/// A run that still failed after all retries, kept so it can be replayed
+#[derive(Debug, FromRow, Serialize)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub automation_id: Uuid,
+    pub params: serde_json::Value,
+    pub error: String,
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAutomationRequest {
+    pub kind: i32,
+    pub target: Option<String>,
+    /// Cron pattern; weekdays count from 0 (Monday) to 6 (Sunday)
+    pub schedule: Option<String>,
+    pub param: String,
+    pub is_active: Option<bool>,
+    pub options: Option<serde_json::Value>,
+    pub timeout_secs: Option<i32>,
+    pub max_retries: Option<i32>,
+    pub secret: Option<String>,
+}
+
+/// Fields left out are kept; `target`, `schedule` and `secret` are cleared
+/// by an explicit null
+#[derive(Debug, Deserialize)]
+pub struct UpdateAutomationRequest {
+    pub kind: Option<i32>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub target: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub schedule: Option<Option<String>>,
+    pub param: Option<String>,
+    pub options: Option<serde_json::Value>,
+    pub timeout_secs: Option<i32>,
+    pub max_retries: Option<i32>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub secret: Option<Option<String>>,
+}
+
+// Tells a null field (`Some(None)`) from a missing one (`None`)
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RunAutomationRequest {
+    // Values for the script's PARAM declarations
+    #[serde(default)]
+    pub params: serde_json::Map<String, serde_json::Value>,
+    // Return every keyword call with its arguments and timing
+    #[serde(default)]
+    pub trace: bool,
 }
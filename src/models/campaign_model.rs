@@ -0,0 +1,66 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Created, not sent yet
+pub const CAMPAIGN_DRAFT: &str = "draft";
+/// Its messages are being queued in the background
+pub const CAMPAIGN_SENDING: &str = "sending";
+/// Its messages are in the outbox
+pub const CAMPAIGN_QUEUED: &str = "queued";
+/// Sending stopped at `last_error`; sending again resumes it
+pub const CAMPAIGN_FAILED: &str = "failed";
+
+/// A mailing: template `template` merged with every row of `recipient_query`
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Campaign {
+    pub id: Uuid,
+    pub name: String,
+    pub template: String,
+    pub language: Option<String>,
+    pub subject: Option<String>,
+    /// SELECT against the custom database; its `email` column is the
+    /// recipient and every column is available to the template
+    pub recipient_query: String,
+    pub status: String,
+    pub send_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateCampaignRequest {
+    pub name: String,
+    pub template: String,
+    pub language: Option<String>,
+    /// Overrides the template's subject; may have placeholders
+    pub subject: Option<String>,
+    pub recipient_query: String,
+    pub send_at: Option<DateTime<Utc>>,
+}
+
+/// Delivery, open and click counts of a campaign
+#[derive(Debug, Serialize)]
+pub struct CampaignStats {
+    pub recipients: i64,
+    pub queued: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub cancelled: i64,
+    /// Recipients that opened the email at least once
+    pub opened: i64,
+    pub opens: i64,
+    /// Recipients that clicked at least one link
+    pub clicked: i64,
+    pub clicks: i64,
+    pub links: Vec<LinkStats>,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct LinkStats {
+    pub url: String,
+    pub clicks: i64,
+    pub recipients: i64,
+}
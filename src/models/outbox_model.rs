@@ -0,0 +1,30 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Waiting to be sent at `send_at`
+pub const OUTBOX_QUEUED: &str = "queued";
+/// Claimed by the sender named in `claimed_by`
+pub const OUTBOX_SENDING: &str = "sending";
+pub const OUTBOX_SENT: &str = "sent";
+/// Rejected by the server, or still failing after the last attempt
+pub const OUTBOX_FAILED: &str = "failed";
+pub const OUTBOX_CANCELLED: &str = "cancelled";
+
+/// A message of `system_email_outbox`; `message` is the `SendEmailRequest`
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct OutboxEntry {
+    pub id: Uuid,
+    pub message: serde_json::Value,
+    pub recipient_domain: String,
+    pub status: String,
+    pub send_at: DateTime<Utc>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub message_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub claimed_by: Option<String>,
+    pub claimed_at: Option<DateTime<Utc>>,
+}
@@ -14,7 +14,7 @@ CALL "/storage/save", ".gbdata/communication_logs", {
 }
 
 # Send actual email
-CALL "/comm/email/send", from, to, subject, body, attachments
+message_id = SEND MAIL to, subject, body, attachments
 
 # If WITH HISTORY flag present, include prior communication 
 IF WITH_HISTORY THEN
@@ -0,0 +1,3 @@
+ALTER TABLE system_automations ALTER COLUMN target TYPE VARCHAR(128);
+ALTER TABLE system_automations ALTER COLUMN schedule TYPE VARCHAR(64);
+ALTER TABLE system_automations ALTER COLUMN param TYPE VARCHAR(255);
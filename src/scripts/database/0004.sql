@@ -0,0 +1 @@
+ALTER TABLE system_automations ADD COLUMN options JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,14 @@
+ALTER TABLE system_automations ADD COLUMN timeout_secs INT NOT NULL DEFAULT 300;
+ALTER TABLE system_automations ADD COLUMN max_retries INT NOT NULL DEFAULT 3;
+ALTER TABLE system_automations ADD COLUMN last_error TEXT;
+
+CREATE TABLE system_automation_dead_letters (
+    id uuid PRIMARY KEY,
+    automation_id uuid NOT NULL REFERENCES system_automations(id) ON DELETE CASCADE,
+    params JSONB NOT NULL DEFAULT '{}',
+    error TEXT NOT NULL,
+    attempts INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_dead_letters_automation ON system_automation_dead_letters(automation_id);
@@ -0,0 +1,3 @@
+ALTER TABLE system_automations ADD COLUMN webhook_secret TEXT;
+
+CREATE UNIQUE INDEX idx_webhook_slugs ON system_automations(target) WHERE kind = 4;
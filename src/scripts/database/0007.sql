@@ -0,0 +1,6 @@
+CREATE TABLE system_email_cursors (
+    folder VARCHAR(255) PRIMARY KEY,
+    uid_validity BIGINT NOT NULL,
+    last_uid BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
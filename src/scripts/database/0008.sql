@@ -0,0 +1,8 @@
+CREATE TABLE system_email_templates (
+    name VARCHAR(255) NOT NULL,
+    language VARCHAR(35) NOT NULL DEFAULT '',
+    subject TEXT,
+    body TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (name, language)
+);
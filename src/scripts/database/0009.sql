@@ -0,0 +1,14 @@
+CREATE TABLE system_email_outbox (
+    id uuid PRIMARY KEY,
+    message JSONB NOT NULL,
+    recipient_domain VARCHAR(255) NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'queued',
+    send_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    message_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    sent_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_email_outbox_due ON system_email_outbox(send_at) WHERE status = 'queued';
@@ -0,0 +1,35 @@
+CREATE TABLE system_campaigns (
+    id uuid PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    template VARCHAR(255) NOT NULL,
+    language VARCHAR(35),
+    subject TEXT,
+    recipient_query TEXT NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'draft',
+    send_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMPTZ
+);
+
+CREATE TABLE system_campaign_recipients (
+    campaign_id uuid NOT NULL REFERENCES system_campaigns(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    outbox_id uuid REFERENCES system_email_outbox(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (campaign_id, email)
+);
+
+-- The pixel has always recorded opens; link clicks get their own table
+ALTER TABLE clicks RENAME TO campaign_opens;
+ALTER TABLE campaign_opens ADD COLUMN opens INT NOT NULL DEFAULT 1;
+ALTER TABLE campaign_opens ADD COLUMN first_opened_at TIMESTAMP DEFAULT NOW();
+
+CREATE TABLE campaign_clicks (
+    campaign_id TEXT NOT NULL,
+    email TEXT NOT NULL,
+    url TEXT NOT NULL,
+    user_agent TEXT,
+    clicked_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_campaign_clicks_campaign ON campaign_clicks(campaign_id);
@@ -0,0 +1,7 @@
+-- Outbox messages being sent are leased to the server that claimed them, so
+-- that a restarting server only requeues messages whose lease ran out
+ALTER TABLE system_email_outbox
+    ADD COLUMN claimed_by TEXT,
+    ADD COLUMN claimed_at TIMESTAMPTZ;
+
+CREATE INDEX idx_email_outbox_claimed ON system_email_outbox(claimed_at) WHERE status = 'sending';
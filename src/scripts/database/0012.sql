@@ -0,0 +1,3 @@
+-- Campaigns are sent in the background; a send that stopped keeps its error
+-- and picks up where it left off when sent again
+ALTER TABLE system_campaigns ADD COLUMN last_error TEXT;
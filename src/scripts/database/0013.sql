@@ -0,0 +1,12 @@
+-- ON and SET_SCHEDULE register their script's automation again on every run;
+-- the same trigger of the same script is now stored once
+DELETE FROM system_automations a
+USING system_automations b
+WHERE a.kind = b.kind
+  AND a.target IS NOT DISTINCT FROM b.target
+  AND a.schedule IS NOT DISTINCT FROM b.schedule
+  AND a.param = b.param
+  AND a.id > b.id;
+
+CREATE UNIQUE INDEX idx_automation_definitions
+    ON system_automations(kind, (COALESCE(target, '')), (COALESCE(schedule, '')), param);
@@ -0,0 +1,2 @@
+-- Trigger kinds are the integers of TriggerKind
+ALTER TABLE system_automations ALTER COLUMN kind TYPE INT USING kind::INT;
@@ -1,12 +1,27 @@
 pub mod automation;
+pub mod campaign;
 pub mod config;
 pub mod email;
+pub mod email_template;
 pub mod file;
+pub mod imap_pool;
 pub mod keywords;
 pub mod llm;
 pub mod llm_local;
 pub mod llm_provider;
+pub mod outbox;
 pub mod script;
+pub mod script_analysis;
+pub mod script_backend;
+pub mod script_cache;
+pub mod script_debug;
+pub mod script_fmt;
+pub mod script_lint;
+pub mod script_lsp;
+pub mod script_pool;
+pub mod script_syntax;
+pub mod script_test;
 pub mod state;
+pub mod tracking;
 pub mod utils;
 pub mod web_automation;
@@ -1,125 +1,905 @@
-use crate::models::automation_model::{Automation, TriggerKind};
+use crate::models::automation_model::{
+    Automation, CreateAutomationRequest, DeadLetter, EmailTriggerOptions, FileTriggerOptions,
+    FileUpload, RunAutomationRequest, TableChange, TableTriggerOptions, TriggerKind,
+    UpdateAutomationRequest, WebhookOptions,
+};
+use crate::services::config::EmailConfig;
+use crate::services::email::fetch_unseen_since;
 use crate::services::script::ScriptService;
+use crate::services::script_backend::{LiveBackend, TraceEntry, TracingBackend};
+use crate::services::script_cache::{ScriptCache, ScriptReload};
+use crate::services::script_debug::{serve_debug_session, ScriptDebugger};
+use crate::services::script_pool::ScriptPool;
 use crate::services::state::AppState;
+use crate::services::utils::{dynamic_to_json, json_value_to_dynamic, row_to_json};
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Result};
 use chrono::Datelike;
 use chrono::Timelike;
-use chrono::{DateTime, Utc};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rhai::Scope;
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+use sqlx::postgres::PgListener;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Semaphore};
 use tokio::time::Duration;
 use uuid::Uuid;
 
+// Upper bound for rows handed to a table trigger script in one run
+const MAX_CHANGED_ROWS: i64 = 1000;
+
+const TABLE_CHANGES_CHANNEL: &str = "gb_table_changes";
+
+// How long to wait for more notifications before dispatching a batch
+const CHANGE_BATCH_WINDOW: Duration = Duration::from_millis(500);
+
+// Trigger arguments: table name as registered, primary key column, soft delete
+// column, automation id. A soft delete (the column going from NULL to a value)
+// is reported as DELETE.
+const NOTIFY_FUNCTION_SQL: &str = r#"
+CREATE OR REPLACE FUNCTION gb_notify_table_change() RETURNS trigger AS $$
+DECLARE
+    new_row JSONB := CASE WHEN TG_OP = 'DELETE' THEN NULL ELSE to_jsonb(NEW) END;
+    old_row JSONB := CASE WHEN TG_OP = 'INSERT' THEN NULL ELSE to_jsonb(OLD) END;
+    operation TEXT := TG_OP;
+BEGIN
+    IF TG_OP = 'UPDATE' AND new_row ? TG_ARGV[2]
+        AND old_row ->> TG_ARGV[2] IS NULL AND new_row ->> TG_ARGV[2] IS NOT NULL THEN
+        operation := 'DELETE';
+    END IF;
+
+    PERFORM pg_notify('gb_table_changes', json_build_object(
+        'table', TG_ARGV[0],
+        'operation', operation,
+        'pk_column', TG_ARGV[1],
+        'pk', COALESCE(new_row, old_row) -> TG_ARGV[1],
+        'automation', TG_ARGV[3]
+    )::text);
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql
+"#;
+
+const AUTOMATION_COLUMNS: &str = "id, kind, target, schedule, param, is_active, last_triggered, \
+     options, timeout_secs, max_retries, last_error, webhook_secret";
+
+const DEFAULT_TIMEOUT_SECS: i32 = 300;
+const MAX_TIMEOUT_SECS: i32 = 3600;
+const DEFAULT_MAX_RETRIES: i32 = 3;
+const MAX_RETRIES: i32 = 10;
+
+// First retry waits this long, doubling on every further attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+
+// Debugged runs sit paused for a long time, so they only stop at the longest
+// timeout, or when their client stays idle while paused
+const DEBUG_TIMEOUT: Duration = Duration::from_secs(MAX_TIMEOUT_SECS as u64);
+// Debugged runs have threads of their own, so open debuggers never hold up
+// the automations
+const MAX_DEBUG_SESSIONS: usize = 2;
+
+const EMAIL_POLL_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_EMAIL_FOLDER: &str = "INBOX";
+
 pub struct AutomationService {
     state: AppState, // Use web::Data directly
     scripts_dir: String,
+    scripts: Arc<ScriptCache>,
+    pool: ScriptPool,   // Dedicated threads the scripts run on
+    workers: Semaphore, // Bounds how many scripts run at the same time
+    debug_pool: ScriptPool,
+    debuggers: Semaphore,
 }
 
 impl AutomationService {
-    pub fn new(state: AppState, scripts_dir: &str) -> Self {
+    pub fn new(state: AppState, scripts_dir: &str, max_concurrent: usize) -> Self {
+        // Keywords are registered once and the engine is shared by every run
+        let script_service = Arc::new(ScriptService::new(&state, scripts_dir));
+
         Self {
             state,
             scripts_dir: scripts_dir.to_string(),
+            scripts: Arc::new(ScriptCache::new(scripts_dir, script_service)),
+            pool: ScriptPool::new(max_concurrent, tokio::runtime::Handle::current()),
+            workers: Semaphore::new(max_concurrent),
+            debug_pool: ScriptPool::new(MAX_DEBUG_SESSIONS, tokio::runtime::Handle::current()),
+            debuggers: Semaphore::new(MAX_DEBUG_SESSIONS),
         }
     }
 
-    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+    pub fn spawn(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let (change_tx, change_rx) = mpsc::unbounded_channel();
+
+        let listener = self.clone();
+        tokio::spawn(async move { listener.listen_table_changes(change_tx).await });
+
+        let dispatcher = self.clone();
+        tokio::spawn(async move { dispatcher.dispatch_table_changes(change_rx).await });
+
+        let mail_poller = self.clone();
+        tokio::spawn(async move { mail_poller.poll_mailboxes().await });
+
+        match self.scripts.watch() {
+            Ok(reload_rx) => {
+                let reporter = self.clone();
+                tokio::spawn(async move { reporter.report_script_reloads(reload_rx).await });
+            }
+            Err(e) => log::error!("Failed to watch {}: {}", self.scripts_dir, e),
+        }
+
         tokio::spawn(async move {
+            self.install_table_triggers().await;
+
             let mut interval = tokio::time::interval(Duration::from_secs(5));
-            let mut last_check = Utc::now();
 
             loop {
                 interval.tick().await;
 
-                if let Err(e) = self.run_cycle(&mut last_check).await {
-                    eprintln!("Automation cycle error: {}", e);
+                if let Err(e) = self.run_cycle().await {
+                    log::error!("Automation cycle error: {}", e);
                 }
             }
         })
     }
 
-    async fn run_cycle(
-        &self,
-        last_check: &mut DateTime<Utc>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    async fn run_cycle(self: &Arc<Self>) -> Result<(), Box<dyn std::error::Error>> {
         let automations = self.load_active_automations().await?;
-        self.check_table_changes(&automations, *last_check).await;
         self.process_schedules(&automations).await;
-        *last_check = Utc::now();
         Ok(())
     }
 
+    fn pool(&self) -> Result<&PgPool, sqlx::Error> {
+        self.state.db.as_ref().ok_or(sqlx::Error::PoolClosed)
+    }
+
     async fn load_active_automations(&self) -> Result<Vec<Automation>, sqlx::Error> {
-        if let Some(pool) = &self.state.db {
-            sqlx::query_as::<_, Automation>(
-                r#"
-                SELECT id, kind, target, schedule, param, is_active, last_triggered
-                FROM system_automations
-                WHERE is_active = true
-                "#,
-            )
-            .fetch_all(pool)
-            .await
-        } else {
-            Err(sqlx::Error::PoolClosed)
-        }
-    }
-
-    async fn check_table_changes(&self, automations: &[Automation], since: DateTime<Utc>) {
-        if let Some(pool) = &self.state.db_custom {
-            for automation in automations {
-                if let Some(trigger_kind) = TriggerKind::from_i32(automation.kind) {
-                    if matches!(
-                        trigger_kind,
-                        TriggerKind::TableUpdate
-                            | TriggerKind::TableInsert
-                            | TriggerKind::TableDelete
-                    ) {
-                        if let Some(table) = &automation.target {
-                            let column = match trigger_kind {
-                                TriggerKind::TableInsert => "created_at",
-                                _ => "updated_at",
-                            };
-
-                            let query =
-                                format!("SELECT COUNT(*) FROM {} WHERE {} > $1", table, column);
-
-                            match sqlx::query_scalar::<_, i64>(&query)
-                                .bind(since)
-                                .fetch_one(pool)
-                                .await
-                            {
-                                Ok(count) => {
-                                    if count > 0 {
-                                        self.execute_action(&automation.param).await;
-                                        self.update_last_triggered(automation.id).await;
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("Error checking changes for table {}: {}", table, e);
-                                }
-                            }
-                        }
+        sqlx::query_as::<_, Automation>(&format!(
+            "SELECT {} FROM system_automations WHERE is_active = true",
+            AUTOMATION_COLUMNS
+        ))
+        .fetch_all(self.pool()?)
+        .await
+    }
+
+    pub async fn list_automations(&self) -> Result<Vec<Automation>, sqlx::Error> {
+        sqlx::query_as::<_, Automation>(&format!(
+            "SELECT {} FROM system_automations ORDER BY kind, param",
+            AUTOMATION_COLUMNS
+        ))
+        .fetch_all(self.pool()?)
+        .await
+    }
+
+    pub async fn get_automation(&self, id: Uuid) -> Result<Automation, sqlx::Error> {
+        sqlx::query_as::<_, Automation>(&format!(
+            "SELECT {} FROM system_automations WHERE id = $1",
+            AUTOMATION_COLUMNS
+        ))
+        .bind(id)
+        .fetch_one(self.pool()?)
+        .await
+    }
+
+    /// Starts a transaction on the database automations are stored in
+    pub async fn begin(&self) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+        self.pool()?.begin().await
+    }
+
+    pub async fn create_automation(
+        &self,
+        db: impl PgExecutor<'_>,
+        request: &CreateAutomationRequest,
+    ) -> Result<Automation, sqlx::Error> {
+        sqlx::query_as::<_, Automation>(&format!(
+            r#"
+            INSERT INTO system_automations
+            (id, kind, target, schedule, param, is_active, options, timeout_secs, max_retries,
+             webhook_secret)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING {}
+            "#,
+            AUTOMATION_COLUMNS
+        ))
+        .bind(Uuid::new_v4())
+        .bind(request.kind)
+        .bind(&request.target)
+        .bind(&request.schedule)
+        .bind(&request.param)
+        .bind(request.is_active.unwrap_or(true))
+        .bind(request.options.clone().unwrap_or_else(|| json!({})))
+        .bind(request.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS))
+        .bind(request.max_retries.unwrap_or(DEFAULT_MAX_RETRIES))
+        .bind(&request.secret)
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn update_automation(
+        &self,
+        db: impl PgExecutor<'_>,
+        automation: &Automation,
+    ) -> Result<Automation, sqlx::Error> {
+        sqlx::query_as::<_, Automation>(&format!(
+            r#"
+            UPDATE system_automations
+            SET kind = $1, target = $2, schedule = $3, param = $4, options = $5,
+                timeout_secs = $6, max_retries = $7, webhook_secret = $8
+            WHERE id = $9
+            RETURNING {}
+            "#,
+            AUTOMATION_COLUMNS
+        ))
+        .bind(automation.kind)
+        .bind(&automation.target)
+        .bind(&automation.schedule)
+        .bind(&automation.param)
+        .bind(&automation.options)
+        .bind(automation.timeout_secs)
+        .bind(automation.max_retries)
+        .bind(&automation.webhook_secret)
+        .bind(automation.id)
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn set_active(&self, id: Uuid, is_active: bool) -> Result<Automation, sqlx::Error> {
+        sqlx::query_as::<_, Automation>(&format!(
+            "UPDATE system_automations SET is_active = $1 WHERE id = $2 RETURNING {}",
+            AUTOMATION_COLUMNS
+        ))
+        .bind(is_active)
+        .bind(id)
+        .fetch_one(self.pool()?)
+        .await
+    }
+
+    /// Looks up an active webhook automation by its id or slug (`target`)
+    pub async fn find_webhook(&self, hook: &str) -> Result<Automation, sqlx::Error> {
+        sqlx::query_as::<_, Automation>(&format!(
+            r#"
+            SELECT {} FROM system_automations
+            WHERE kind = $1 AND is_active = true AND (id::text = $2 OR target = $2)
+            "#,
+            AUTOMATION_COLUMNS
+        ))
+        .bind(TriggerKind::Webhook as i32)
+        .bind(hook)
+        .fetch_one(self.pool()?)
+        .await
+    }
+
+    pub async fn delete_automation(&self, id: Uuid) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM system_automations WHERE id = $1")
+            .bind(id)
+            .execute(self.pool()?)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Checks the timeout and retry policy of an automation
+    pub fn validate_run_policy(timeout_secs: i32, max_retries: i32) -> Result<(), String> {
+        if !(1..=MAX_TIMEOUT_SECS).contains(&timeout_secs) {
+            return Err(format!(
+                "timeout_secs must be between 1 and {}",
+                MAX_TIMEOUT_SECS
+            ));
+        }
+        if !(0..=MAX_RETRIES).contains(&max_retries) {
+            return Err(format!("max_retries must be between 0 and {}", MAX_RETRIES));
+        }
+        Ok(())
+    }
+
+    /// Checks an automation definition before it is saved
+    pub async fn validate_automation(
+        &self,
+        kind: i32,
+        target: Option<&str>,
+        schedule: Option<&str>,
+        param: &str,
+        options: &Value,
+    ) -> Result<(), String> {
+        validate_automation(
+            &self.state,
+            &self.scripts_dir,
+            kind,
+            target,
+            schedule,
+            param,
+            options,
+        )
+        .await
+    }
+
+    /// Makes sure every active table automation has its notify trigger installed
+    async fn install_table_triggers(&self) {
+        match self.load_active_automations().await {
+            Ok(automations) => {
+                for automation in &automations {
+                    if let Err(e) = self.ensure_table_trigger(automation).await {
+                        log::error!(
+                            "Failed to install trigger for automation {}: {}",
+                            automation.id,
+                            e
+                        );
+                    }
+                }
+            }
+            Err(e) => log::error!("Failed to load automations: {}", e),
+        }
+    }
+
+    /// Installs the notify trigger of the automation on its table, if it
+    /// watches one
+    pub async fn ensure_table_trigger(&self, automation: &Automation) -> Result<(), String> {
+        let kind = match TriggerKind::from_i32(automation.kind) {
+            Some(kind) if kind.is_table_trigger() => kind,
+            _ => return Ok(()),
+        };
+
+        let table = automation
+            .target
+            .as_deref()
+            .ok_or("Table automations require a target table")?;
+        let pool = self
+            .state
+            .db_custom
+            .as_ref()
+            .ok_or("Custom database not available")?;
+
+        install_table_trigger(
+            pool,
+            automation.id,
+            kind,
+            table,
+            &automation.table_options().soft_delete_column,
+        )
+        .await
+    }
+
+    /// Drops the notify trigger of the automation, if it watched a table
+    pub async fn remove_table_trigger(&self, automation: &Automation) -> Result<(), String> {
+        let is_table_trigger =
+            TriggerKind::from_i32(automation.kind).map_or(false, |kind| kind.is_table_trigger());
+        let (table, pool) = match (&automation.target, &self.state.db_custom) {
+            (Some(table), Some(pool)) if is_table_trigger => (table, pool),
+            _ => return Ok(()),
+        };
+
+        drop_table_trigger(pool, automation.id, table).await
+    }
+
+    async fn listen_table_changes(&self, change_tx: mpsc::UnboundedSender<TableChange>) {
+        let pool = match &self.state.db_custom {
+            Some(pool) => pool,
+            None => return,
+        };
+
+        loop {
+            if let Err(e) = Self::forward_notifications(pool, &change_tx).await {
+                log::error!("Table change listener error: {}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    async fn forward_notifications(
+        pool: &PgPool,
+        change_tx: &mpsc::UnboundedSender<TableChange>,
+    ) -> Result<(), sqlx::Error> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(TABLE_CHANGES_CHANNEL).await?;
+
+        loop {
+            let notification = listener.recv().await?;
+            match serde_json::from_str::<TableChange>(notification.payload()) {
+                Ok(change) => {
+                    if change_tx.send(change).is_err() {
+                        return Ok(());
                     }
                 }
+                Err(e) => log::error!(
+                    "Invalid table change notification {}: {}",
+                    notification.payload(),
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Groups notifications arriving within a short window so that bursts of
+    /// changes reach the script as a single `CHANGES` batch
+    async fn dispatch_table_changes(
+        self: &Arc<Self>,
+        mut change_rx: mpsc::UnboundedReceiver<TableChange>,
+    ) {
+        while let Some(first) = change_rx.recv().await {
+            tokio::time::sleep(CHANGE_BATCH_WINDOW).await;
+
+            let mut batch = vec![first];
+            while let Ok(change) = change_rx.try_recv() {
+                batch.push(change);
+            }
+
+            if let Err(e) = self.run_table_changes(&batch).await {
+                log::error!("Failed to dispatch table changes: {}", e);
+            }
+        }
+    }
+
+    async fn run_table_changes(self: &Arc<Self>, batch: &[TableChange]) -> Result<(), sqlx::Error> {
+        let automations = self.load_active_automations().await?;
+
+        for automation in &automations {
+            let trigger_kind = match TriggerKind::from_i32(automation.kind) {
+                Some(kind) if kind.is_table_trigger() => kind,
+                _ => continue,
+            };
+            let table = match &automation.target {
+                Some(table) => table,
+                None => continue,
+            };
+
+            // Every automation has a trigger of its own, with its own settings
+            let options = automation.table_options();
+            let matching: Vec<&TableChange> = batch
+                .iter()
+                .filter(|change| {
+                    change.automation == Some(automation.id)
+                        && &change.table == table
+                        && TriggerKind::from_operation(&change.operation) == Some(trigger_kind)
+                })
+                .take(options.max_rows.max(1) as usize)
+                .collect();
+
+            if matching.is_empty() {
+                continue;
+            }
+
+            let mut changes = Vec::new();
+            for change in matching {
+                changes.push(self.fetch_changed_row(change).await);
+            }
+
+            self.update_last_triggered(automation.id).await;
+            self.execute_table_action(automation, &changes, options.per_row);
+        }
+
+        Ok(())
+    }
+
+    /// Loads the current row for a change; hard-deleted rows only carry their key
+    async fn fetch_changed_row(&self, change: &TableChange) -> Value {
+        let mut key = Map::new();
+        key.insert(change.pk_column.clone(), change.pk.clone());
+        let key = Value::Object(key);
+
+        let pool = match &self.state.db_custom {
+            Some(pool) => pool,
+            None => return key,
+        };
+        if !is_identifier(&change.pk_column) {
+            return key;
+        }
+
+        let query = format!(
+            "SELECT * FROM {table} WHERE {pk} = (jsonb_populate_record(NULL::{table}, $1)).{pk}",
+            table = change.table,
+            pk = change.pk_column
+        );
+
+        match sqlx::query(&query).bind(&key).fetch_optional(pool).await {
+            Ok(Some(row)) => row_to_json(row).unwrap_or(key),
+            Ok(None) => key,
+            Err(e) => {
+                log::error!("Failed to fetch changed row from {}: {}", change.table, e);
+                key
+            }
+        }
+    }
+
+    async fn poll_mailboxes(self: &Arc<Self>) {
+        let mut interval = tokio::time::interval(EMAIL_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.check_mailboxes().await {
+                log::error!("Mailbox polling error: {}", e);
+            }
+        }
+    }
+
+    /// Runs email-received automations for the unseen messages that arrived
+    /// since the stored cursor of each watched folder
+    async fn check_mailboxes(self: &Arc<Self>) -> Result<(), String> {
+        let email_config = match &self.state.config {
+            Some(config) => config.email.clone(),
+            None => return Ok(()),
+        };
+
+        let automations: Vec<Automation> = self
+            .load_active_automations()
+            .await
+            .map_err(|e| format!("Failed to load automations: {}", e))?
+            .into_iter()
+            .filter(|a| TriggerKind::from_i32(a.kind) == Some(TriggerKind::EmailReceived))
+            .collect();
+
+        let folder_of = |automation: &Automation| {
+            automation
+                .target
+                .clone()
+                .unwrap_or_else(|| DEFAULT_EMAIL_FOLDER.to_string())
+        };
+        let mut folders: Vec<String> = automations.iter().map(folder_of).collect();
+        folders.sort();
+        folders.dedup();
+
+        // One folder failing must not keep the others from being checked
+        for folder in folders {
+            let watching: Vec<&Automation> = automations
+                .iter()
+                .filter(|a| folder_of(a) == folder)
+                .collect();
+            if let Err(e) = self.check_folder(&email_config, &folder, &watching).await {
+                log::error!("Mailbox polling error in {}: {}", folder, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn check_folder(
+        self: &Arc<Self>,
+        email_config: &EmailConfig,
+        folder: &str,
+        automations: &[&Automation],
+    ) -> Result<(), String> {
+        let cursor = self
+            .load_email_cursor(folder)
+            .await
+            .map_err(|e| format!("Failed to load cursor: {}", e))?;
+
+        let unseen = fetch_unseen_since(&self.state.imap, email_config, folder, cursor).await?;
+        if cursor == Some((unseen.uid_validity, unseen.last_uid)) {
+            return Ok(());
+        }
+
+        // Advance the cursor before running anything so that a restart
+        // never hands the same message to a script twice
+        self.save_email_cursor(folder, unseen.uid_validity, unseen.last_uid)
+            .await
+            .map_err(|e| format!("Failed to save cursor: {}", e))?;
+
+        for automation in automations {
+            let options = automation.email_options();
+            let mut triggered = false;
+
+            for email in &unseen.emails {
+                if !options.matches(&email.sender, &email.subject) {
+                    continue;
+                }
+
+                let mut params = Map::new();
+                params.insert("sender".to_string(), json!(email.sender));
+                params.insert("subject".to_string(), json!(email.subject));
+                params.insert("body".to_string(), json!(email.body));
+
+                self.spawn_execution((*automation).clone(), params);
+                triggered = true;
+            }
+
+            if triggered {
+                self.update_last_triggered(automation.id).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn load_email_cursor(&self, folder: &str) -> Result<Option<(u32, u32)>, sqlx::Error> {
+        let cursor = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT uid_validity, last_uid FROM system_email_cursors WHERE folder = $1",
+        )
+        .bind(folder)
+        .fetch_optional(self.pool()?)
+        .await?;
+
+        Ok(cursor.map(|(uid_validity, last_uid)| (uid_validity as u32, last_uid as u32)))
+    }
+
+    async fn save_email_cursor(
+        &self,
+        folder: &str,
+        uid_validity: u32,
+        last_uid: u32,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO system_email_cursors (folder, uid_validity, last_uid, updated_at)
+            VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (folder) DO UPDATE
+            SET uid_validity = EXCLUDED.uid_validity, last_uid = EXCLUDED.last_uid,
+                updated_at = NOW()
+            "#,
+        )
+        .bind(folder)
+        .bind(uid_validity as i64)
+        .bind(last_uid as i64)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Reports compile errors of changed scripts as soon as they are saved, in
+    /// the log and as `last_error` of the automations that run them
+    async fn report_script_reloads(&self, mut reload_rx: mpsc::UnboundedReceiver<ScriptReload>) {
+        while let Some(reload) = reload_rx.recv().await {
+            let query = match &reload.result {
+                Ok(()) => {
+                    log::info!("Recompiled script {}", reload.param);
+                    sqlx::query(
+                        "UPDATE system_automations SET last_error = NULL \
+                         WHERE param = $1 AND last_error LIKE 'Error compiling script%'",
+                    )
+                    .bind(&reload.param)
+                }
+                Err(e) => {
+                    log::error!("Script {} no longer compiles: {}", reload.param, e);
+                    sqlx::query("UPDATE system_automations SET last_error = $1 WHERE param = $2")
+                        .bind(e)
+                        .bind(&reload.param)
+                }
+            };
+
+            if let Ok(pool) = self.pool() {
+                if let Err(e) = query.execute(pool).await {
+                    log::error!("Failed to record compile result of {}: {}", reload.param, e);
+                }
+            }
+        }
+    }
+
+    /// Runs the file-upload automations watching the folder `upload` was stored
+    /// in. `from_notification` tells bucket notifications apart from uploads made
+    /// through `/files/upload`, so that each automation fires from one source only.
+    pub async fn run_file_uploaded(
+        self: &Arc<Self>,
+        upload: &FileUpload,
+        from_notification: bool,
+    ) -> Result<(), sqlx::Error> {
+        let automations = self.load_active_automations().await?;
+
+        for automation in &automations {
+            if TriggerKind::from_i32(automation.kind) != Some(TriggerKind::FileUploaded)
+                || automation.file_options().from_notifications != from_notification
+            {
+                continue;
             }
+
+            let in_folder = match automation.target.as_deref().map(|t| t.trim_matches('/')) {
+                None | Some("") => true,
+                Some(folder) => upload.key.starts_with(&format!("{}/", folder)),
+            };
+            if !in_folder {
+                continue;
+            }
+
+            let mut params = Map::new();
+            params.insert("key".to_string(), json!(upload.key));
+            params.insert("size".to_string(), json!(upload.size));
+            params.insert("content_type".to_string(), json!(upload.content_type));
+            params.insert("uploader".to_string(), json!(upload.uploader));
+
+            self.update_last_triggered(automation.id).await;
+            self.spawn_execution(automation.clone(), params);
+        }
+
+        Ok(())
+    }
+
+    /// Runs a table trigger's script with the changed rows exposed as `CHANGES`
+    /// and the current (or first) row as `ROW`
+    fn execute_table_action(
+        self: &Arc<Self>,
+        automation: &Automation,
+        changes: &[Value],
+        per_row: bool,
+    ) {
+        let rows: Vec<&Value> = if per_row {
+            changes.iter().collect()
+        } else {
+            changes.first().into_iter().collect()
+        };
+
+        for row in rows {
+            let mut params = Map::new();
+            params.insert("CHANGES".to_string(), Value::Array(changes.to_vec()));
+            params.insert("ROW".to_string(), row.clone());
+
+            self.spawn_execution(automation.clone(), params);
         }
     }
 
-    async fn process_schedules(&self, automations: &[Automation]) {
-        let now = Utc::now().timestamp();
+    async fn process_schedules(self: &Arc<Self>, automations: &[Automation]) {
+        let now = Utc::now();
 
         for automation in automations {
             if let Some(TriggerKind::Scheduled) = TriggerKind::from_i32(automation.kind) {
                 if let Some(pattern) = &automation.schedule {
-                    if Self::should_run_cron(pattern, now) {
-                        self.execute_action(&automation.param).await;
+                    // The cycle runs several times a minute; fire once per matching minute
+                    let already_ran = automation
+                        .last_triggered
+                        .map_or(false, |last| last.timestamp() / 60 == now.timestamp() / 60);
+
+                    if !already_ran && Self::should_run_cron(pattern, now.timestamp()) {
                         self.update_last_triggered(automation.id).await;
+                        self.spawn_execution(automation.clone(), Map::new());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the automation in the background so that a slow script doesn't hold
+    /// up the other automations
+    fn spawn_execution(self: &Arc<Self>, automation: Automation, params: Map<String, Value>) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            service.execute_with_retries(&automation, params).await;
+        });
+    }
+
+    /// Runs the automation, retrying failures with exponential backoff and
+    /// dead-lettering the run once `max_retries` is exhausted
+    async fn execute_with_retries(&self, automation: &Automation, params: Map<String, Value>) {
+        let timeout = Duration::from_secs(automation.timeout_secs.max(1) as u64);
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            match self.run_script(&automation.param, &params, timeout).await {
+                Ok(_) => {
+                    if attempt > 1 || automation.last_error.is_some() {
+                        self.record_error(automation.id, None).await;
+                    }
+                    return;
+                }
+                Err(e) if attempt <= automation.max_retries => {
+                    let delay = RETRY_BASE_DELAY
+                        .saturating_mul(2u32.saturating_pow(attempt as u32 - 1))
+                        .min(RETRY_MAX_DELAY);
+                    log::error!(
+                        "Automation {} failed (attempt {}), retrying in {:?}: {}",
+                        automation.id,
+                        attempt,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => {
+                    log::error!(
+                        "Automation {} failed after {} attempts: {}",
+                        automation.id,
+                        attempt,
+                        e
+                    );
+                    self.record_error(automation.id, Some(&e)).await;
+                    if let Err(db_error) =
+                        self.dead_letter(automation.id, &params, &e, attempt).await
+                    {
+                        log::error!(
+                            "Failed to dead-letter run of automation {}: {}",
+                            automation.id,
+                            db_error
+                        );
                     }
+                    return;
                 }
             }
         }
     }
 
-    async fn update_last_triggered(&self, automation_id: Uuid) {
+    async fn record_error(&self, automation_id: Uuid, error: Option<&str>) {
+        if let Ok(pool) = self.pool() {
+            if let Err(e) =
+                sqlx::query("UPDATE system_automations SET last_error = $1 WHERE id = $2")
+                    .bind(error)
+                    .bind(automation_id)
+                    .execute(pool)
+                    .await
+            {
+                log::error!(
+                    "Failed to update last_error for automation {}: {}",
+                    automation_id,
+                    e
+                );
+            }
+        }
+    }
+
+    async fn dead_letter(
+        &self,
+        automation_id: Uuid,
+        params: &Map<String, Value>,
+        error: &str,
+        attempts: i32,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO system_automation_dead_letters
+            (id, automation_id, params, error, attempts, created_at)
+            VALUES ($1, $2, $3, $4, $5, NOW())
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(automation_id)
+        .bind(Value::Object(params.clone()))
+        .bind(error)
+        .bind(attempts)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_dead_letters(
+        &self,
+        automation_id: Uuid,
+    ) -> Result<Vec<DeadLetter>, sqlx::Error> {
+        sqlx::query_as::<_, DeadLetter>(
+            r#"
+            SELECT id, automation_id, params, error, attempts, created_at
+            FROM system_automation_dead_letters
+            WHERE automation_id = $1
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(automation_id)
+        .fetch_all(self.pool()?)
+        .await
+    }
+
+    pub async fn get_dead_letter(
+        &self,
+        automation_id: Uuid,
+        dead_letter_id: Uuid,
+    ) -> Result<DeadLetter, sqlx::Error> {
+        sqlx::query_as::<_, DeadLetter>(
+            r#"
+            SELECT id, automation_id, params, error, attempts, created_at
+            FROM system_automation_dead_letters
+            WHERE id = $1 AND automation_id = $2
+            "#,
+        )
+        .bind(dead_letter_id)
+        .bind(automation_id)
+        .fetch_one(self.pool()?)
+        .await
+    }
+
+    pub async fn delete_dead_letter(
+        &self,
+        automation_id: Uuid,
+        dead_letter_id: Uuid,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            "DELETE FROM system_automation_dead_letters WHERE id = $1 AND automation_id = $2",
+        )
+        .bind(dead_letter_id)
+        .bind(automation_id)
+        .execute(self.pool()?)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn update_last_triggered(&self, automation_id: Uuid) {
         if let Some(pool) = &self.state.db {
             if let Err(e) = sqlx::query!(
                 "UPDATE system_automations SET last_triggered = $1 WHERE id = $2",
@@ -129,14 +909,49 @@ impl AutomationService {
             .execute(pool)
             .await
             {
-                eprintln!(
+                log::error!(
                     "Failed to update last_triggered for automation {}: {}",
-                    automation_id, e
+                    automation_id,
+                    e
                 );
             }
         }
     }
 
+    /// Validates a 5-field cron pattern using the subset `should_run_cron` understands.
+    /// Unlike standard cron, weekdays count from Monday: 0 is Monday, 6 Sunday.
+    pub fn validate_cron(pattern: &str) -> Result<(), String> {
+        let parts: Vec<&str> = pattern.split_whitespace().collect();
+        if parts.len() != 5 {
+            return Err(format!(
+                "Invalid cron pattern '{}': expected 5 fields \
+                 (minute hour day month weekday, 0 = Monday)",
+                pattern
+            ));
+        }
+
+        let ranges = [(0, 59), (0, 23), (1, 31), (1, 12), (0, 6)];
+        for (part, (min, max)) in parts.iter().zip(ranges.iter()) {
+            let valid = if *part == "*" {
+                true
+            } else if let Some(step) = part.strip_prefix("*/") {
+                step.parse::<i32>().map_or(false, |n| n > 0 && n <= *max)
+            } else {
+                part.parse::<i32>()
+                    .map_or(false, |n| n >= *min && n <= *max)
+            };
+
+            if !valid {
+                return Err(format!(
+                    "Invalid cron field '{}' in '{}' (allowed: *, */N or {}-{})",
+                    part, pattern, min, max
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     fn should_run_cron(pattern: &str, timestamp: i64) -> bool {
         let parts: Vec<&str> = pattern.split_whitespace().collect();
         if parts.len() != 5 {
@@ -173,25 +988,897 @@ impl AutomationService {
         part.parse::<i32>().map_or(false, |num| num == value)
     }
 
-    async fn execute_action(&self, param: &str) {
-        let full_path = Path::new(&self.scripts_dir).join(param);
-        match tokio::fs::read_to_string(&full_path).await {
-            Ok(script_content) => {
-                println!("Executing action with param: {}", param);
+    /// Runs a script from `scripts_dir` (compiled once and cached until the file
+    /// changes), exposing `params` as variables. The script runs on the script
+    /// pool once a worker slot is free and is aborted after `timeout`; the slot
+    /// is held until the script thread is free again.
+    pub async fn run_script(
+        &self,
+        param: &str,
+        params: &Map<String, Value>,
+        timeout: Duration,
+    ) -> Result<Value, String> {
+        let _permit = self
+            .workers
+            .acquire()
+            .await
+            .map_err(|e| format!("Automation workers unavailable: {}", e))?;
+
+        log::debug!("Executing action with param: {}", param);
+
+        let scripts = self.scripts.clone();
+        let param_path = param.to_string();
+        let params = params.clone();
+        let run = self.pool.run(move || {
+            let ast = scripts.get(&param_path)?;
 
-                let script_service = ScriptService::new(&self.state.clone());
+            let mut scope = Scope::new();
+            for (name, value) in &params {
+                scope.push(name.clone(), json_value_to_dynamic(value));
+            }
 
-                match script_service.compile(&script_content) {
-                    Ok(ast) => match script_service.run(&ast) {
-                        Ok(result) => println!("Script executed successfully: {:?}", result),
-                        Err(e) => eprintln!("Error executing script: {}", e),
-                    },
-                    Err(e) => eprintln!("Error compiling script: {}", e),
+            let result = scripts
+                .script_service()
+                .run_with_timeout(&ast, &mut scope, timeout)
+                .map_err(|e| format!("Error executing script: {}", e))?;
+            log::debug!("Script executed successfully: {:?}", result);
+
+            Ok(dynamic_to_json(&result))
+        });
+
+        // The engine and the keyword bridge stop the run at its deadline, so
+        // the job is awaited to the end: a retry must never overlap it
+        match run.await {
+            Ok(result) => result,
+            Err(e) => Err(format!("Script {} aborted: {}", param, e)),
+        }
+    }
+
+    /// Like `run_script`, also returning every keyword call the script made
+    /// with its arguments and timing
+    pub async fn run_script_traced(
+        &self,
+        param: &str,
+        params: &Map<String, Value>,
+        timeout: Duration,
+    ) -> (Result<Value, String>, Vec<TraceEntry>) {
+        self.run_script_isolated(param, params, timeout, None).await
+    }
+
+    /// Runs a script under `debugger`, which pauses it at breakpoints and steps.
+    /// Fails at once when `MAX_DEBUG_SESSIONS` scripts are being debugged.
+    pub async fn debug_script(
+        &self,
+        param: &str,
+        params: &Map<String, Value>,
+        debugger: ScriptDebugger,
+    ) -> (Result<Value, String>, Vec<TraceEntry>) {
+        self.run_script_isolated(param, params, DEBUG_TIMEOUT, Some(debugger))
+            .await
+    }
+
+    // Traced and debugged runs get an engine of their own, so that their
+    // backend and debugger don't touch the runs sharing the cached engine
+    async fn run_script_isolated(
+        &self,
+        param: &str,
+        params: &Map<String, Value>,
+        timeout: Duration,
+        debugger: Option<ScriptDebugger>,
+    ) -> (Result<Value, String>, Vec<TraceEntry>) {
+        let (pool, _permit) = if debugger.is_some() {
+            match self.debuggers.try_acquire() {
+                Ok(permit) => (&self.debug_pool, permit),
+                Err(_) => {
+                    return (
+                        Err(format!(
+                            "Only {} scripts can be debugged at the same time",
+                            MAX_DEBUG_SESSIONS
+                        )),
+                        vec![],
+                    )
                 }
             }
-            Err(e) => {
-                eprintln!("Failed to execute action {}: {}", full_path.display(), e);
+        } else {
+            match self.workers.acquire().await {
+                Ok(permit) => (&self.pool, permit),
+                Err(e) => {
+                    return (
+                        Err(format!("Automation workers unavailable: {}", e)),
+                        vec![],
+                    )
+                }
+            }
+        };
+
+        log::debug!("Executing traced action with param: {}", param);
+
+        let state = self.state.clone();
+        let scripts_dir = self.scripts_dir.clone();
+        let param_path = param.to_string();
+        let params = params.clone();
+        let run = pool.run(move || {
+            let trace = Arc::new(TracingBackend::new(Arc::new(LiveBackend::new(
+                state.clone(),
+                &scripts_dir,
+            ))));
+            let result = run_traced(
+                &state,
+                &scripts_dir,
+                &param_path,
+                &params,
+                timeout,
+                &trace,
+                debugger,
+            );
+            (result, trace.entries())
+        });
+
+        match run.await {
+            Ok(outcome) => outcome,
+            Err(e) => (Err(format!("Script {} aborted: {}", param, e)), vec![]),
+        }
+    }
+}
+
+/// Checks an automation definition before it is saved, be it sent to the
+/// REST API or registered by a script through ON or SET_SCHEDULE
+pub async fn validate_automation(
+    state: &AppState,
+    scripts_dir: &str,
+    kind: i32,
+    target: Option<&str>,
+    schedule: Option<&str>,
+    param: &str,
+    options: &Value,
+) -> Result<(), String> {
+    let trigger_kind =
+        TriggerKind::from_i32(kind).ok_or_else(|| format!("Unknown trigger kind: {}", kind))?;
+
+    if trigger_kind == TriggerKind::Scheduled {
+        let schedule = schedule.ok_or("Scheduled automations require a schedule")?;
+        AutomationService::validate_cron(schedule)?;
+    }
+
+    if trigger_kind.is_table_trigger() {
+        let table = target.ok_or("Table automations require a target table")?;
+        validate_table(state, table).await?;
+
+        let table_options: TableTriggerOptions = serde_json::from_value(options.clone())
+            .map_err(|e| format!("Invalid table trigger options: {}", e))?;
+        if !is_identifier(&table_options.soft_delete_column) {
+            return Err(format!(
+                "Invalid soft delete column: {}",
+                table_options.soft_delete_column
+            ));
+        }
+        if !(1..=MAX_CHANGED_ROWS).contains(&table_options.max_rows) {
+            return Err(format!(
+                "max_rows must be between 1 and {}",
+                MAX_CHANGED_ROWS
+            ));
+        }
+    }
+
+    if trigger_kind == TriggerKind::Webhook {
+        if let Some(slug) = target {
+            let valid = slug.parse::<Uuid>().is_err()
+                && !slug.is_empty()
+                && slug
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid {
+                return Err(format!("Invalid webhook slug: {}", slug));
             }
         }
+        let _: WebhookOptions = serde_json::from_value(options.clone())
+            .map_err(|e| format!("Invalid webhook options: {}", e))?;
+    }
+
+    if trigger_kind == TriggerKind::EmailReceived {
+        if target.map_or(false, |folder| folder.trim().is_empty()) {
+            return Err("Mail folder must not be empty".to_string());
+        }
+        let _: EmailTriggerOptions = serde_json::from_value(options.clone())
+            .map_err(|e| format!("Invalid email trigger options: {}", e))?;
+    }
+
+    if trigger_kind == TriggerKind::FileUploaded {
+        if target.map_or(false, |folder| folder.contains("..")) {
+            return Err("Invalid drive folder".to_string());
+        }
+        let _: FileTriggerOptions = serde_json::from_value(options.clone())
+            .map_err(|e| format!("Invalid file trigger options: {}", e))?;
+    }
+
+    if param.is_empty() || param.contains("..") || Path::new(param).is_absolute() {
+        return Err(format!("Invalid script path: {}", param));
+    }
+    let full_path = Path::new(scripts_dir).join(param);
+    if tokio::fs::metadata(&full_path).await.is_err() {
+        return Err(format!("Script not found: {}", full_path.display()));
+    }
+
+    Ok(())
+}
+
+async fn validate_table(state: &AppState, table: &str) -> Result<(), String> {
+    let parts: Vec<&str> = table.split('.').collect();
+    if parts.len() > 2 || !parts.iter().all(|part| is_identifier(part)) {
+        return Err(format!("Invalid table name: {}", table));
+    }
+
+    let pool = state
+        .db_custom
+        .as_ref()
+        .ok_or("Custom database not available")?;
+    let exists = sqlx::query_scalar::<_, bool>("SELECT to_regclass($1) IS NOT NULL")
+        .bind(table)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Failed to check table {}: {}", table, e))?;
+
+    if exists {
+        Ok(())
+    } else {
+        Err(format!("Table not found: {}", table))
+    }
+}
+
+// Compiles and runs the script `param` of `scripts_dir` on a new engine whose
+// keywords go through `trace`
+fn run_traced(
+    state: &AppState,
+    scripts_dir: &str,
+    param: &str,
+    params: &Map<String, Value>,
+    timeout: Duration,
+    trace: &Arc<TracingBackend>,
+    debugger: Option<ScriptDebugger>,
+) -> Result<Value, String> {
+    let path = Path::new(scripts_dir).join(param);
+    let script = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to execute action {}: {}", path.display(), e))?;
+
+    let limits = state
+        .config
+        .as_ref()
+        .map(|config| config.script_limits.clone())
+        .unwrap_or_default();
+    let mut script_service = ScriptService::with_backend(trace.clone(), limits);
+
+    let (mut ast, line_map) = script_service
+        .compile_with_line_map(&script)
+        .map_err(|e| format!("Error compiling script: {}", e))?;
+    // Tells ON and SET_SCHEDULE which script registers itself
+    ast.set_source(param);
+    if let Some(debugger) = debugger {
+        script_service.enable_debugging(debugger.attach(line_map, trace.clone()));
+    }
+
+    let mut scope = Scope::new();
+    for (name, value) in params {
+        scope.push(name.clone(), json_value_to_dynamic(value));
+    }
+
+    let result = script_service
+        .run_with_timeout(&ast, &mut scope, timeout)
+        .map_err(|e| format!("Error executing script: {}", e))?;
+    Ok(dynamic_to_json(&result))
+}
+
+/// Checks the hex HMAC-SHA256 signature of a webhook body, accepting an
+/// optional `sha256=` prefix as sent by Git forges
+fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = signature.trim();
+    let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+    let expected = match hex::decode(signature) {
+        Ok(expected) => expected,
+        Err(_) => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+// Each automation gets its own trigger, so that automations on the same table
+// keep their own settings
+fn table_trigger_name(automation_id: Uuid) -> String {
+    format!("gb_automation_{}", automation_id.simple())
+}
+
+/// Installs (or refreshes) the trigger that reports the row changes of `table`
+/// that automation `automation_id` of `kind` runs on, through `pg_notify`
+pub async fn install_table_trigger(
+    pool: &PgPool,
+    automation_id: Uuid,
+    kind: TriggerKind,
+    table: &str,
+    soft_delete_column: &str,
+) -> Result<(), String> {
+    let valid_table = table.split('.').count() <= 2 && table.split('.').all(is_identifier);
+    if !valid_table || !is_identifier(soft_delete_column) {
+        return Err(format!("Invalid table trigger for {}", table));
+    }
+
+    let pk_column = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT a.attname::text
+        FROM pg_index i
+        JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+        WHERE i.indrelid = $1::regclass AND i.indisprimary
+        LIMIT 1
+        "#,
+    )
+    .bind(table)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to inspect table {}: {}", table, e))?
+    .ok_or_else(|| format!("Table {} has no primary key", table))?;
+
+    // Soft deletes are updates
+    let events = match kind {
+        TriggerKind::TableInsert => "INSERT",
+        TriggerKind::TableUpdate => "UPDATE",
+        _ => "UPDATE OR DELETE",
+    };
+    let trigger = table_trigger_name(automation_id);
+    let statements = [
+        NOTIFY_FUNCTION_SQL.to_string(),
+        // Shared by all automations of the table before triggers were per automation
+        format!("DROP TRIGGER IF EXISTS gb_automation_notify ON {}", table),
+        format!("DROP TRIGGER IF EXISTS {} ON {}", trigger, table),
+        format!(
+            "CREATE TRIGGER {} AFTER {} ON {} FOR EACH ROW \
+             EXECUTE FUNCTION gb_notify_table_change('{}', '{}', '{}', '{}')",
+            trigger, events, table, table, pk_column, soft_delete_column, automation_id
+        ),
+    ];
+
+    // All or nothing, so a failed install leaves the previous trigger in place
+    let install_error = |e: sqlx::Error| format!("Failed to install trigger on {}: {}", table, e);
+    let mut tx = pool.begin().await.map_err(install_error)?;
+    for statement in &statements {
+        sqlx::query(statement)
+            .execute(&mut *tx)
+            .await
+            .map_err(install_error)?;
+    }
+    tx.commit().await.map_err(install_error)
+}
+
+/// Drops the trigger installed for automation `automation_id` on `table`
+pub async fn drop_table_trigger(
+    pool: &PgPool,
+    automation_id: Uuid,
+    table: &str,
+) -> Result<(), String> {
+    if !(table.split('.').count() <= 2 && table.split('.').all(is_identifier)) {
+        return Err(format!("Invalid table name: {}", table));
+    }
+
+    sqlx::query(&format!(
+        "DROP TRIGGER IF EXISTS {} ON {}",
+        table_trigger_name(automation_id),
+        table
+    ))
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to drop trigger on {}: {}", table, e))?;
+    Ok(())
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn automation_error(action: &str, e: sqlx::Error) -> actix_web::Error {
+    match e {
+        sqlx::Error::RowNotFound => actix_web::error::ErrorNotFound("Automation not found"),
+        sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => {
+            actix_web::error::ErrorConflict("An automation with this target already exists")
+        }
+        _ => {
+            log::error!("Failed to {} automation: {}", action, e);
+            actix_web::error::ErrorInternalServerError(format!("Failed to {} automation", action))
+        }
+    }
+}
+
+#[get("/automations")]
+pub async fn list_automations(
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let automations = automation
+        .list_automations()
+        .await
+        .map_err(|e| automation_error("list", e))?;
+
+    Ok(HttpResponse::Ok().json(automations))
+}
+
+#[get("/automations/{id}")]
+pub async fn get_automation(
+    path: web::Path<Uuid>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let found = automation
+        .get_automation(path.into_inner())
+        .await
+        .map_err(|e| automation_error("fetch", e))?;
+
+    Ok(HttpResponse::Ok().json(found))
+}
+
+/// Creates an automation. `schedule` is a cron pattern of `*`, `*/N` or single
+/// values; its weekday field counts from Monday (0) to Sunday (6).
+#[post("/automations")]
+pub async fn create_automation(
+    payload: web::Json<CreateAutomationRequest>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let options = payload.options.clone().unwrap_or_else(|| json!({}));
+    AutomationService::validate_run_policy(
+        payload.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS),
+        payload.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+    )
+    .map_err(actix_web::error::ErrorBadRequest)?;
+    automation
+        .validate_automation(
+            payload.kind,
+            payload.target.as_deref(),
+            payload.schedule.as_deref(),
+            &payload.param,
+            &options,
+        )
+        .await
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    // The trigger is named after the automation, so it is installed after the
+    // insert, which is only committed once the trigger is in place
+    let mut tx = automation
+        .begin()
+        .await
+        .map_err(|e| automation_error("create", e))?;
+    let created = automation
+        .create_automation(&mut *tx, &payload)
+        .await
+        .map_err(|e| automation_error("create", e))?;
+    automation
+        .ensure_table_trigger(&created)
+        .await
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    if let Err(e) = tx.commit().await {
+        restore_table_trigger(&automation, None, &created).await;
+        return Err(automation_error("create", e));
+    }
+
+    Ok(HttpResponse::Created().json(created))
+}
+
+#[put("/automations/{id}")]
+pub async fn update_automation(
+    path: web::Path<Uuid>,
+    payload: web::Json<UpdateAutomationRequest>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let payload = payload.into_inner();
+    let previous = automation
+        .get_automation(path.into_inner())
+        .await
+        .map_err(|e| automation_error("fetch", e))?;
+    let mut existing = previous.clone();
+
+    existing.kind = payload.kind.unwrap_or(existing.kind);
+    if let Some(target) = payload.target {
+        existing.target = target;
+    }
+    if let Some(schedule) = payload.schedule {
+        existing.schedule = schedule;
+    }
+    existing.param = payload.param.unwrap_or(existing.param);
+    existing.options = payload.options.unwrap_or(existing.options);
+    existing.timeout_secs = payload.timeout_secs.unwrap_or(existing.timeout_secs);
+    existing.max_retries = payload.max_retries.unwrap_or(existing.max_retries);
+    if let Some(secret) = payload.secret {
+        existing.webhook_secret = secret;
+    }
+
+    AutomationService::validate_run_policy(existing.timeout_secs, existing.max_retries)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    automation
+        .validate_automation(
+            existing.kind,
+            existing.target.as_deref(),
+            existing.schedule.as_deref(),
+            &existing.param,
+            &existing.options,
+        )
+        .await
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    // The row is written first and committed once the new trigger is in place
+    let mut tx = automation
+        .begin()
+        .await
+        .map_err(|e| automation_error("update", e))?;
+    let updated = automation
+        .update_automation(&mut *tx, &existing)
+        .await
+        .map_err(|e| automation_error("update", e))?;
+    automation
+        .ensure_table_trigger(&updated)
+        .await
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    if let Err(e) = tx.commit().await {
+        restore_table_trigger(&automation, Some(&previous), &updated).await;
+        return Err(automation_error("update", e));
+    }
+
+    // The old trigger goes once the automation left its table, or tables
+    // altogether; on the same table it was just replaced
+    let same_table = existing.target == previous.target
+        && TriggerKind::from_i32(existing.kind).map_or(false, |kind| kind.is_table_trigger());
+    if !same_table {
+        if let Err(e) = automation.remove_table_trigger(&previous).await {
+            log::error!(
+                "Failed to drop trigger of automation {}: {}",
+                previous.id,
+                e
+            );
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+// Puts the triggers back as they were before `current` failed to be saved:
+// drops its trigger and reinstalls that of `previous`, if there was one
+async fn restore_table_trigger(
+    automation: &AutomationService,
+    previous: Option<&Automation>,
+    current: &Automation,
+) {
+    if let Err(e) = automation.remove_table_trigger(current).await {
+        log::error!("Failed to drop trigger of automation {}: {}", current.id, e);
+    }
+    if let Some(previous) = previous {
+        if let Err(e) = automation.ensure_table_trigger(previous).await {
+            log::error!(
+                "Failed to restore trigger of automation {}: {}",
+                previous.id,
+                e
+            );
+        }
+    }
+}
+
+#[delete("/automations/{id}")]
+pub async fn delete_automation(
+    path: web::Path<Uuid>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let found = automation
+        .get_automation(path.into_inner())
+        .await
+        .map_err(|e| automation_error("fetch", e))?;
+    let rows_affected = automation
+        .delete_automation(found.id)
+        .await
+        .map_err(|e| automation_error("delete", e))?;
+
+    if rows_affected == 0 {
+        return Err(actix_web::error::ErrorNotFound("Automation not found"));
+    }
+    if let Err(e) = automation.remove_table_trigger(&found).await {
+        log::error!("Failed to drop trigger of automation {}: {}", found.id, e);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/automations/{id}/pause")]
+pub async fn pause_automation(
+    path: web::Path<Uuid>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let paused = automation
+        .set_active(path.into_inner(), false)
+        .await
+        .map_err(|e| automation_error("pause", e))?;
+
+    Ok(HttpResponse::Ok().json(paused))
+}
+
+#[post("/automations/{id}/resume")]
+pub async fn resume_automation(
+    path: web::Path<Uuid>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let resumed = automation
+        .set_active(path.into_inner(), true)
+        .await
+        .map_err(|e| automation_error("resume", e))?;
+
+    Ok(HttpResponse::Ok().json(resumed))
+}
+
+#[post("/automations/{id}/run")]
+pub async fn run_automation(
+    path: web::Path<Uuid>,
+    payload: Option<web::Json<RunAutomationRequest>>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let found = automation
+        .get_automation(path.into_inner())
+        .await
+        .map_err(|e| automation_error("fetch", e))?;
+    let request = payload.map(|p| p.into_inner()).unwrap_or_default();
+
+    // Manual runs report failures straight back to the caller instead of retrying
+    let timeout = Duration::from_secs(found.timeout_secs.max(1) as u64);
+    if request.trace {
+        let (result, trace) = automation
+            .run_script_traced(&found.param, &request.params, timeout)
+            .await;
+        automation.update_last_triggered(found.id).await;
+
+        return Ok(match result {
+            Ok(result) => HttpResponse::Ok().json(json!({
+                "id": found.id,
+                "param": found.param,
+                "result": result,
+                "trace": trace
+            })),
+            Err(e) => HttpResponse::InternalServerError().json(json!({
+                "id": found.id,
+                "param": found.param,
+                "error": e,
+                "trace": trace
+            })),
+        });
+    }
+
+    let result = automation
+        .run_script(&found.param, &request.params, timeout)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    automation.update_last_triggered(found.id).await;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "id": found.id,
+        "param": found.param,
+        "result": result
+    })))
+}
+
+/// WebSocket for stepping through the automation's script: breakpoints by .bas
+/// line, step/continue and variable inspection
+#[get("/automations/{id}/debug")]
+pub async fn debug_automation(
+    req: HttpRequest,
+    body: web::Payload,
+    path: web::Path<Uuid>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let found = automation
+        .get_automation(path.into_inner())
+        .await
+        .map_err(|e| automation_error("fetch", e))?;
+
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    actix_web::rt::spawn(serve_debug_session(
+        automation,
+        found.param,
+        session,
+        messages,
+    ));
+
+    Ok(response)
+}
+
+#[get("/automations/{id}/dead-letters")]
+pub async fn list_dead_letters(
+    path: web::Path<Uuid>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let dead_letters = automation
+        .list_dead_letters(path.into_inner())
+        .await
+        .map_err(|e| automation_error("list dead letters for", e))?;
+
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+#[post("/automations/{id}/dead-letters/{dead_letter_id}/retry")]
+pub async fn retry_dead_letter(
+    path: web::Path<(Uuid, Uuid)>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (id, dead_letter_id) = path.into_inner();
+    let found = automation
+        .get_automation(id)
+        .await
+        .map_err(|e| automation_error("fetch", e))?;
+    let dead_letter = automation
+        .get_dead_letter(id, dead_letter_id)
+        .await
+        .map_err(|e| automation_error("fetch dead letter for", e))?;
+
+    let params = match dead_letter.params {
+        Value::Object(params) => params,
+        _ => Map::new(),
+    };
+    let timeout = Duration::from_secs(found.timeout_secs.max(1) as u64);
+    let result = automation
+        .run_script(&found.param, &params, timeout)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    // Only drop the dead letter once the replay went through
+    automation
+        .delete_dead_letter(id, dead_letter_id)
+        .await
+        .map_err(|e| automation_error("delete dead letter for", e))?;
+    automation.update_last_triggered(found.id).await;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "id": found.id,
+        "param": found.param,
+        "result": result
+    })))
+}
+
+#[delete("/automations/{id}/dead-letters/{dead_letter_id}")]
+pub async fn delete_dead_letter(
+    path: web::Path<(Uuid, Uuid)>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (id, dead_letter_id) = path.into_inner();
+    let rows_affected = automation
+        .delete_dead_letter(id, dead_letter_id)
+        .await
+        .map_err(|e| automation_error("delete dead letter for", e))?;
+
+    if rows_affected == 0 {
+        return Err(actix_web::error::ErrorNotFound("Dead letter not found"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/hooks/{hook}")]
+pub async fn run_webhook(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Bytes,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let found = automation
+        .find_webhook(&path.into_inner())
+        .await
+        .map_err(|e| automation_error("fetch", e))?;
+
+    if let Some(secret) = &found.webhook_secret {
+        let header = found.webhook_options().signature_header;
+        let signature = req
+            .headers()
+            .get(header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing signature"))?;
+
+        if !verify_signature(secret, &body, signature) {
+            return Err(actix_web::error::ErrorUnauthorized("Invalid signature"));
+        }
+    }
+
+    // JSON bodies reach the script as maps, anything else as plain text
+    let body_value = serde_json::from_slice::<Value>(&body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+    let headers: Map<String, Value> = req
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .to_str()
+                .ok()
+                .map(|value| (name.as_str().to_string(), Value::String(value.to_string())))
+        })
+        .collect();
+
+    let mut params = Map::new();
+    params.insert("BODY".to_string(), body_value);
+    params.insert("HEADERS".to_string(), Value::Object(headers));
+
+    let timeout = Duration::from_secs(found.timeout_secs.max(1) as u64);
+    let result = automation
+        .run_script(&found.param, &params, timeout)
+        .await
+        .map_err(|e| {
+            log::error!("Webhook {} failed: {}", found.id, e);
+            actix_web::error::ErrorInternalServerError("Webhook script failed")
+        })?;
+    automation.update_last_triggered(found.id).await;
+
+    // The script's RETURN value becomes the response
+    Ok(match result {
+        Value::Null => HttpResponse::NoContent().finish(),
+        Value::String(text) => HttpResponse::Ok().content_type("text/plain").body(text),
+        value => HttpResponse::Ok().json(value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-01 00:00 UTC
+    const NEW_YEAR: i64 = 1_704_067_200;
+
+    #[test]
+    fn validate_cron_accepts_the_supported_subset() {
+        for pattern in ["* * * * *", "*/15 * * * *", "30 9 1 12 0", "0 */2 * * 6"] {
+            // Weekdays run from 0 (Monday) to 6 (Sunday)
+            assert!(
+                AutomationService::validate_cron(pattern).is_ok(),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn validate_cron_rejects_other_patterns() {
+        for pattern in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 7",
+            "*/0 * * * *",
+            "1-5 * * * *",
+            "1,2 * * * *",
+            "a * * * *",
+        ] {
+            assert!(
+                AutomationService::validate_cron(pattern).is_err(),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn should_run_cron_matches_fields_and_steps() {
+        let at = |hour: i64, minute: i64| NEW_YEAR + hour * 3600 + minute * 60;
+
+        assert!(AutomationService::should_run_cron("30 9 * * *", at(9, 30)));
+        assert!(!AutomationService::should_run_cron("30 9 * * *", at(9, 31)));
+        assert!(AutomationService::should_run_cron(
+            "*/15 * * * *",
+            at(0, 45)
+        ));
+        assert!(!AutomationService::should_run_cron(
+            "*/15 * * * *",
+            at(0, 46)
+        ));
+        assert!(AutomationService::should_run_cron("0 0 1 1 *", at(0, 0)));
+        assert!(!AutomationService::should_run_cron("0 0 2 1 *", at(0, 0)));
+        assert!(!AutomationService::should_run_cron("* * * *", at(0, 0)));
+
+        // 2024-01-01 was a Monday, which is weekday 0
+        assert!(AutomationService::should_run_cron("0 0 * * 0", at(0, 0)));
+        assert!(!AutomationService::should_run_cron("0 0 * * 6", at(0, 0)));
+        assert!(AutomationService::should_run_cron(
+            "0 0 * * 6",
+            NEW_YEAR - 86_400
+        ));
     }
 }
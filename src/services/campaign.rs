@@ -0,0 +1,536 @@
+use crate::models::campaign_model::{
+    Campaign, CampaignStats, CreateCampaignRequest, LinkStats, CAMPAIGN_DRAFT, CAMPAIGN_FAILED,
+    CAMPAIGN_QUEUED, CAMPAIGN_SENDING,
+};
+use crate::models::outbox_model::{
+    OUTBOX_CANCELLED, OUTBOX_FAILED, OUTBOX_QUEUED, OUTBOX_SENDING, OUTBOX_SENT,
+};
+use crate::services::email::{parse_recipients, SendEmailRequest};
+use crate::services::email_template::{load_template, plain_text, EmailTemplate, TemplateError};
+use crate::services::outbox::enqueue_email;
+use crate::services::state::AppState;
+use crate::services::tracking::{link_token, open_signature, tracking_secret, verify_link_token};
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound};
+use actix_web::http::header::{LOCATION, USER_AGENT};
+use actix_web::{web, HttpRequest, HttpResponse};
+use regex::{Captures, Regex};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const CAMPAIGN_COLUMNS: &str = "id, name, template, language, subject, recipient_query, status, \
+                                send_at, created_at, started_at, last_error";
+
+fn database(state: &AppState) -> Result<&PgPool, actix_web::Error> {
+    state
+        .db
+        .as_ref()
+        .ok_or_else(|| ErrorInternalServerError("Database not available"))
+}
+
+fn public_url(state: &AppState) -> String {
+    state
+        .config
+        .as_ref()
+        .map(|config| config.server.public_url.clone())
+        .unwrap_or_default()
+}
+
+/// The recipient query may only read: one SELECT (or WITH ... SELECT), which
+/// also runs in a read-only transaction
+fn validate_recipient_query(query: &str) -> Result<(), String> {
+    let query = query.trim().trim_end_matches(';');
+    let first_word = query
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    if first_word != "select" && first_word != "with" {
+        return Err("The recipient query must be a SELECT".to_string());
+    }
+    if query.contains(';') {
+        return Err("The recipient query must be a single statement".to_string());
+    }
+    Ok(())
+}
+
+/// The rows of the recipient query as JSON objects
+async fn fetch_recipients(db_custom: &PgPool, query: &str) -> Result<Vec<Value>, sqlx::Error> {
+    let mut tx = db_custom.begin().await?;
+    sqlx::query("SET TRANSACTION READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+    let rows = sqlx::query_scalar::<_, Value>(&format!(
+        "SELECT to_jsonb(r) FROM ({}) r",
+        query.trim().trim_end_matches(';')
+    ))
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.rollback().await?;
+    Ok(rows)
+}
+
+pub async fn get_campaign(db: &PgPool, id: Uuid) -> Result<Option<Campaign>, sqlx::Error> {
+    sqlx::query_as::<_, Campaign>(&format!(
+        "SELECT {} FROM system_campaigns WHERE id = $1",
+        CAMPAIGN_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(db)
+    .await
+}
+
+fn link_pattern() -> Regex {
+    Regex::new(r#"(?i)(<a\b[^>]*?\bhref\s*=\s*)(["'])(https?://[^"']+)["']"#).unwrap()
+}
+
+/// Tracks the messages of one campaign with signed links and pixels
+struct CampaignTracker<'a> {
+    campaign: &'a Campaign,
+    public_url: String,
+    secret: &'a str,
+}
+
+impl CampaignTracker<'_> {
+    // Points the http(s) links of `html` at tracked redirects and appends
+    // the open pixel
+    fn track(&self, html: &str, email: &str) -> String {
+        let campaign_id = self.campaign.id.to_string();
+        let tracked = link_pattern().replace_all(html, |captures: &Captures| {
+            let url = captures[3].replace("&amp;", "&");
+            format!(
+                "{}{}{}/campaigns/{}/r/{}{}",
+                &captures[1],
+                &captures[2],
+                self.public_url,
+                campaign_id,
+                link_token(self.secret, &campaign_id, email, &url),
+                &captures[2],
+            )
+        });
+
+        let pixel = format!(
+            r#"<img src="{}/campaigns/{}/click/{}?sig={}" width="1" height="1" alt="" style="display:none">"#,
+            self.public_url,
+            campaign_id,
+            urlencoding::encode(email),
+            open_signature(self.secret, &campaign_id, email)
+        );
+        match tracked.rfind("</body>") {
+            Some(end) => format!("{}{}{}", &tracked[..end], pixel, &tracked[end..]),
+            None => format!("{}{}", tracked, pixel),
+        }
+    }
+}
+
+/// What sending a campaign did with the rows of its recipient query
+#[derive(Debug, Default, Serialize)]
+pub struct CampaignSendResult {
+    pub queued: usize,
+    /// Recipients the campaign was already sent to
+    pub skipped: usize,
+    /// Rows without a usable `email`
+    pub invalid: Vec<String>,
+}
+
+/// Merges the template with every recipient and queues the messages in the
+/// outbox. Sending again only mails the recipients not queued yet, so a send
+/// that stopped halfway resumes where it left off.
+pub async fn send_campaign(
+    state: &AppState,
+    campaign: &Campaign,
+) -> Result<CampaignSendResult, String> {
+    let db = state.db.as_ref().ok_or("Database not available")?;
+    let db_custom = state
+        .db_custom
+        .as_ref()
+        .ok_or("Custom database not available")?;
+    let secret = tracking_secret(state).ok_or("EMAIL_TRACKING_SECRET not set")?;
+
+    let rows = fetch_recipients(db_custom, &campaign.recipient_query)
+        .await
+        .map_err(|e| format!("Recipient query failed: {}", e))?;
+
+    let tracker = CampaignTracker {
+        campaign,
+        public_url: public_url(state),
+        secret,
+    };
+    // Templates by language, as recipients may have their own
+    let mut templates: HashMap<Option<String>, EmailTemplate> = HashMap::new();
+    let mut result = CampaignSendResult::default();
+
+    for mut row in rows {
+        let email = row["email"].as_str().unwrap_or_default().trim().to_string();
+        if email.is_empty() {
+            result.invalid.push(row.to_string());
+            continue;
+        }
+
+        let language = row["language"]
+            .as_str()
+            .map(str::to_string)
+            .or_else(|| campaign.language.clone());
+        if !templates.contains_key(&language) {
+            let template = load_template(state, &campaign.template, language.as_deref())
+                .await
+                .and_then(|template| template.with_subject(campaign.subject.as_deref()))
+                .map_err(|e| e.to_string())?;
+            templates.insert(language.clone(), template);
+        }
+
+        if let Some(data) = row.as_object_mut() {
+            data.insert(
+                "campaign".to_string(),
+                json!({ "id": campaign.id, "name": campaign.name }),
+            );
+        }
+        let rendered = templates[&language].render(&row);
+        let html = tracker.track(&rendered.html, &email);
+        let request = SendEmailRequest {
+            to: vec![email.clone()],
+            cc: Vec::new(),
+            bcc: Vec::new(),
+            subject: rendered.subject.unwrap_or_else(|| campaign.name.clone()),
+            text: Some(plain_text(&html)),
+            html: Some(html),
+            attachments: Vec::new(),
+        };
+        if parse_recipients(&request).is_err() {
+            result.invalid.push(email);
+            continue;
+        }
+
+        if queue_recipient(db, campaign, &email, &request)
+            .await
+            .map_err(|e| format!("Failed to queue email to {}: {}", email, e))?
+        {
+            result.queued += 1;
+        } else {
+            result.skipped += 1;
+        }
+    }
+
+    Ok(result)
+}
+
+// Records the recipient and queues their message in one transaction, so a
+// recipient is only ever marked as mailed along with their outbox message.
+// Returns false when the campaign was already sent to them.
+async fn queue_recipient(
+    db: &PgPool,
+    campaign: &Campaign,
+    email: &str,
+    request: &SendEmailRequest,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let added = sqlx::query(
+        r#"
+        INSERT INTO system_campaign_recipients (campaign_id, email) VALUES ($1, $2)
+        ON CONFLICT (campaign_id, email) DO NOTHING
+        "#,
+    )
+    .bind(campaign.id)
+    .bind(email)
+    .execute(&mut *tx)
+    .await?;
+    if added.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    let entry = enqueue_email(&mut *tx, request, campaign.send_at).await?;
+    sqlx::query(
+        "UPDATE system_campaign_recipients SET outbox_id = $3 \
+         WHERE campaign_id = $1 AND email = $2",
+    )
+    .bind(campaign.id)
+    .bind(email)
+    .bind(entry.id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+async fn set_campaign_status(
+    db: &PgPool,
+    id: Uuid,
+    status: &str,
+    error: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE system_campaigns \
+         SET status = $2, last_error = $3, started_at = COALESCE(started_at, NOW()) \
+         WHERE id = $1",
+    )
+    .bind(id)
+    .bind(status)
+    .bind(error)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn campaign_stats(db: &PgPool, id: Uuid) -> Result<CampaignStats, sqlx::Error> {
+    let (recipients, queued, sent, failed, cancelled) =
+        sqlx::query_as::<_, (i64, i64, i64, i64, i64)>(
+            r#"
+            SELECT COUNT(*),
+                   COUNT(*) FILTER (WHERE o.status IN ($2, $3)),
+                   COUNT(*) FILTER (WHERE o.status = $4),
+                   COUNT(*) FILTER (WHERE o.status = $5),
+                   COUNT(*) FILTER (WHERE o.status = $6)
+            FROM system_campaign_recipients r
+            LEFT JOIN system_email_outbox o ON o.id = r.outbox_id
+            WHERE r.campaign_id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(OUTBOX_QUEUED)
+        .bind(OUTBOX_SENDING)
+        .bind(OUTBOX_SENT)
+        .bind(OUTBOX_FAILED)
+        .bind(OUTBOX_CANCELLED)
+        .fetch_one(db)
+        .await?;
+
+    // Opens and clicks are keyed by the campaign id as text, like the pixel
+    let campaign_id = id.to_string();
+    let (opened, opens) = sqlx::query_as::<_, (i64, i64)>(
+        "SELECT COUNT(*), COALESCE(SUM(opens), 0)::BIGINT FROM campaign_opens \
+         WHERE campaign_id = $1",
+    )
+    .bind(&campaign_id)
+    .fetch_one(db)
+    .await?;
+    let (clicked, clicks) = sqlx::query_as::<_, (i64, i64)>(
+        "SELECT COUNT(DISTINCT email), COUNT(*) FROM campaign_clicks WHERE campaign_id = $1",
+    )
+    .bind(&campaign_id)
+    .fetch_one(db)
+    .await?;
+    let links = sqlx::query_as::<_, LinkStats>(
+        r#"
+        SELECT url, COUNT(*) AS clicks, COUNT(DISTINCT email) AS recipients
+        FROM campaign_clicks WHERE campaign_id = $1
+        GROUP BY url ORDER BY clicks DESC
+        "#,
+    )
+    .bind(&campaign_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(CampaignStats {
+        recipients,
+        queued,
+        sent,
+        failed,
+        cancelled,
+        opened,
+        opens,
+        clicked,
+        clicks,
+        links,
+    })
+}
+
+#[actix_web::post("/campaigns")]
+pub async fn create_campaign(
+    state: web::Data<AppState>,
+    request: web::Json<CreateCampaignRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let db = database(&state)?;
+    // Campaign emails can't be tracked, and so can't be sent, without it
+    tracking_secret(&state)
+        .ok_or_else(|| ErrorInternalServerError("EMAIL_TRACKING_SECRET not set"))?;
+    validate_recipient_query(&request.recipient_query).map_err(ErrorBadRequest)?;
+    match load_template(&state, &request.template, request.language.as_deref()).await {
+        Ok(_) => {}
+        Err(e @ (TemplateError::NotFound(_) | TemplateError::Syntax(_))) => {
+            return Err(ErrorBadRequest(e.to_string()))
+        }
+        Err(e) => return Err(ErrorInternalServerError(e.to_string())),
+    }
+
+    let campaign = sqlx::query_as::<_, Campaign>(&format!(
+        r#"
+        INSERT INTO system_campaigns
+        (id, name, template, language, subject, recipient_query, status, send_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING {}
+        "#,
+        CAMPAIGN_COLUMNS
+    ))
+    .bind(Uuid::new_v4())
+    .bind(&request.name)
+    .bind(&request.template)
+    .bind(&request.language)
+    .bind(&request.subject)
+    .bind(&request.recipient_query)
+    .bind(CAMPAIGN_DRAFT)
+    .bind(request.send_at)
+    .fetch_one(db)
+    .await
+    .map_err(|e| ErrorInternalServerError(format!("Failed to create campaign: {}", e)))?;
+
+    Ok(HttpResponse::Created().json(campaign))
+}
+
+#[actix_web::get("/campaigns")]
+pub async fn list_campaigns(
+    state: web::Data<AppState>,
+) -> Result<web::Json<Vec<Campaign>>, actix_web::Error> {
+    let campaigns = sqlx::query_as::<_, Campaign>(&format!(
+        "SELECT {} FROM system_campaigns ORDER BY created_at DESC",
+        CAMPAIGN_COLUMNS
+    ))
+    .fetch_all(database(&state)?)
+    .await
+    .map_err(|e| ErrorInternalServerError(format!("Failed to list campaigns: {}", e)))?;
+
+    Ok(web::Json(campaigns))
+}
+
+async fn find_campaign(state: &AppState, id: Uuid) -> Result<Campaign, actix_web::Error> {
+    get_campaign(database(state)?, id)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Failed to load campaign: {}", e)))?
+        .ok_or_else(|| ErrorNotFound("Campaign not found"))
+}
+
+#[actix_web::get("/campaigns/{id}")]
+pub async fn get_campaign_details(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+) -> Result<web::Json<Campaign>, actix_web::Error> {
+    Ok(web::Json(find_campaign(&state, path.into_inner()).await?))
+}
+
+/// Starts queueing the campaign's messages in the outbox, to go out at its
+/// `send_at`. Queueing runs in the background: the campaign's status and
+/// stats show its progress, and sending a failed campaign again resumes it.
+#[actix_web::post("/campaigns/{id}/send")]
+pub async fn send_campaign_now(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let db = database(&state)?;
+    tracking_secret(&state)
+        .ok_or_else(|| ErrorInternalServerError("EMAIL_TRACKING_SECRET not set"))?;
+    let campaign = find_campaign(&state, path.into_inner()).await?;
+    set_campaign_status(db, campaign.id, CAMPAIGN_SENDING, None)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Failed to start campaign: {}", e)))?;
+
+    let state = state.get_ref().clone();
+    let sending = campaign.clone();
+    tokio::spawn(async move {
+        let (status, error) = match send_campaign(&state, &sending).await {
+            Ok(result) => {
+                log::info!(
+                    "Campaign {} queued {} messages, skipped {}, {} invalid recipients",
+                    sending.id,
+                    result.queued,
+                    result.skipped,
+                    result.invalid.len()
+                );
+                if !result.invalid.is_empty() {
+                    log::warn!(
+                        "Invalid recipients of campaign {}: {}",
+                        sending.id,
+                        result.invalid.join(", ")
+                    );
+                }
+                (CAMPAIGN_QUEUED, None)
+            }
+            Err(e) => {
+                log::error!("Failed to send campaign {}: {}", sending.id, e);
+                (CAMPAIGN_FAILED, Some(e))
+            }
+        };
+        if let Some(db) = state.db.as_ref() {
+            if let Err(e) = set_campaign_status(db, sending.id, status, error.as_deref()).await {
+                log::error!("Failed to update campaign {}: {}", sending.id, e);
+            }
+        }
+    });
+
+    Ok(HttpResponse::Accepted().json(Campaign {
+        status: CAMPAIGN_SENDING.to_string(),
+        last_error: None,
+        ..campaign
+    }))
+}
+
+#[actix_web::get("/campaigns/{id}/stats")]
+pub async fn get_campaign_stats(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+) -> Result<web::Json<CampaignStats>, actix_web::Error> {
+    let campaign = find_campaign(&state, path.into_inner()).await?;
+    let stats = campaign_stats(database(&state)?, campaign.id)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Failed to load stats: {}", e)))?;
+
+    Ok(web::Json(stats))
+}
+
+/// Tracked link of a campaign email: checks the token's signature, records
+/// the click and redirects to the URL the token carries
+#[actix_web::get("/campaigns/{id}/r/{token}")]
+pub async fn follow_link(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    path: web::Path<(Uuid, String)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (campaign_id, token) = path.into_inner();
+    let campaign_id = campaign_id.to_string();
+    let secret = tracking_secret(&state).ok_or_else(|| ErrorNotFound("Link not found"))?;
+    // A forged or altered token must not turn this into an open redirect
+    let (email, url) = verify_link_token(secret, &campaign_id, &token)
+        .ok_or_else(|| ErrorNotFound("Link not found"))?;
+
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    // A failure to record must not keep the recipient from the page
+    if let Err(e) = sqlx::query(
+        "INSERT INTO campaign_clicks (campaign_id, email, url, user_agent, clicked_at) \
+         VALUES ($1, $2, $3, $4, NOW())",
+    )
+    .bind(&campaign_id)
+    .bind(&email)
+    .bind(&url)
+    .bind(user_agent)
+    .execute(database(&state)?)
+    .await
+    {
+        log::error!("Failed to record click of campaign {}: {}", campaign_id, e);
+    }
+
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recipient_query_must_be_a_single_select() {
+        assert!(validate_recipient_query("SELECT email FROM customers").is_ok());
+        assert!(validate_recipient_query("  select email from customers;  ").is_ok());
+        assert!(validate_recipient_query(
+            "WITH active AS (SELECT * FROM customers) SELECT email FROM active"
+        )
+        .is_ok());
+
+        assert!(validate_recipient_query("DELETE FROM customers").is_err());
+        assert!(validate_recipient_query("").is_err());
+        assert!(validate_recipient_query("SELECT 1; DROP TABLE customers").is_err());
+    }
+}
@@ -9,6 +9,7 @@ pub struct AppConfig {
     pub email: EmailConfig,
     pub ai: AIConfig,
     pub site_path: String,
+    pub script_limits: ScriptLimits,
 }
 
 #[derive(Clone)]
@@ -27,12 +28,15 @@ pub struct MinioConfig {
     pub secret_key: String,
     pub use_ssl: bool,
     pub bucket: String,
+    pub notify_token: Option<String>, // Bearer token expected on bucket notifications
 }
 
 #[derive(Clone)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Base URL the server is reached at from outside, for links in emails
+    pub public_url: String,
 }
 
 #[derive(Clone)]
@@ -42,6 +46,99 @@ pub struct EmailConfig {
     pub port: u16,
     pub username: String,
     pub password: String,
+    pub imap_server: String,
+    pub imap_port: u16,
+    pub imap_tls: ImapTls,
+    /// Outbox messages sent per recipient domain and minute
+    pub outbox_domain_rate: u32,
+    /// Sending attempts before a transient failure is final
+    pub outbox_max_attempts: u32,
+    /// Key campaign links and open pixels are signed with
+    pub tracking_secret: Option<String>,
+}
+
+/// How the IMAP connection is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImapTls {
+    /// TLS from the first byte, usually on port 993
+    Implicit,
+    /// Plain connection upgraded with STARTTLS, usually on port 143
+    StartTls,
+    /// No encryption, only for local test servers
+    Plain,
+}
+
+impl ImapTls {
+    /// Parses EMAIL_IMAP_TLS: `tls`, `starttls` or `plain`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "tls" | "ssl" | "implicit" => Some(ImapTls::Implicit),
+            "starttls" => Some(ImapTls::StartTls),
+            "plain" | "none" => Some(ImapTls::Plain),
+            _ => None,
+        }
+    }
+
+    fn default_port(self) -> u16 {
+        match self {
+            ImapTls::Implicit => 993,
+            ImapTls::StartTls | ImapTls::Plain => 143,
+        }
+    }
+}
+
+/// Sandbox limits applied to every BASIC script the server runs. They are
+/// global, read once from the `SCRIPT_MAX_*` variables; bots have no limits
+/// of their own.
+#[derive(Clone)]
+pub struct ScriptLimits {
+    pub max_operations: u64,
+    pub max_call_depth: usize,
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+    pub max_llm_calls: u32,
+    pub max_http_calls: u32,
+    pub max_db_calls: u32,
+    /// Email recipients per run, so a loop can't mass-mail by accident
+    pub max_emails: u32,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: 1_000_000,
+            max_call_depth: 64,
+            max_string_size: 1_048_576,
+            max_array_size: 10_000,
+            max_llm_calls: 20,
+            max_http_calls: 50,
+            max_db_calls: 200,
+            max_emails: 10,
+        }
+    }
+}
+
+impl ScriptLimits {
+    pub fn from_env() -> Self {
+        fn limit<T: std::str::FromStr>(name: &str, default: T) -> T {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+
+        let defaults = Self::default();
+        Self {
+            max_operations: limit("SCRIPT_MAX_OPERATIONS", defaults.max_operations),
+            max_call_depth: limit("SCRIPT_MAX_CALL_DEPTH", defaults.max_call_depth),
+            max_string_size: limit("SCRIPT_MAX_STRING_SIZE", defaults.max_string_size),
+            max_array_size: limit("SCRIPT_MAX_ARRAY_SIZE", defaults.max_array_size),
+            max_llm_calls: limit("SCRIPT_MAX_LLM_CALLS", defaults.max_llm_calls),
+            max_http_calls: limit("SCRIPT_MAX_HTTP_CALLS", defaults.max_http_calls),
+            max_db_calls: limit("SCRIPT_MAX_DB_CALLS", defaults.max_db_calls),
+            max_emails: limit("SCRIPT_MAX_EMAILS", defaults.max_emails),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -109,17 +206,52 @@ impl AppConfig {
                 .parse()
                 .unwrap_or(false),
             bucket: env::var("DRIVE_ORG_PREFIX").unwrap_or_else(|_| "".to_string()),
+            notify_token: env::var("DRIVE_NOTIFY_TOKEN").ok(),
         };
 
+        let email_server = env::var("EMAIL_SERVER").expect("EMAIL_SERVER not set");
+        let imap_tls = env::var("EMAIL_IMAP_TLS")
+            .map(|value| {
+                ImapTls::parse(&value).expect("EMAIL_IMAP_TLS must be tls, starttls or plain")
+            })
+            .unwrap_or(ImapTls::Implicit);
         let email = EmailConfig {
             from: env::var("EMAIL_FROM").expect("EMAIL_FROM not set"),
-            server: env::var("EMAIL_SERVER").expect("EMAIL_SERVER not set"),
+            imap_server: env::var("EMAIL_IMAP_SERVER").unwrap_or_else(|_| email_server.clone()),
+            imap_port: env::var("EMAIL_IMAP_PORT")
+                .ok()
+                .and_then(|p| p.parse().ok())
+                .unwrap_or(imap_tls.default_port()),
+            imap_tls,
+            server: email_server,
             port: env::var("EMAIL_PORT")
                 .expect("EMAIL_PORT not set")
                 .parse()
                 .expect("EMAIL_PORT must be a number"),
             username: env::var("EMAIL_USER").expect("EMAIL_USER not set"),
             password: env::var("EMAIL_PASS").expect("EMAIL_PASS not set"),
+            outbox_domain_rate: env::var("EMAIL_OUTBOX_DOMAIN_RATE")
+                .ok()
+                .and_then(|p| p.parse().ok())
+                .unwrap_or(30),
+            outbox_max_attempts: env::var("EMAIL_OUTBOX_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|p| p.parse().ok())
+                .unwrap_or(5),
+            tracking_secret: env::var("EMAIL_TRACKING_SECRET").ok(),
+        };
+
+        let host = env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let port = env::var("SERVER_PORT")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(8080);
+        let server = ServerConfig {
+            public_url: env::var("SERVER_PUBLIC_URL")
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or_else(|_| format!("http://{}:{}", host, port)),
+            host,
+            port,
         };
 
         let ai = AIConfig {
@@ -131,18 +263,13 @@ impl AppConfig {
 
         AppConfig {
             minio,
-            server: ServerConfig {
-                host: env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-                port: env::var("SERVER_PORT")
-                    .ok()
-                    .and_then(|p| p.parse().ok())
-                    .unwrap_or(8080),
-            },
+            server,
             database,
             database_custom,
             email,
             ai,
-            site_path: env::var("SITES_ROOT").unwrap()
+            site_path: env::var("SITES_ROOT").unwrap(),
+            script_limits: ScriptLimits::from_env(),
         }
     }
 }
\ No newline at end of file
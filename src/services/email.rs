@@ -1,12 +1,31 @@
+use crate::services::email_template::plain_text;
+use crate::services::imap_pool::{ImapPool, ImapSession, PooledSession};
+use crate::services::tracking::{tracking_secret, verify_open_signature};
 use crate::services::{config::EmailConfig, state::AppState};
-use actix_web::error::ErrorInternalServerError;
-use actix_web::http::header::ContentType;
-use actix_web::{web, HttpResponse, Result};
-use lettre::{transport::smtp::authentication::Credentials, Message, SmtpTransport, Transport};
-use serde::Serialize;
-
-use imap::types::Seq;
-use mailparse::{parse_mail, MailHeaderMap}; // Added MailHeaderMap import
+use actix_multipart::Multipart;
+use actix_web::error::{
+    ErrorBadRequest, ErrorInternalServerError, ErrorNotFound, ErrorPayloadTooLarge,
+};
+use actix_web::http::header::{ContentDisposition, ContentType, CONTENT_TYPE};
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use async_imap::imap_proto::{BodyStructure, ContentEncoding, SectionPath};
+use async_imap::types::{Fetch, Flag, Name, NameAttribute};
+use bytes::Bytes;
+use futures::TryStreamExt;
+use lettre::message::header::ContentType as MailContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use minio::s3::builders::ObjectContent;
+use minio::s3::types::S3Api;
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Once};
+use uuid::Uuid;
+
+use mailparse::{parse_mail, DispositionType, MailHeaderMap, ParsedMail}; // Added MailHeaderMap import
 
 #[derive(Debug, Serialize)]
 pub struct EmailResponse {
@@ -20,135 +39,1035 @@ pub struct EmailResponse {
     labels: Vec<String>,
 }
 
-async fn internal_send_email(config: &EmailConfig, to: &str, subject: &str, body: &str) {
-    let email = Message::builder()
-        .from(config.from.parse().unwrap())
-        .to(to.parse().unwrap())
-        .subject(subject)
-        .body(body.to_string())
-        .unwrap();
+/// A recipient that couldn't be parsed, and which of the fields it was in
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressError {
+    pub field: &'static str,
+    pub address: String,
+    pub error: String,
+}
+
+/// Why an email wasn't sent
+#[derive(Debug)]
+pub enum SendError {
+    /// Recipients that aren't valid addresses; nothing was sent
+    InvalidAddresses(Vec<AddressError>),
+    /// The message couldn't be put together, e.g. an attachment is missing
+    Message(String),
+    /// A failure that may pass, such as a 4xx reply or a lost connection;
+    /// sending again later may work
+    Transient(String),
+    /// The server rejected the message with a 5xx reply; retrying won't help
+    Permanent(String),
+}
 
-    let creds = Credentials::new(config.username.clone(), config.password.clone());
+impl SendError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, SendError::Transient(_))
+    }
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::InvalidAddresses(errors) => {
+                let addresses: Vec<String> = errors
+                    .iter()
+                    .map(|error| format!("{} ({})", error.address, error.error))
+                    .collect();
+                write!(f, "Invalid recipients: {}", addresses.join(", "))
+            }
+            SendError::Message(message) => write!(f, "{}", message),
+            SendError::Transient(message) => write!(f, "Temporary SMTP failure: {}", message),
+            SendError::Permanent(message) => {
+                write!(f, "SMTP server rejected the email: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+/// The SMTP transport of `EmailConfig`, built once at startup. Lettre keeps a
+/// pool of its connections, so sends reuse them.
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl Mailer {
+    pub fn new(config: &EmailConfig) -> Result<Self, String> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.server)
+            .map_err(|e| format!("Invalid SMTP server {}: {}", config.server, e))?
+            .port(config.port)
+            .credentials(Credentials::new(
+                config.username.clone(),
+                config.password.clone(),
+            ))
+            .build();
+        Ok(Self { transport })
+    }
+
+    pub async fn send(&self, message: Message) -> Result<(), SendError> {
+        self.transport.send(message).await.map(|_| ()).map_err(|e| {
+            // Connection, TLS and timeout errors carry no reply code
+            if e.is_permanent() || e.is_client() {
+                SendError::Permanent(e.to_string())
+            } else {
+                SendError::Transient(e.to_string())
+            }
+        })
+    }
+}
 
-    SmtpTransport::relay(&config.server)
-        .unwrap()
-        .port(config.port)
-        .credentials(creds)
-        .build()
-        .send(&email)
-        .unwrap();
+// Messages per page of `/emails/list` unless `page_size` says otherwise
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+// Bytes of the text part fetched for the preview of a listed message
+const PREVIEW_FETCH_BYTES: usize = 2048;
+const PREVIEW_CHARS: usize = 150;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ListEmailsQuery {
+    pub folder: Option<String>,
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+    /// Only list messages without `\Seen`
+    pub unseen: Option<bool>,
 }
 
+/// A page of the messages of a folder, newest first. `id` is the message's
+/// UID, which stays valid until the folder's UIDVALIDITY changes; the number of
+/// matching messages is returned in `X-Total-Count`.
 #[actix_web::get("/emails/list")]
 pub async fn list_emails(
     state: web::Data<AppState>,
-) -> Result<web::Json<Vec<EmailResponse>>, actix_web::Error> {
-    let _config = state
+    query: web::Query<ListEmailsQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let folder = query.folder.as_deref().unwrap_or("INBOX");
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let mut session = open_folder(&state, folder).await?;
+
+    let search = if query.unseen.unwrap_or(false) {
+        "UNSEEN"
+    } else {
+        "ALL"
+    };
+    let mut uids: Vec<u32> = session
+        .uid_search(search)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Failed to search emails: {:?}", e)))?
+        .into_iter()
+        .collect();
+    uids.sort_unstable_by(|a, b| b.cmp(a));
+    let total = uids.len();
+
+    let page_uids: Vec<u32> = uids
+        .into_iter()
+        .skip((page as usize - 1).saturating_mul(page_size as usize))
+        .take(page_size as usize)
+        .collect();
+    if page_uids.is_empty() {
+        session.release();
+        return Ok(HttpResponse::Ok()
+            .insert_header(("X-Total-Count", total.to_string()))
+            .json(Vec::<EmailResponse>::new()));
+    }
+
+    let email_list = fetch_summaries(&mut session, &page_uids)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Failed to fetch emails: {:?}", e)))?;
+    session.release();
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Total-Count", total.to_string()))
+        .json(email_list))
+}
+
+/// A mailbox of the account. `special_use` is its RFC 6154 role (`sent`,
+/// `drafts`, `trash`, ...) when the server reports one.
+#[derive(Debug, Serialize)]
+pub struct FolderResponse {
+    pub name: String,
+    pub delimiter: Option<String>,
+    pub selectable: bool,
+    pub special_use: Option<String>,
+}
+
+#[actix_web::get("/emails/folders")]
+pub async fn list_folders(
+    state: web::Data<AppState>,
+) -> Result<web::Json<Vec<FolderResponse>>, actix_web::Error> {
+    let mut session = email_session(&state).await?;
+    let folders = mailboxes(&mut session)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Failed to list folders: {:?}", e)))?;
+    session.release();
+
+    Ok(web::Json(folders))
+}
+
+// Every mailbox of the account, as LIST returns them
+async fn mailboxes(session: &mut ImapSession) -> async_imap::error::Result<Vec<FolderResponse>> {
+    let names: Vec<Name> = session
+        .list(Some(""), Some("*"))
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(names
+        .iter()
+        .map(|name| FolderResponse {
+            name: name.name().to_string(),
+            delimiter: name.delimiter().map(str::to_string),
+            selectable: !name
+                .attributes()
+                .iter()
+                .any(|attribute| matches!(attribute, NameAttribute::NoSelect)),
+            special_use: name.attributes().iter().find_map(|attribute| {
+                match attribute {
+                    NameAttribute::All => Some("all"),
+                    NameAttribute::Archive => Some("archive"),
+                    NameAttribute::Drafts => Some("drafts"),
+                    NameAttribute::Flagged => Some("flagged"),
+                    NameAttribute::Junk => Some("junk"),
+                    NameAttribute::Sent => Some("sent"),
+                    NameAttribute::Trash => Some("trash"),
+                    _ => None,
+                }
+                .map(str::to_string)
+            }),
+        })
+        .collect())
+}
+
+// A pooled session for the configured account
+async fn email_session(state: &AppState) -> Result<PooledSession, actix_web::Error> {
+    let config = state
         .config
         .as_ref()
         .ok_or_else(|| ErrorInternalServerError("Configuration not available"))?;
 
-    // Establish connection
-    let tls = native_tls::TlsConnector::builder().build().map_err(|e| {
-        ErrorInternalServerError(format!("Failed to create TLS connector: {:?}", e))
-    })?;
+    state
+        .imap
+        .get(&config.email)
+        .await
+        .map_err(ErrorInternalServerError)
+}
 
-    let client = imap::connect(
-        (_config.email.server.as_str(), 993),
-        _config.email.server.as_str(),
-        &tls,
-    )
-    .map_err(|e| ErrorInternalServerError(format!("Failed to connect to IMAP: {:?}", e)))?;
+// A pooled session with `folder` selected
+async fn open_folder(state: &AppState, folder: &str) -> Result<PooledSession, actix_web::Error> {
+    let mut session = email_session(state).await?;
+    session
+        .select(folder)
+        .await
+        .map_err(|e| ErrorNotFound(format!("Failed to select {}: {:?}", folder, e)))?;
+    Ok(session)
+}
 
-    // Login
-    let mut session = client
-        .login(&_config.email.username, &_config.email.password)
-        .map_err(|e| ErrorInternalServerError(format!("Login failed: {:?}", e)))?;
+// Folder names in paths are percent-encoded, as they may contain `/`
+fn folder_name(folder: &str) -> String {
+    urlencoding::decode(folder)
+        .map(|folder| folder.into_owned())
+        .unwrap_or_else(|_| folder.to_string())
+}
 
-    // Select INBOX
-    session
-        .select("INBOX")
-        .map_err(|e| ErrorInternalServerError(format!("Failed to select INBOX: {:?}", e)))?;
+// The listing entry of one message, or 404 when the folder doesn't have it
+async fn fetch_summary(
+    session: &mut ImapSession,
+    folder: &str,
+    uid: u32,
+) -> Result<EmailResponse, actix_web::Error> {
+    fetch_summaries(session, &[uid])
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Failed to fetch email: {:?}", e)))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| ErrorNotFound(format!("No email {} in {}", uid, folder)))
+}
 
-    // Search for all messages
-    let messages = session
-        .search("ALL")
-        .map_err(|e| ErrorInternalServerError(format!("Failed to search emails: {:?}", e)))?;
+#[derive(Debug, Serialize)]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
 
-    let mut email_list = Vec::new();
+/// A part of a message that is an attachment. `part` is its IMAP part
+/// number, e.g. `2` or `1.3`.
+#[derive(Debug, Serialize)]
+pub struct AttachmentInfo {
+    pub part: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: usize,
+}
 
-    // Get last 20 messages
-    let recent_messages: Vec<_> = messages.iter().cloned().collect(); // Collect items into a Vec
-    let recent_messages: Vec<Seq> = recent_messages.into_iter().rev().take(20).collect(); // Now you can reverse and take the last 20
-    for seq in recent_messages {
-        // Fetch the entire message (headers + body)
-        let fetch_result = session.fetch(seq.to_string(), "RFC822");
-        let messages = fetch_result
-            .map_err(|e| ErrorInternalServerError(format!("Failed to fetch email: {:?}", e)))?;
+/// A whole message: the listing fields, with `text` holding the full plain
+/// text body, plus its HTML body, headers and attachments
+#[derive(Debug, Serialize)]
+pub struct EmailDetailResponse {
+    #[serde(flatten)]
+    pub email: EmailResponse,
+    pub folder: String,
+    pub html: Option<String>,
+    pub headers: Vec<EmailHeader>,
+    pub attachments: Vec<AttachmentInfo>,
+}
 
-        for msg in messages.iter() {
-            let body = msg
-                .body()
-                .ok_or_else(|| ErrorInternalServerError("No body found"))?;
+// A whole message with its flags and envelope
+async fn fetch_message(
+    state: &AppState,
+    folder: &str,
+    uid: u32,
+) -> Result<Fetch, actix_web::Error> {
+    let mut session = open_folder(state, folder).await?;
+    let messages = fetch_all(
+        session
+            .uid_fetch(uid.to_string(), "(UID FLAGS ENVELOPE BODY.PEEK[])")
+            .await,
+    )
+    .await
+    .map_err(|e| ErrorInternalServerError(format!("Failed to fetch email: {:?}", e)))?;
+    session.release();
+
+    messages
+        .into_iter()
+        .find(|msg| msg.uid == Some(uid))
+        .ok_or_else(|| ErrorNotFound(format!("No email {} in {}", uid, folder)))
+}
 
-            // Parse the complete email message
-            let parsed = parse_mail(body)
-                .map_err(|e| ErrorInternalServerError(format!("Failed to parse email: {:?}", e)))?;
+#[actix_web::get("/emails/{folder}/{uid}")]
+pub async fn get_email(
+    state: web::Data<AppState>,
+    path: web::Path<(String, u32)>,
+) -> Result<web::Json<EmailDetailResponse>, actix_web::Error> {
+    let (folder, uid) = path.into_inner();
+    let folder = folder_name(&folder);
+    let msg = fetch_message(&state, &folder, uid).await?;
+    let body = msg
+        .body()
+        .ok_or_else(|| ErrorInternalServerError("No body found"))?;
+    let parsed = parse_mail(body)
+        .map_err(|e| ErrorInternalServerError(format!("Failed to parse email: {:?}", e)))?;
+
+    let html = body_part(&parsed, "text/html").and_then(|part| part.get_body().ok());
+    let mut email = email_summary(uid, &msg);
+    email.text = match body_part(&parsed, "text/plain") {
+        Some(part) => part.get_body().unwrap_or_default(),
+        None => html.as_deref().map(plain_text).unwrap_or_default(),
+    };
+
+    Ok(web::Json(EmailDetailResponse {
+        email,
+        folder,
+        html,
+        headers: parsed
+            .headers
+            .iter()
+            .map(|header| EmailHeader {
+                name: header.get_key(),
+                value: header.get_value(),
+            })
+            .collect(),
+        attachments: attachment_parts(&parsed)
+            .into_iter()
+            .map(|(part, attachment)| AttachmentInfo {
+                part,
+                filename: attachment_filename(attachment),
+                content_type: attachment.ctype.mimetype.clone(),
+                size: attachment
+                    .get_body_raw()
+                    .map(|body| body.len())
+                    .unwrap_or(0),
+            })
+            .collect(),
+    }))
+}
 
-            // Extract headers
-            let headers = parsed.get_headers();
-            let subject = headers.get_first_value("Subject").unwrap_or_default();
-            let from = headers.get_first_value("From").unwrap_or_default();
-            let date = headers.get_first_value("Date").unwrap_or_default();
+#[derive(Debug, serde::Deserialize)]
+pub struct AttachmentQuery {
+    /// Drive path to save the attachment to instead of returning it; a path
+    /// ending in `/` gets the attachment's filename appended
+    pub save_to: Option<String>,
+}
 
-            // Extract body text (handles both simple and multipart emails)
-            let body_text = if let Some(body_part) = parsed
-                .subparts
-                .iter()
-                .find(|p| p.ctype.mimetype == "text/plain")
-            {
-                body_part.get_body().unwrap_or_default()
+#[actix_web::get("/emails/{folder}/{uid}/attachments/{part}")]
+pub async fn get_attachment(
+    state: web::Data<AppState>,
+    path: web::Path<(String, u32, String)>,
+    query: web::Query<AttachmentQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (folder, uid, part) = path.into_inner();
+    let folder = folder_name(&folder);
+    let msg = fetch_message(&state, &folder, uid).await?;
+    let body = msg
+        .body()
+        .ok_or_else(|| ErrorInternalServerError("No body found"))?;
+    let parsed = parse_mail(body)
+        .map_err(|e| ErrorInternalServerError(format!("Failed to parse email: {:?}", e)))?;
+
+    let attachment = attachment_parts(&parsed)
+        .into_iter()
+        .find(|(number, _)| *number == part)
+        .map(|(_, attachment)| attachment)
+        .ok_or_else(|| ErrorNotFound(format!("No attachment {} in email {}", part, uid)))?;
+    let filename = attachment_filename(attachment);
+    let data = attachment
+        .get_body_raw()
+        .map_err(|e| ErrorInternalServerError(format!("Failed to decode attachment: {:?}", e)))?;
+
+    match &query.save_to {
+        Some(save_to) => {
+            let key = if save_to.ends_with('/') {
+                format!("{}{}", save_to, filename)
             } else {
-                parsed.get_body().unwrap_or_default()
+                save_to.clone()
             };
+            let size = data.len();
+            save_to_drive(&state, &key, data)
+                .await
+                .map_err(ErrorInternalServerError)?;
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "path": key, "size": size })))
+        }
+        None => Ok(HttpResponse::Ok()
+            .content_type(attachment.ctype.mimetype.clone())
+            .insert_header(ContentDisposition::attachment(filename))
+            .body(data)),
+    }
+}
+
+async fn save_to_drive(state: &AppState, key: &str, data: Vec<u8>) -> Result<(), String> {
+    let client = state.minio_client.as_ref().ok_or("Drive not available")?;
+    let bucket = &state
+        .config
+        .as_ref()
+        .ok_or("Configuration not available")?
+        .minio
+        .bucket;
+
+    client
+        .put_object_content(bucket, key, ObjectContent::from(Bytes::from(data)))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to save {} to the drive: {}", key, e))?;
+    Ok(())
+}
 
-            // Create preview
-            let preview = body_text.lines().take(3).collect::<Vec<_>>().join(" ");
-            let preview_truncated = if preview.len() > 150 {
-                format!("{}...", &preview[..150])
+// The first inline part of the given type
+fn body_part<'a>(parsed: &'a ParsedMail<'a>, mimetype: &str) -> Option<&'a ParsedMail<'a>> {
+    if parsed.subparts.is_empty() {
+        return (parsed.ctype.mimetype == mimetype && !is_attachment(parsed)).then_some(parsed);
+    }
+    parsed
+        .subparts
+        .iter()
+        .find_map(|part| body_part(part, mimetype))
+}
+
+fn is_attachment(part: &ParsedMail) -> bool {
+    part.get_content_disposition().disposition == DispositionType::Attachment
+}
+
+/// The attachments of a message with their IMAP part numbers, which follow
+/// the nesting of its multipart bodies
+pub fn attachment_parts<'a>(parsed: &'a ParsedMail<'a>) -> Vec<(String, &'a ParsedMail<'a>)> {
+    fn collect<'a>(
+        part: &'a ParsedMail<'a>,
+        path: &mut Vec<usize>,
+        found: &mut Vec<(String, &'a ParsedMail<'a>)>,
+    ) {
+        if !part.subparts.is_empty() {
+            for (index, subpart) in part.subparts.iter().enumerate() {
+                path.push(index + 1);
+                collect(subpart, path, found);
+                path.pop();
+            }
+        } else if is_attachment(part) || part.ctype.params.contains_key("name") {
+            // A message that isn't multipart has its body as part 1
+            let number = if path.is_empty() {
+                "1".to_string()
             } else {
-                preview
+                path.iter()
+                    .map(usize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(".")
             };
+            found.push((number, part));
+        }
+    }
 
-            // Parse From field
-            let (from_name, from_email) = parse_from_field(&from);
-
-            email_list.push(EmailResponse {
-                id: seq.to_string(),
-                name: from_name,
-                email: from_email,
-                subject: if subject.is_empty() {
-                    "(No Subject)".to_string()
-                } else {
-                    subject
-                },
-                text: preview_truncated,
-                date: if date.is_empty() {
-                    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
-                } else {
-                    date
+    let mut found = Vec::new();
+    collect(parsed, &mut Vec::new(), &mut found);
+    found
+}
+
+pub fn attachment_filename(part: &ParsedMail) -> String {
+    part.get_content_disposition()
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .cloned()
+        .unwrap_or_else(|| "attachment".to_string())
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SetFlagsRequest {
+    /// Sets or clears `\Seen`
+    pub seen: Option<bool>,
+    /// Sets or clears `\Flagged`
+    pub flagged: Option<bool>,
+}
+
+#[actix_web::put("/emails/{folder}/{uid}/flags")]
+pub async fn set_email_flags(
+    state: web::Data<AppState>,
+    path: web::Path<(String, u32)>,
+    request: web::Json<SetFlagsRequest>,
+) -> Result<web::Json<EmailResponse>, actix_web::Error> {
+    let (folder, uid) = path.into_inner();
+    let folder = folder_name(&folder);
+    let mut session = open_folder(&state, &folder).await?;
+
+    for (flag, set) in [("\\Seen", request.seen), ("\\Flagged", request.flagged)] {
+        let set = match set {
+            Some(set) => set,
+            None => continue,
+        };
+        let query = format!("{}FLAGS.SILENT ({})", if set { "+" } else { "-" }, flag);
+        fetch_all(session.uid_store(uid.to_string(), &query).await)
+            .await
+            .map_err(|e| ErrorInternalServerError(format!("Failed to store {}: {:?}", flag, e)))?;
+    }
+
+    let email = fetch_summary(&mut session, &folder, uid).await?;
+    session.release();
+    Ok(web::Json(email))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct MoveEmailRequest {
+    pub to: String,
+}
+
+/// Moves a message to another folder and returns it as it was before the move
+#[actix_web::post("/emails/{folder}/{uid}/move")]
+pub async fn move_email(
+    state: web::Data<AppState>,
+    path: web::Path<(String, u32)>,
+    request: web::Json<MoveEmailRequest>,
+) -> Result<web::Json<EmailResponse>, actix_web::Error> {
+    let (folder, uid) = path.into_inner();
+    let folder = folder_name(&folder);
+    let mut session = open_folder(&state, &folder).await?;
+
+    let email = fetch_summary(&mut session, &folder, uid).await?;
+    move_message(&mut session, uid, &request.to)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Failed to move email: {:?}", e)))?;
+    session.release();
+    Ok(web::Json(email))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct DeleteEmailQuery {
+    /// Removes the message for good instead of moving it to Trash
+    pub expunge: Option<bool>,
+}
+
+/// Moves a message to Trash, or expunges it when asked to or when it already
+/// is in Trash, and returns it as it was before
+#[actix_web::delete("/emails/{folder}/{uid}")]
+pub async fn delete_email(
+    state: web::Data<AppState>,
+    path: web::Path<(String, u32)>,
+    query: web::Query<DeleteEmailQuery>,
+) -> Result<web::Json<EmailResponse>, actix_web::Error> {
+    let (folder, uid) = path.into_inner();
+    let folder = folder_name(&folder);
+    let mut session = open_folder(&state, &folder).await?;
+
+    let email = fetch_summary(&mut session, &folder, uid).await?;
+    let trash = trash_folder(&mut session)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Failed to find Trash: {:?}", e)))?;
+
+    let deleted = if query.expunge.unwrap_or(false) || folder == trash {
+        expunge_message(&mut session, uid).await
+    } else {
+        move_message(&mut session, uid, &trash).await
+    };
+    deleted.map_err(|e| ErrorInternalServerError(format!("Failed to delete email: {:?}", e)))?;
+    session.release();
+    Ok(web::Json(email))
+}
+
+// The folder marked as `\Trash`, or one named Trash, created if missing
+async fn trash_folder(session: &mut ImapSession) -> async_imap::error::Result<String> {
+    let folders = mailboxes(session).await?;
+    let trash = folders
+        .iter()
+        .find(|folder| folder.special_use.as_deref() == Some("trash"))
+        .or_else(|| {
+            folders
+                .iter()
+                .find(|folder| folder.name.eq_ignore_ascii_case("Trash"))
+        });
+
+    match trash {
+        Some(trash) => Ok(trash.name.clone()),
+        None => {
+            session.create("Trash").await?;
+            Ok("Trash".to_string())
+        }
+    }
+}
+
+// Moves a message of the selected folder, with MOVE where the server has it
+async fn move_message(
+    session: &mut ImapSession,
+    uid: u32,
+    to: &str,
+) -> async_imap::error::Result<()> {
+    if session.capabilities().await?.has_str("MOVE") {
+        return session.uid_mv(uid.to_string(), to).await;
+    }
+    session.uid_copy(uid.to_string(), to).await?;
+    expunge_message(session, uid).await
+}
+
+// Removes a message of the selected folder. Without UIDPLUS only EXPUNGE is
+// available, which also removes any other message marked `\Deleted`.
+async fn expunge_message(session: &mut ImapSession, uid: u32) -> async_imap::error::Result<()> {
+    fetch_all(
+        session
+            .uid_store(uid.to_string(), "+FLAGS.SILENT (\\Deleted)")
+            .await,
+    )
+    .await?;
+
+    if session.capabilities().await?.has_str("UIDPLUS") {
+        let _: Vec<u32> = session
+            .uid_expunge(uid.to_string())
+            .await?
+            .try_collect()
+            .await?;
+    } else {
+        let _: Vec<u32> = session.expunge().await?.try_collect().await?;
+    }
+    Ok(())
+}
+
+// The listing entries of the messages with `uids` in the selected folder,
+// newest first
+async fn fetch_summaries(
+    session: &mut ImapSession,
+    uids: &[u32],
+) -> async_imap::error::Result<Vec<EmailResponse>> {
+    let messages = fetch_all(
+        session
+            .uid_fetch(uid_set(uids), "(UID FLAGS ENVELOPE BODYSTRUCTURE)")
+            .await,
+    )
+    .await?;
+
+    let mut email_list: Vec<EmailResponse> = Vec::new();
+    let mut text_parts: Vec<(u32, TextPart)> = Vec::new();
+    for msg in &messages {
+        let uid = match msg.uid {
+            Some(uid) => uid,
+            None => continue,
+        };
+        if let Some(part) = msg.bodystructure().and_then(preview_part) {
+            text_parts.push((uid, part));
+        }
+        email_list.push(email_summary(uid, msg));
+    }
+
+    let previews = fetch_previews(session, &text_parts).await?;
+    for email in &mut email_list {
+        if let Some(preview) = previews.get(&email.id) {
+            email.text = preview.clone();
+        }
+    }
+    email_list.sort_by_key(|email| std::cmp::Reverse(email.id.parse::<u32>().unwrap_or(0)));
+    Ok(email_list)
+}
+
+// The listing entry of a message fetched with FLAGS and ENVELOPE; `text` is
+// filled in with the preview afterwards
+fn email_summary(uid: u32, msg: &Fetch) -> EmailResponse {
+    let envelope = msg.envelope();
+    let from = envelope
+        .and_then(|envelope| envelope.from.as_ref())
+        .and_then(|from| from.first());
+    let (name, email) = match from {
+        Some(address) => {
+            let mailbox = address.mailbox.as_deref().map(String::from_utf8_lossy);
+            let host = address.host.as_deref().map(String::from_utf8_lossy);
+            let email = match (mailbox, host) {
+                (Some(mailbox), Some(host)) => format!("{}@{}", mailbox, host),
+                (Some(mailbox), None) => mailbox.to_string(),
+                _ => String::new(),
+            };
+            let name = address
+                .name
+                .as_deref()
+                .map(decode_header_value)
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| "Unknown".to_string());
+            (name, email)
+        }
+        None => ("Unknown".to_string(), String::new()),
+    };
+    let subject = envelope
+        .and_then(|envelope| envelope.subject.as_deref())
+        .map(decode_header_value)
+        .unwrap_or_default();
+    let date = envelope
+        .and_then(|envelope| envelope.date.as_deref())
+        .map(decode_header_value)
+        .unwrap_or_default();
+    let (read, labels) = flag_labels(msg);
+
+    EmailResponse {
+        id: uid.to_string(),
+        name,
+        email,
+        subject: if subject.is_empty() {
+            "(No Subject)".to_string()
+        } else {
+            subject
+        },
+        text: String::new(),
+        date: if date.is_empty() {
+            chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
+        } else {
+            date
+        },
+        read,
+        labels,
+    }
+}
+
+// Whether the message is `\Seen`, and its other flags and keywords as labels
+fn flag_labels(msg: &Fetch) -> (bool, Vec<String>) {
+    let mut read = false;
+    let mut labels = Vec::new();
+    for flag in msg.flags() {
+        match flag {
+            Flag::Seen => read = true,
+            Flag::Answered => labels.push("answered".to_string()),
+            Flag::Flagged => labels.push("flagged".to_string()),
+            Flag::Deleted => labels.push("deleted".to_string()),
+            Flag::Draft => labels.push("draft".to_string()),
+            Flag::Custom(keyword) => labels.push(keyword.to_string()),
+            _ => {}
+        }
+    }
+    (read, labels)
+}
+
+// IMAP sequence set of `uids`, with runs of consecutive UIDs as ranges
+fn uid_set(uids: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &uid in uids {
+        match ranges.last_mut() {
+            Some((_, end)) if uid == *end + 1 => *end = uid,
+            _ => ranges.push((uid, uid)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}:{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Envelope fields may hold RFC 2047 encoded words
+fn decode_header_value(value: &[u8]) -> String {
+    let mut header = b"X: ".to_vec();
+    header.extend_from_slice(value);
+    match mailparse::parse_header(&header) {
+        Ok((header, _)) => header.get_value(),
+        Err(_) => String::from_utf8_lossy(value).to_string(),
+    }
+}
+
+/// A text part of a message, located through its BODYSTRUCTURE
+struct TextPart {
+    /// Part number, e.g. `1.2`
+    section: String,
+    subtype: String,
+    charset: Option<String>,
+    encoding: String,
+}
+
+// The text/plain part of a message, or its text/html one
+fn preview_part(structure: &BodyStructure) -> Option<TextPart> {
+    let mut parts = Vec::new();
+    text_parts(structure, &mut Vec::new(), &mut parts);
+    let plain = parts.iter().position(|part| part.subtype == "plain");
+    let index = plain.or_else(|| parts.iter().position(|part| part.subtype == "html"))?;
+    Some(parts.swap_remove(index))
+}
+
+// Collects the inline text parts, skipping attachments and attached messages
+fn text_parts(structure: &BodyStructure, path: &mut Vec<u32>, parts: &mut Vec<TextPart>) {
+    match structure {
+        BodyStructure::Multipart { bodies, .. } => {
+            for (index, body) in bodies.iter().enumerate() {
+                path.push(index as u32 + 1);
+                text_parts(body, path, parts);
+                path.pop();
+            }
+        }
+        BodyStructure::Text { common, other, .. } => {
+            let is_attachment = common.disposition.as_ref().map_or(false, |disposition| {
+                disposition.ty.eq_ignore_ascii_case("attachment")
+            });
+            if is_attachment {
+                return;
+            }
+            // A message that isn't multipart has its body as part 1
+            let section = if path.is_empty() {
+                "1".to_string()
+            } else {
+                path.iter()
+                    .map(u32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(".")
+            };
+            parts.push(TextPart {
+                section,
+                subtype: common.ty.subtype.to_ascii_lowercase(),
+                charset: common.ty.params.as_ref().and_then(|params| {
+                    params
+                        .iter()
+                        .find(|(key, _)| key.eq_ignore_ascii_case("charset"))
+                        .map(|(_, value)| value.to_string())
+                }),
+                encoding: match &other.transfer_encoding {
+                    ContentEncoding::Base64 => "base64".to_string(),
+                    ContentEncoding::QuotedPrintable => "quoted-printable".to_string(),
+                    _ => "8bit".to_string(),
                 },
-                read: false,
-                labels: Vec::new(),
             });
         }
+        _ => {}
     }
+}
 
-    session
-        .logout()
-        .map_err(|e| ErrorInternalServerError(format!("Failed to logout: {:?}", e)))?;
+// Fetches the start of the text part of each message, one UID FETCH per part
+// number, and returns the previews by UID
+async fn fetch_previews(
+    session: &mut ImapSession,
+    parts: &[(u32, TextPart)],
+) -> async_imap::error::Result<HashMap<String, String>> {
+    let mut by_section: HashMap<&str, Vec<u32>> = HashMap::new();
+    for (uid, part) in parts {
+        by_section.entry(&part.section).or_default().push(*uid);
+    }
+
+    let mut previews = HashMap::new();
+    for (section, uids) in by_section {
+        let path = SectionPath::Part(
+            section.split('.').filter_map(|n| n.parse().ok()).collect(),
+            None,
+        );
+        let query = format!("(UID BODY.PEEK[{}]<0.{}>)", section, PREVIEW_FETCH_BYTES);
+        for msg in fetch_all(session.uid_fetch(uid_set(&uids), &query).await).await? {
+            let (uid, data) = match (msg.uid, msg.section(&path)) {
+                (Some(uid), Some(data)) => (uid, data),
+                _ => continue,
+            };
+            if let Some((_, part)) = parts.iter().find(|(part_uid, _)| *part_uid == uid) {
+                previews.insert(uid.to_string(), preview_text(part, data));
+            }
+        }
+    }
+    Ok(previews)
+}
+
+// Decodes the fetched start of a text part into a one-line preview
+fn preview_text(part: &TextPart, data: &[u8]) -> String {
+    let mut data = data.to_vec();
+    // The fetch may cut base64 mid-group, which wouldn't decode
+    if part.encoding == "base64" {
+        data.retain(|byte| !byte.is_ascii_whitespace());
+        data.truncate(data.len() / 4 * 4);
+    }
+
+    // Let mailparse undo the transfer encoding and charset
+    let mut raw = format!(
+        "Content-Type: text/{}; charset=\"{}\"\r\nContent-Transfer-Encoding: {}\r\n\r\n",
+        part.subtype,
+        part.charset.as_deref().unwrap_or("utf-8"),
+        part.encoding
+    )
+    .into_bytes();
+    raw.extend_from_slice(&data);
+    let text = parse_mail(&raw)
+        .and_then(|parsed| parsed.get_body())
+        .unwrap_or_else(|_| String::from_utf8_lossy(&data).to_string());
+    let text = if part.subtype == "html" {
+        html_to_text(&text)
+    } else {
+        text
+    };
+
+    let preview = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match preview.char_indices().nth(PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}...", &preview[..end]),
+        None => preview,
+    }
+}
+
+fn html_to_text(html: &str) -> String {
+    Html::parse_fragment(html)
+        .root_element()
+        .text()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Collects the responses of a FETCH or UID FETCH, which borrow the session
+// until they are read
+async fn fetch_all<S>(
+    fetched: async_imap::error::Result<S>,
+) -> async_imap::error::Result<Vec<Fetch>>
+where
+    S: futures::Stream<Item = async_imap::error::Result<Fetch>>,
+{
+    fetched?.try_collect().await
+}
+
+/// A message picked up by the email-received trigger
+pub struct ReceivedEmail {
+    pub uid: u32,
+    pub sender: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// What a poll of a watched folder found
+pub struct UnseenEmails {
+    pub uid_validity: u32,
+    /// Highest UID the poll covered: the cursor to poll from next time
+    pub last_uid: u32,
+    pub emails: Vec<ReceivedEmail>,
+}
+
+/// Fetches the unseen messages of `folder` above the `(uid_validity, last_uid)`
+/// cursor without marking them as read. Without a cursor, or when the folder's
+/// UIDVALIDITY no longer matches it, nothing is returned and the cursor starts
+/// at the folder's highest UID, so only messages arriving from then on count.
+pub async fn fetch_unseen_since(
+    imap: &Arc<ImapPool>,
+    email_config: &EmailConfig,
+    folder: &str,
+    cursor: Option<(u32, u32)>,
+) -> Result<UnseenEmails, String> {
+    let mut session = imap.get(email_config).await?;
+
+    let mailbox = session
+        .select(folder)
+        .await
+        .map_err(|e| format!("Failed to select {}: {:?}", folder, e))?;
+    let uid_validity = mailbox.uid_validity.unwrap_or(0);
+    let after_uid = match cursor {
+        Some((validity, last_uid)) if validity == uid_validity => last_uid,
+        _ => {
+            let last_uid = match mailbox.uid_next {
+                Some(uid_next) => uid_next.saturating_sub(1),
+                // `UID *` matches the highest UID only
+                None => session
+                    .uid_search("UID *")
+                    .await
+                    .map_err(|e| format!("Failed to search {}: {:?}", folder, e))?
+                    .into_iter()
+                    .max()
+                    .unwrap_or(0),
+            };
+            session.release();
+            return Ok(UnseenEmails {
+                uid_validity,
+                last_uid,
+                emails: Vec::new(),
+            });
+        }
+    };
+
+    // `N:*` always matches the highest UID, so filter the result as well
+    let mut uids: Vec<u32> = session
+        .uid_search(format!("UNSEEN UID {}:*", after_uid + 1))
+        .await
+        .map_err(|e| format!("Failed to search {}: {:?}", folder, e))?
+        .into_iter()
+        .filter(|uid| *uid > after_uid)
+        .collect();
+    uids.sort_unstable();
+
+    let mut emails = Vec::new();
+    if !uids.is_empty() {
+        let messages = fetch_all(session.uid_fetch(uid_set(&uids), "(UID BODY.PEEK[])").await)
+            .await
+            .map_err(|e| format!("Failed to fetch emails from {}: {:?}", folder, e))?;
 
-    Ok(web::Json(email_list))
+        for msg in messages.iter() {
+            let (uid, body) = match (msg.uid, msg.body()) {
+                (Some(uid), Some(body)) => (uid, body),
+                _ => continue,
+            };
+            let parsed = match parse_mail(body) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    eprintln!("Skipping unparsable email {} in {}: {:?}", uid, folder, e);
+                    continue;
+                }
+            };
+
+            let headers = parsed.get_headers();
+            let from = headers.get_first_value("From").unwrap_or_default();
+            let (_, sender) = parse_from_field(&from);
+
+            emails.push(ReceivedEmail {
+                uid,
+                sender,
+                subject: headers.get_first_value("Subject").unwrap_or_default(),
+                body: plain_text_body(&parsed),
+            });
+        }
+    }
+    emails.sort_by_key(|email| email.uid);
+
+    session.release();
+
+    Ok(UnseenEmails {
+        uid_validity,
+        last_uid: uids.last().copied().unwrap_or(after_uid),
+        emails,
+    })
+}
+
+// Prefers the text/plain part of multipart emails
+fn plain_text_body(parsed: &ParsedMail) -> String {
+    parsed
+        .subparts
+        .iter()
+        .find(|p| p.ctype.mimetype == "text/plain")
+        .unwrap_or(parsed)
+        .get_body()
+        .unwrap_or_default()
 }
 
 // Helper function to parse From field
@@ -200,7 +1119,7 @@ pub async fn save_draft(
         .as_ref()
         .ok_or_else(|| ErrorInternalServerError("Configuration not available"))?;
 
-    match save_email_draft(&config.email, &draft_data).await {
+    match save_email_draft(&state.imap, &config.email, &draft_data).await {
         Ok(draft_id) => Ok(web::Json(SaveDraftResponse {
             success: true,
             message: "Draft saved successfully".to_string(),
@@ -215,27 +1134,17 @@ pub async fn save_draft(
 }
 
 pub async fn save_email_draft(
+    imap: &Arc<ImapPool>,
     email_config: &EmailConfig,
     draft_data: &SaveDraftRequest,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    // Establish connection
-    let tls = native_tls::TlsConnector::builder().build()?;
-    let client = imap::connect(
-        (email_config.server.as_str(), 993),
-        email_config.server.as_str(),
-        &tls,
-    )?;
-
-    // Login
-    let mut session = client
-        .login(&email_config.username, &email_config.password)
-        .map_err(|e| format!("Login failed: {:?}", e))?;
+    let mut session = imap.get(email_config).await?;
 
     // Select or create Drafts folder
-    if session.select("Drafts").is_err() {
+    if session.select("Drafts").await.is_err() {
         // Try to create Drafts folder if it doesn't exist
-        session.create("Drafts")?;
-        session.select("Drafts")?;
+        session.create("Drafts").await?;
+        session.select("Drafts").await?;
     }
 
     // Create email message
@@ -245,60 +1154,59 @@ pub async fn save_email_draft(
         .filter(|cc| !cc.is_empty())
         .map(|cc| format!("Cc: {}\r\n", cc))
         .unwrap_or_default();
+    // The HTML goes with a plain-text alternative generated from it
+    let boundary = format!("=_draft_{}", Uuid::new_v4().simple());
     let email_message = format!(
-        "From: {}\r\nTo: {}\r\n{}Subject: {}\r\nDate: {}\r\nContent-Type: text/html; charset=UTF-8\r\n\r\n{}",
+        "From: {}\r\nTo: {}\r\n{}Subject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: multipart/alternative; boundary=\"{boundary}\"\r\n\r\n\
+         --{boundary}\r\nContent-Type: text/plain; charset=UTF-8\r\n\r\n{}\r\n\
+         --{boundary}\r\nContent-Type: text/html; charset=UTF-8\r\n\r\n{}\r\n\
+         --{boundary}--\r\n",
         email_config.username,
         draft_data.to,
         cc_header,
         draft_data.subject,
         chrono::Utc::now().format("%a, %d %b %Y %H:%M:%S +0000"),
-        draft_data.text
+        plain_text(&draft_data.text).replace('\n', "\r\n"),
+        draft_data.text,
+        boundary = boundary,
     );
 
     // Append to Drafts folder
-    session.append("Drafts", &email_message)?;
+    session.append("Drafts", &email_message).await?;
 
-    session.logout()?;
+    session.release();
 
     Ok(chrono::Utc::now().timestamp().to_string())
 }
 
 pub async fn fetch_latest_email_from_sender(
+    imap: &Arc<ImapPool>,
     email_config: &EmailConfig,
     from_email: &str,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    // Establish connection
-    let tls = native_tls::TlsConnector::builder().build()?;
-    let client = imap::connect(
-        (email_config.server.as_str(), 993),
-        email_config.server.as_str(),
-        &tls,
-    )?;
-
-    // Login
-    let mut session = client
-        .login(&email_config.username, &email_config.password)
-        .map_err(|e| format!("Login failed: {:?}", e))?;
+    let mut session = imap.get(email_config).await?;
 
     // Try to select Archive folder first, then fall back to INBOX
-    if session.select("Archive").is_err() {
-        session.select("INBOX")?;
+    if session.select("Archive").await.is_err() {
+        session.select("INBOX").await?;
     }
 
     // Search for emails from the specified sender
     let search_query = format!("FROM \"{}\"", from_email);
-    let messages = session.search(&search_query)?;
-
-    if messages.is_empty() {
-        session.logout()?;
-        return Err(format!("No emails found from {}", from_email).into());
-    }
+    let messages = session.search(&search_query).await?;
 
     // Get the latest message (highest sequence number)
-    let latest_seq = messages.iter().max().unwrap();
+    let latest_seq = match messages.iter().max() {
+        Some(seq) => *seq,
+        None => {
+            session.release();
+            return Err(format!("No emails found from {}", from_email).into());
+        }
+    };
 
     // Fetch the entire message
-    let messages = session.fetch(latest_seq.to_string(), "RFC822")?;
+    let messages = fetch_all(session.fetch(latest_seq.to_string(), "RFC822").await).await?;
 
     let mut email_text = String::new();
 
@@ -335,7 +1243,7 @@ pub async fn fetch_latest_email_from_sender(
         break; // We only want the first (and should be only) message
     }
 
-    session.logout()?;
+    session.release();
 
     if email_text.is_empty() {
         Err("Failed to extract email content".into())
@@ -354,7 +1262,7 @@ pub async fn get_latest_email_from(
         .as_ref()
         .ok_or_else(|| ErrorInternalServerError("Configuration not available"))?;
 
-    match fetch_latest_email_from_sender(&config.email, &request.from_email).await {
+    match fetch_latest_email_from_sender(&state.imap, &config.email, &request.from_email).await {
         Ok(email_text) => Ok(web::Json(LatestEmailResponse {
             success: true,
             email_text: Some(email_text),
@@ -375,41 +1283,32 @@ pub async fn get_latest_email_from(
 }
 
 pub async fn fetch_latest_sent_to(
+    imap: &Arc<ImapPool>,
     email_config: &EmailConfig,
     to_email: &str,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    // Establish connection
-    let tls = native_tls::TlsConnector::builder().build()?;
-    let client = imap::connect(
-        (email_config.server.as_str(), 993),
-        email_config.server.as_str(),
-        &tls,
-    )?;
-
-    // Login
-    let mut session = client
-        .login(&email_config.username, &email_config.password)
-        .map_err(|e| format!("Login failed: {:?}", e))?;
+    let mut session = imap.get(email_config).await?;
 
-    // Try to select Archive folder first, then fall back to INBOX
-    if session.select("Sent").is_err() {
-        session.select("Sent Items")?;
+    // Try to select Sent folder first, then fall back to Sent Items
+    if session.select("Sent").await.is_err() {
+        session.select("Sent Items").await?;
     }
 
-    // Search for emails from the specified sender
+    // Search for emails to the specified recipient
     let search_query = format!("TO \"{}\"", to_email);
-    let messages = session.search(&search_query)?;
-
-    if messages.is_empty() {
-        session.logout()?;
-        return Err(format!("No emails found to {}", to_email).into());
-    }
+    let messages = session.search(&search_query).await?;
 
     // Get the latest message (highest sequence number)
-    let latest_seq = messages.iter().max().unwrap();
+    let latest_seq = match messages.iter().max() {
+        Some(seq) => *seq,
+        None => {
+            session.release();
+            return Err(format!("No emails found to {}", to_email).into());
+        }
+    };
 
     // Fetch the entire message
-    let messages = session.fetch(latest_seq.to_string(), "RFC822")?;
+    let messages = fetch_all(session.fetch(latest_seq.to_string(), "RFC822").await).await?;
 
     let mut email_text = String::new();
 
@@ -462,7 +1361,7 @@ pub async fn fetch_latest_sent_to(
         break; // We only want the first (and should be only) message
     }
 
-    session.logout()?;
+    session.release();
 
     // Always return something, even if it's just headers
     if email_text.is_empty() {
@@ -472,34 +1371,488 @@ pub async fn fetch_latest_sent_to(
     }
 }
 
+// Largest JSON body accepted by `/emails/send`; attachments that are larger
+// come from the drive or a multipart upload
+const MAX_SEND_BODY: usize = 1_048_576;
+// Largest file of a multipart `/emails/send`, and largest sum of its fields;
+// most SMTP servers reject messages above 25 MB anyway
+const MAX_UPLOAD_FILE: usize = 10 * 1_048_576;
+const MAX_UPLOAD_TOTAL: usize = 25 * 1_048_576;
+
+/// A file attached to an outgoing email
+pub struct MailAttachment {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// A drive file to attach, with the name to give it in the email
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct DriveAttachment {
+    pub path: String,
+    pub filename: Option<String>,
+}
+
+/// The JSON body of `/emails/send`. Recipients may be given as an array or as
+/// one comma-separated string. With only `html`, the text alternative is
+/// generated from it.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct SendEmailRequest {
+    #[serde(deserialize_with = "addresses")]
+    pub to: Vec<String>,
+    #[serde(default, deserialize_with = "addresses")]
+    pub cc: Vec<String>,
+    #[serde(default, deserialize_with = "addresses")]
+    pub bcc: Vec<String>,
+    #[serde(default)]
+    pub subject: String,
+    pub text: Option<String>,
+    pub html: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<DriveAttachment>,
+}
+
+impl SendEmailRequest {
+    /// Reads the positional form `[to, subject, body, attachments]` used by
+    /// `CALL "/comm/email/send", ...` and by the former tuple payload. A body
+    /// with HTML tags is sent as HTML; attachments are drive paths.
+    pub fn from_args(args: &[Value]) -> Result<Self, String> {
+        let text = |index: usize| {
+            args.get(index)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let to = split_addresses(&text(0));
+        if to.is_empty() {
+            return Err("No recipient given".to_string());
+        }
+        let body = text(2);
+        let (text, html) = if body.contains("</") || body.contains("<br") {
+            (None, Some(body))
+        } else {
+            (Some(body), None)
+        };
+
+        Ok(Self {
+            to,
+            cc: Vec::new(),
+            bcc: Vec::new(),
+            subject: args
+                .get(1)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            text,
+            html,
+            attachments: args.get(3).map(drive_attachments).unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Addresses {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn addresses<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(match Addresses::deserialize(deserializer)? {
+        Addresses::One(addresses) => split_addresses(&addresses),
+        Addresses::Many(addresses) => addresses,
+    })
+}
+
+fn split_addresses(addresses: &str) -> Vec<String> {
+    addresses
+        .split([',', ';'])
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// A drive path, an array of them, or `{ "path": ..., "filename": ... }` objects
+fn drive_attachments(value: &Value) -> Vec<DriveAttachment> {
+    match value {
+        Value::String(path) if !path.is_empty() => vec![DriveAttachment {
+            path: path.clone(),
+            filename: None,
+        }],
+        Value::Array(items) => items.iter().flat_map(drive_attachments).collect(),
+        Value::Object(_) => serde_json::from_value(value.clone()).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Sends an email with text and HTML alternatives, with the drive files of
+/// the request and `uploads` attached. Returns its Message-ID.
+pub async fn deliver_email(
+    state: &AppState,
+    request: &SendEmailRequest,
+    uploads: Vec<MailAttachment>,
+) -> Result<String, SendError> {
+    let config = state
+        .config
+        .as_ref()
+        .ok_or_else(|| SendError::Message("Configuration not available".to_string()))?;
+    let recipients = parse_recipients(request)?;
+
+    let mut attachments = Vec::new();
+    for attachment in &request.attachments {
+        attachments.push(
+            read_drive_attachment(state, attachment)
+                .await
+                .map_err(SendError::Message)?,
+        );
+    }
+    attachments.extend(uploads);
+
+    let (message, message_id) = build_message(&config.email, request, recipients, attachments)
+        .map_err(SendError::Message)?;
+    state.mailer.send(message).await?;
+    Ok(message_id)
+}
+
+/// The parsed to, cc and bcc recipients of a request
+pub struct Recipients {
+    to: Vec<Mailbox>,
+    cc: Vec<Mailbox>,
+    bcc: Vec<Mailbox>,
+}
+
+/// Parses every recipient, collecting the error of each invalid one
+pub fn parse_recipients(request: &SendEmailRequest) -> Result<Recipients, SendError> {
+    let mut errors = Vec::new();
+    let mut parse = |field: &'static str, addresses: &[String]| -> Vec<Mailbox> {
+        addresses
+            .iter()
+            .filter_map(|address| match address.parse::<Mailbox>() {
+                Ok(mailbox) => Some(mailbox),
+                Err(e) => {
+                    errors.push(AddressError {
+                        field,
+                        address: address.clone(),
+                        error: e.to_string(),
+                    });
+                    None
+                }
+            })
+            .collect()
+    };
+    let recipients = Recipients {
+        to: parse("to", &request.to),
+        cc: parse("cc", &request.cc),
+        bcc: parse("bcc", &request.bcc),
+    };
+
+    if request.to.is_empty() {
+        errors.push(AddressError {
+            field: "to",
+            address: String::new(),
+            error: "At least one recipient is required".to_string(),
+        });
+    }
+    if errors.is_empty() {
+        Ok(recipients)
+    } else {
+        Err(SendError::InvalidAddresses(errors))
+    }
+}
+
+// The message and the Message-ID given to it
+fn build_message(
+    config: &EmailConfig,
+    request: &SendEmailRequest,
+    recipients: Recipients,
+    attachments: Vec<MailAttachment>,
+) -> Result<(Message, String), String> {
+    let from = config
+        .from
+        .parse::<Mailbox>()
+        .map_err(|e| format!("Invalid sender address {}: {}", config.from, e))?;
+    let message_id = format!("<{}@{}>", Uuid::new_v4(), from.email.domain());
+
+    let mut builder = Message::builder()
+        .from(from)
+        .message_id(Some(message_id.clone()))
+        .subject(request.subject.as_str());
+    for to in recipients.to {
+        builder = builder.to(to);
+    }
+    for cc in recipients.cc {
+        builder = builder.cc(cc);
+    }
+    for bcc in recipients.bcc {
+        builder = builder.bcc(bcc);
+    }
+
+    let alternative = request.html.as_ref().map(|html| {
+        let text = request.text.clone().unwrap_or_else(|| plain_text(html));
+        MultiPart::alternative_plain_html(text, html.clone())
+    });
+    let plain = || SinglePart::plain(request.text.clone().unwrap_or_default());
+
+    let message = if attachments.is_empty() {
+        match alternative {
+            Some(alternative) => builder.multipart(alternative),
+            None => builder.singlepart(plain()),
+        }
+    } else {
+        let mut mixed = match alternative {
+            Some(alternative) => MultiPart::mixed().multipart(alternative),
+            None => MultiPart::mixed().singlepart(plain()),
+        };
+        for attachment in attachments {
+            let content_type = MailContentType::parse(&attachment.content_type)
+                .or_else(|_| MailContentType::parse("application/octet-stream"))
+                .map_err(|e| format!("Invalid content type: {}", e))?;
+            mixed = mixed.singlepart(
+                Attachment::new(attachment.filename).body(attachment.data, content_type),
+            );
+        }
+        builder.multipart(mixed)
+    };
+    let message = message.map_err(|e| format!("Failed to build email: {}", e))?;
+    Ok((message, message_id))
+}
+
+async fn read_drive_attachment(
+    state: &AppState,
+    attachment: &DriveAttachment,
+) -> Result<MailAttachment, String> {
+    let client = state.minio_client.as_ref().ok_or("Drive not available")?;
+    let bucket = &state
+        .config
+        .as_ref()
+        .ok_or("Configuration not available")?
+        .minio
+        .bucket;
+
+    let data = client
+        .get_object(bucket, &attachment.path)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to read {} from the drive: {}", attachment.path, e))?
+        .content
+        .to_segmented_bytes()
+        .await
+        .map_err(|e| format!("Failed to read {} from the drive: {}", attachment.path, e))?
+        .to_bytes()
+        .to_vec();
+
+    let filename = attachment.filename.clone().unwrap_or_else(|| {
+        attachment
+            .path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&attachment.path)
+            .to_string()
+    });
+    Ok(MailAttachment {
+        content_type: content_type_of(&filename).to_string(),
+        filename,
+        data,
+    })
+}
+
+// The MIME type of the common document types, by file extension
+fn content_type_of(filename: &str) -> &'static str {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "zip" => "application/zip",
+        "json" => "application/json",
+        "csv" => "text/csv",
+        "txt" => "text/plain",
+        "html" | "htm" => "text/html",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Sends an email. Takes a `SendEmailRequest` as JSON, the positional
+/// `[to, subject, body, attachments]` array, or multipart form data with the
+/// request as JSON in a `message` field and every other field as an uploaded
+/// attachment.
 #[actix_web::post("/emails/send")]
 pub async fn send_email(
-    payload: web::Json<(String, String, String)>,
+    req: HttpRequest,
+    mut payload: web::Payload,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let (to, subject, body) = payload.into_inner();
+    let is_multipart = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("multipart/form-data"));
+
+    let (request, uploads) = if is_multipart {
+        read_send_form(Multipart::new(req.headers(), payload)).await?
+    } else {
+        let mut body = web::BytesMut::new();
+        while let Some(chunk) = payload.try_next().await? {
+            if body.len() + chunk.len() > MAX_SEND_BODY {
+                return Err(ErrorPayloadTooLarge("Email request too large"));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        (parse_send_request(&body)?, Vec::new())
+    };
 
-    println!("To: {}", to);
-    println!("Subject: {}", subject);
-    println!("Body: {}", body);
+    match deliver_email(&state, &request, uploads).await {
+        Ok(message_id) => Ok(HttpResponse::Ok().json(SendEmailResponse {
+            success: true,
+            message_id: Some(message_id),
+            error: None,
+            transient: false,
+            invalid_addresses: Vec::new(),
+        })),
+        Err(e) => {
+            let mut response = match &e {
+                SendError::InvalidAddresses(_) | SendError::Message(_) => {
+                    HttpResponse::BadRequest()
+                }
+                SendError::Transient(_) => HttpResponse::ServiceUnavailable(),
+                SendError::Permanent(_) => HttpResponse::UnprocessableEntity(),
+            };
+            Ok(response.json(SendEmailResponse {
+                success: false,
+                message_id: None,
+                error: Some(e.to_string()),
+                transient: e.is_transient(),
+                invalid_addresses: match e {
+                    SendError::InvalidAddresses(errors) => errors,
+                    _ => Vec::new(),
+                },
+            }))
+        }
+    }
+}
 
-    // Send via SMTP
-    internal_send_email(&state.config.clone().unwrap().email, &to, &subject, &body).await;
+#[derive(Debug, Serialize)]
+pub struct SendEmailResponse {
+    pub success: bool,
+    pub message_id: Option<String>,
+    pub error: Option<String>,
+    /// Whether sending again later may work
+    pub transient: bool,
+    pub invalid_addresses: Vec<AddressError>,
+}
 
-    Ok(HttpResponse::Ok().finish())
+fn parse_send_request(body: &[u8]) -> Result<SendEmailRequest, actix_web::Error> {
+    let value: Value = serde_json::from_slice(body)
+        .map_err(|e| ErrorBadRequest(format!("Invalid email request: {}", e)))?;
+    match value {
+        Value::Array(args) => SendEmailRequest::from_args(&args).map_err(ErrorBadRequest),
+        value => serde_json::from_value(value)
+            .map_err(|e| ErrorBadRequest(format!("Invalid email request: {}", e))),
+    }
 }
 
+// Limits are checked while streaming, so an oversized upload is refused
+// before it is held in memory
+async fn read_send_form(
+    mut form: Multipart,
+) -> Result<(SendEmailRequest, Vec<MailAttachment>), actix_web::Error> {
+    let mut request = None;
+    let mut uploads = Vec::new();
+    let mut total = 0;
+
+    while let Some(mut field) = form.try_next().await? {
+        let name = field.name().to_string();
+        let filename = field
+            .content_disposition()
+            .get_filename()
+            .map(|filename| filename.to_string());
+        let content_type = field.content_type().map(|mime| mime.to_string());
+
+        let limit = if name == "message" {
+            MAX_SEND_BODY
+        } else {
+            MAX_UPLOAD_FILE
+        };
+        let mut data = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            if data.len() + chunk.len() > limit {
+                return Err(ErrorPayloadTooLarge(format!("Field {} too large", name)));
+            }
+            total += chunk.len();
+            if total > MAX_UPLOAD_TOTAL {
+                return Err(ErrorPayloadTooLarge("Email request too large"));
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        if name == "message" {
+            request = Some(parse_send_request(&data)?);
+        } else {
+            let filename = filename.unwrap_or(name);
+            uploads.push(MailAttachment {
+                content_type: content_type
+                    .unwrap_or_else(|| content_type_of(&filename).to_string()),
+                filename,
+                data,
+            });
+        }
+    }
+
+    let request = request.ok_or_else(|| ErrorBadRequest("Missing message field"))?;
+    Ok((request, uploads))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenPixelQuery {
+    pub sig: Option<String>,
+}
+
+/// Open pixel of campaign emails. Only opens with a valid `sig` are counted,
+/// so entries can't be forged for arbitrary addresses; the image is served
+/// either way.
 #[actix_web::get("/campaigns/{campaign_id}/click/{email}")]
 pub async fn save_click(
     path: web::Path<(String, String)>,
+    query: web::Query<OpenPixelQuery>,
     state: web::Data<AppState>,
-) -> HttpResponse {
+) -> Result<HttpResponse, actix_web::Error> {
+    static UNVERIFIED_OPENS: Once = Once::new();
+
+    let db = state
+        .db
+        .as_ref()
+        .ok_or_else(|| ErrorInternalServerError("Database not available"))?;
     let (campaign_id, email) = path.into_inner();
-    let _ = sqlx::query("INSERT INTO clicks (campaign_id, email, updated_at) VALUES ($1, $2, NOW()) ON CONFLICT (campaign_id, email) DO UPDATE SET updated_at = NOW()")
-        .bind(campaign_id)
-        .bind(email)
-        .execute(state.db.as_ref().unwrap())
-        .await;
+    let secret = tracking_secret(&state);
+    let signed = match (secret, query.sig.as_deref()) {
+        (Some(secret), Some(sig)) => verify_open_signature(secret, &campaign_id, &email, sig),
+        _ => false,
+    };
+    if !signed {
+        UNVERIFIED_OPENS.call_once(|| match secret {
+            Some(_) => log::warn!("Not counting campaign opens without a valid signature"),
+            None => log::warn!("Not counting campaign opens: EMAIL_TRACKING_SECRET is not set"),
+        });
+    }
+    // The pixel counts opens; link clicks go through the campaign's redirects
+    if signed {
+        let _ = sqlx::query("INSERT INTO campaign_opens (campaign_id, email, updated_at) VALUES ($1, $2, NOW()) ON CONFLICT (campaign_id, email) DO UPDATE SET updated_at = NOW(), opens = campaign_opens.opens + 1")
+            .bind(campaign_id)
+            .bind(email)
+            .execute(db)
+            .await;
+    }
 
     let pixel = [
         0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, // PNG header
@@ -514,18 +1867,25 @@ pub async fn save_click(
     ]; // EOF
 
     // At the end of your save_click function:
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::png())
-        .body(pixel.to_vec()) // Using slicing to pass a reference
+        .body(pixel.to_vec())) // Using slicing to pass a reference
 }
 
 #[actix_web::get("/campaigns/{campaign_id}/emails")]
-pub async fn get_emails(path: web::Path<String>, state: web::Data<AppState>) -> String {
+pub async fn get_emails(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<String, actix_web::Error> {
+    let db = state
+        .db
+        .as_ref()
+        .ok_or_else(|| ErrorInternalServerError("Database not available"))?;
     let campaign_id = path.into_inner();
-    let rows = sqlx::query_scalar::<_, String>("SELECT email FROM clicks WHERE campaign_id = $1")
+    let rows = sqlx::query_scalar::<_, String>("SELECT email FROM campaign_opens WHERE campaign_id = $1")
         .bind(campaign_id)
-        .fetch_all(state.db.as_ref().unwrap())
+        .fetch_all(db)
         .await
-        .unwrap_or_default();
-    rows.join(",")
+        .map_err(|e| ErrorInternalServerError(format!("Failed to list opens: {}", e)))?;
+    Ok(rows.join(","))
 }
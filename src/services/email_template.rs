@@ -0,0 +1,541 @@
+use crate::services::state::AppState;
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound};
+use actix_web::web;
+use scraper::{ElementRef, Html};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Template files of the bot package: `<name>.html` for the default language
+/// and `<name>.<language>.html` for its variants, e.g. `reply.pt-BR.html`
+pub const TEMPLATE_DIR: &str = "src/prompts/templates";
+
+/// Why a template couldn't be rendered
+#[derive(Debug)]
+pub enum TemplateError {
+    NotFound(String),
+    Syntax(String),
+    Storage(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::NotFound(name) => write!(f, "Template '{}' not found", name),
+            TemplateError::Syntax(error) => write!(f, "Invalid template: {}", error),
+            TemplateError::Storage(error) => write!(f, "Failed to load template: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// A rendered email: the HTML body, its plain-text alternative and the
+/// subject, if the template has one
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderedEmail {
+    pub subject: Option<String>,
+    pub html: String,
+    pub text: String,
+}
+
+/// One piece of a parsed template
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    /// `{{path}}` is HTML-escaped, `{{{path}}}` and `{{&path}}` are not
+    Var {
+        path: String,
+        escape: bool,
+    },
+    /// `{{#path}}...{{/path}}` renders once for a truthy value and once per
+    /// item of a list; `{{^path}}...{{/path}}` renders when it is falsy
+    Section {
+        path: String,
+        inverted: bool,
+        children: Vec<Node>,
+    },
+}
+
+/// An email template with `{{var}}` placeholders and conditional sections.
+/// A first line `Subject: ...` holds the subject, itself a template.
+#[derive(Debug, Clone)]
+pub struct EmailTemplate {
+    subject: Option<Vec<Node>>,
+    body: Vec<Node>,
+}
+
+impl EmailTemplate {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let (subject, body) = match source.strip_prefix("Subject:") {
+            Some(rest) => {
+                let (subject, body) = rest.split_once('\n').unwrap_or((rest, ""));
+                (Some(subject.trim()), body)
+            }
+            None => (None, source),
+        };
+        Ok(Self {
+            subject: subject.map(parse).transpose()?,
+            body: parse(body)?,
+        })
+    }
+
+    /// Replaces the subject with the template `subject`, if it isn't empty
+    pub fn with_subject(mut self, subject: Option<&str>) -> Result<Self, TemplateError> {
+        if let Some(subject) = subject.filter(|subject| !subject.is_empty()) {
+            self.subject = Some(parse(subject)?);
+        }
+        Ok(self)
+    }
+
+    pub fn render(&self, data: &Value) -> RenderedEmail {
+        let mut html = String::new();
+        render_nodes(&self.body, &mut vec![data], true, &mut html);
+
+        let subject = self.subject.as_ref().map(|subject| {
+            let mut text = String::new();
+            render_nodes(subject, &mut vec![data], false, &mut text);
+            text
+        });
+
+        RenderedEmail {
+            subject,
+            text: plain_text(&html),
+            html,
+        }
+    }
+}
+
+fn parse(source: &str) -> Result<Vec<Node>, TemplateError> {
+    // Open sections with the nodes collected so far at the level above them
+    let mut open: Vec<(String, bool, Vec<Node>)> = Vec::new();
+    let mut nodes = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            nodes.push(Node::Text(rest[..start].to_string()));
+        }
+        let (tag, raw, after) = if let Some(tag) = rest[start..].strip_prefix("{{{") {
+            let end = tag
+                .find("}}}")
+                .ok_or_else(|| TemplateError::Syntax("unclosed {{{".to_string()))?;
+            (&tag[..end], true, &tag[end + 3..])
+        } else {
+            let tag = &rest[start + 2..];
+            let end = tag
+                .find("}}")
+                .ok_or_else(|| TemplateError::Syntax("unclosed {{".to_string()))?;
+            (&tag[..end], false, &tag[end + 2..])
+        };
+        rest = after;
+
+        let tag = tag.trim();
+        if raw {
+            nodes.push(Node::Var {
+                path: tag.to_string(),
+                escape: false,
+            });
+            continue;
+        }
+        match tag.chars().next() {
+            Some('!') => {}
+            Some('&') => nodes.push(Node::Var {
+                path: tag[1..].trim().to_string(),
+                escape: false,
+            }),
+            Some(kind @ ('#' | '^')) => {
+                open.push((tag[1..].trim().to_string(), kind == '^', nodes));
+                nodes = Vec::new();
+            }
+            Some('/') => {
+                let name = tag[1..].trim();
+                let (path, inverted, mut parent) = open.pop().ok_or_else(|| {
+                    TemplateError::Syntax(format!("{{{{/{}}}}} without a section", name))
+                })?;
+                if path != name {
+                    return Err(TemplateError::Syntax(format!(
+                        "section {} closed by {{{{/{}}}}}",
+                        path, name
+                    )));
+                }
+                parent.push(Node::Section {
+                    path,
+                    inverted,
+                    children: nodes,
+                });
+                nodes = parent;
+            }
+            Some(_) => nodes.push(Node::Var {
+                path: tag.to_string(),
+                escape: true,
+            }),
+            None => return Err(TemplateError::Syntax("empty {{}}".to_string())),
+        }
+    }
+    if let Some((path, _, _)) = open.last() {
+        return Err(TemplateError::Syntax(format!(
+            "section {} not closed",
+            path
+        )));
+    }
+    if !rest.is_empty() {
+        nodes.push(Node::Text(rest.to_string()));
+    }
+    Ok(nodes)
+}
+
+// `escape` is off for the subject, which is plain text
+fn render_nodes<'v>(nodes: &[Node], scope: &mut Vec<&'v Value>, escape: bool, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var {
+                path,
+                escape: escaped,
+            } => {
+                let text = display(lookup(scope, path));
+                if escape && *escaped {
+                    out.push_str(&html_escape(&text));
+                } else {
+                    out.push_str(&text);
+                }
+            }
+            Node::Section {
+                path,
+                inverted,
+                children,
+            } => {
+                let value = lookup(scope, path);
+                if *inverted {
+                    if !truthy(value) {
+                        render_nodes(children, scope, escape, out);
+                    }
+                    continue;
+                }
+                match value {
+                    Value::Array(items) => {
+                        for item in items {
+                            scope.push(item);
+                            render_nodes(children, scope, escape, out);
+                            scope.pop();
+                        }
+                    }
+                    value if truthy(value) => {
+                        scope.push(value);
+                        render_nodes(children, scope, escape, out);
+                        scope.pop();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// `.` is the innermost value; a dotted path starts at the innermost scope
+// that has its first key
+fn lookup<'a>(scope: &[&'a Value], path: &str) -> &'a Value {
+    if path == "." {
+        return scope.last().copied().unwrap_or(&Value::Null);
+    }
+    let mut keys = path.split('.');
+    let first = keys.next().unwrap_or_default();
+    let Some(mut value) = scope.iter().rev().find_map(|value| value.get(first)) else {
+        return &Value::Null;
+    };
+    for key in keys {
+        value = match value {
+            Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => value.get(key),
+        }
+        .unwrap_or(&Value::Null);
+    }
+    value
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::Object(_) => true,
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+pub fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The plain-text alternative of an HTML body: block elements and `<br>` end
+/// lines, links keep their URL and styles and scripts are left out
+pub fn plain_text(html: &str) -> String {
+    let document = Html::parse_fragment(html);
+    let mut text = String::new();
+    append_text(document.root_element(), &mut text);
+
+    // Collapse the whitespace of the markup, keeping at most one blank line
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !line.is_empty() || lines.last().map_or(false, |last| !last.is_empty()) {
+            lines.push(line);
+        }
+    }
+    while lines.last().map_or(false, String::is_empty) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+fn append_text(element: ElementRef, out: &mut String) {
+    let name = element.value().name();
+    if matches!(name, "style" | "script" | "head" | "title") {
+        return;
+    }
+    let block = matches!(
+        name,
+        "p" | "div"
+            | "table"
+            | "tr"
+            | "ul"
+            | "ol"
+            | "li"
+            | "blockquote"
+            | "pre"
+            | "hr"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+    );
+    if block {
+        out.push('\n');
+    }
+    if name == "li" {
+        out.push_str("- ");
+    }
+
+    for child in element.children() {
+        if let Some(text) = child.value().as_text() {
+            out.push_str(text);
+        } else if let Some(child) = ElementRef::wrap(child) {
+            append_text(child, out);
+        }
+    }
+
+    match name {
+        "br" => out.push('\n'),
+        "td" | "th" => out.push(' '),
+        "a" => {
+            let href = element.value().attr("href").unwrap_or_default();
+            let label = element.text().collect::<String>();
+            if href.starts_with("http") && label.trim() != href {
+                out.push_str(&format!(" ({})", href));
+            }
+        }
+        _ => {}
+    }
+    if block {
+        out.push('\n');
+    }
+}
+
+// The languages a template is looked up in: `pt-BR`, then `pt`, then the
+// default variant
+fn language_candidates(language: Option<&str>) -> Vec<String> {
+    let mut candidates = Vec::new();
+    if let Some(language) = language.map(str::trim).filter(|l| !l.is_empty()) {
+        candidates.push(language.to_string());
+        if let Some((primary, _)) = language.split_once(['-', '_']) {
+            candidates.push(primary.to_string());
+        }
+    }
+    candidates.push(String::new());
+    candidates
+}
+
+// Template names may have folders but can't leave the template directory
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Loads template `name` in `language` or the closest variant: a row of
+/// `system_email_templates` takes precedence over a file of the bot package
+pub async fn load_template(
+    state: &AppState,
+    name: &str,
+    language: Option<&str>,
+) -> Result<EmailTemplate, TemplateError> {
+    if !valid_name(name) {
+        return Err(TemplateError::NotFound(name.to_string()));
+    }
+
+    for language in language_candidates(language) {
+        if let Some(db) = state.db.as_ref() {
+            let row = sqlx::query_as::<_, (Option<String>, String)>(
+                "SELECT subject, body FROM system_email_templates WHERE name = $1 AND language = $2",
+            )
+            .bind(name)
+            .bind(&language)
+            .fetch_optional(db)
+            .await
+            .map_err(|e| TemplateError::Storage(e.to_string()))?;
+
+            if let Some((subject, body)) = row {
+                return EmailTemplate::parse(&body)?.with_subject(subject.as_deref());
+            }
+        }
+
+        let file = if language.is_empty() {
+            format!("{}.html", name)
+        } else {
+            format!("{}.{}.html", name, language)
+        };
+        let path: PathBuf = [TEMPLATE_DIR, &file].iter().collect();
+        match tokio::fs::read_to_string(&path).await {
+            Ok(source) => return EmailTemplate::parse(&source),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(TemplateError::Storage(format!("{}: {}", path.display(), e))),
+        }
+    }
+    Err(TemplateError::NotFound(name.to_string()))
+}
+
+/// Renders template `name` with `data`, HTML-escaping the values
+pub async fn render_template(
+    state: &AppState,
+    name: &str,
+    language: Option<&str>,
+    data: &Value,
+) -> Result<RenderedEmail, TemplateError> {
+    Ok(load_template(state, name, language).await?.render(data))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenderTemplateRequest {
+    pub name: String,
+    pub language: Option<String>,
+    #[serde(default)]
+    pub data: Value,
+}
+
+#[actix_web::post("/comm/email/template")]
+pub async fn render_email_template(
+    state: web::Data<AppState>,
+    request: web::Json<RenderTemplateRequest>,
+) -> Result<web::Json<RenderedEmail>, actix_web::Error> {
+    let rendered = render_template(
+        &state,
+        &request.name,
+        request.language.as_deref(),
+        &request.data,
+    )
+    .await
+    .map_err(|e| match e {
+        TemplateError::NotFound(_) => ErrorNotFound(e.to_string()),
+        TemplateError::Syntax(_) => ErrorBadRequest(e.to_string()),
+        TemplateError::Storage(_) => ErrorInternalServerError(e.to_string()),
+    })?;
+
+    Ok(web::Json(rendered))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(source: &str, data: Value) -> RenderedEmail {
+        EmailTemplate::parse(source).unwrap().render(&data)
+    }
+
+    #[test]
+    fn variables_are_escaped_unless_raw() {
+        let data = json!({ "name": "<b>Ana & Bo</b>" });
+        assert_eq!(
+            render("{{name}}", data.clone()).html,
+            "&lt;b&gt;Ana &amp; Bo&lt;/b&gt;"
+        );
+        assert_eq!(render("{{{name}}}", data.clone()).html, "<b>Ana & Bo</b>");
+        assert_eq!(render("{{& name}}", data).html, "<b>Ana & Bo</b>");
+    }
+
+    #[test]
+    fn subject_is_a_plain_text_template() {
+        let rendered = render(
+            "Subject: Hi {{name}}\n<p>Hello</p>",
+            json!({ "name": "Ana & Bo" }),
+        );
+        assert_eq!(rendered.subject.as_deref(), Some("Hi Ana & Bo"));
+        assert_eq!(rendered.html, "<p>Hello</p>");
+    }
+
+    #[test]
+    fn sections_repeat_lists_and_check_values() {
+        let data = json!({
+            "items": [{ "name": "a" }, { "name": "b" }],
+            "vip": true,
+            "empty": [],
+            "customer": { "name": "Ana" },
+        });
+        assert_eq!(
+            render("{{#items}}[{{name}}]{{/items}}", data.clone()).html,
+            "[a][b]"
+        );
+        assert_eq!(
+            render("{{#vip}}VIP {{customer.name}}{{/vip}}", data.clone()).html,
+            "VIP Ana"
+        );
+        assert_eq!(
+            render(
+                "{{^empty}}none{{/empty}}{{#empty}}some{{/empty}}",
+                data.clone()
+            )
+            .html,
+            "none"
+        );
+        assert_eq!(render("{{missing}}{{! comment }}", data).html, "");
+    }
+
+    #[test]
+    fn unbalanced_sections_are_rejected() {
+        for source in ["{{#a}}x", "x{{/a}}", "{{#a}}x{{/b}}", "{{name", "{{}}"] {
+            assert!(
+                matches!(EmailTemplate::parse(source), Err(TemplateError::Syntax(_))),
+                "{}",
+                source
+            );
+        }
+    }
+}
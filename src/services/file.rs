@@ -3,7 +3,7 @@
 use actix_web::{ web};
 
 use actix_multipart::Multipart;
-use actix_web::{post, HttpResponse};
+use actix_web::{post, HttpRequest, HttpResponse};
 use minio::s3::builders::ObjectContent;
 use minio::s3::Client;
 use std::io::Write;
@@ -16,9 +16,14 @@ use minio::s3::creds::StaticProvider;
 use minio::s3::http::BaseUrl;
 use std::str::FromStr;
 
+use crate::models::automation_model::FileUpload;
+use crate::services::automation::AutomationService;
 use crate::services::config::{AppConfig};
 use crate::services::state::AppState;
 
+// Header identifying the user behind an upload, passed on to file-upload automations
+const UPLOADER_HEADER: &str = "X-User-Id";
+
 pub async fn init_minio(config: &AppConfig) -> Result<MinioClient, minio::s3::error::Error> {
     let scheme = if config.minio.use_ssl { "https" } else { "http" };
     let base_url = format!("{}://{}", scheme, config.minio.server);
@@ -30,7 +35,7 @@ pub async fn init_minio(config: &AppConfig) -> Result<MinioClient, minio::s3::er
     );
 
     let minio_client = MinioClientBuilder::new(base_url)
-        .provider(Some(credentials))
+        .provider(Some(Box::new(credentials)))
         .build()?;
 
     Ok(minio_client)
@@ -38,9 +43,11 @@ pub async fn init_minio(config: &AppConfig) -> Result<MinioClient, minio::s3::er
 
 #[post("/files/upload/{folder_path}")]
 pub async fn upload_file(
+    req: HttpRequest,
     folder_path: web::Path<String>,
     mut payload: Multipart,
     state: web::Data<AppState>,
+    automation: web::Data<AutomationService>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let folder_path = folder_path.into_inner();
 
@@ -51,6 +58,8 @@ pub async fn upload_file(
     })?;
 
     let mut file_name = None;
+    let mut content_type = None;
+    let mut size: i64 = 0;
 
     // Iterate over the multipart stream.
 
@@ -59,9 +68,11 @@ pub async fn upload_file(
         file_name = content_disposition
             .get_filename()
             .map(|name| name.to_string());
+        content_type = field.content_type().map(|mime| mime.to_string());
 
         // Write the file content to the temporary file.
         while let Some(chunk) = field.try_next().await? {
+            size += chunk.len() as i64;
             temp_file.write_all(&chunk).map_err(|e| {
                 actix_web::error::ErrorInternalServerError(format!(
                     "Failed to write to temp file: {}",
@@ -98,6 +109,21 @@ pub async fn upload_file(
         actix_web::error::ErrorInternalServerError(format!("Failed to close temp file: {}", e))
     })?;
 
+    // The file is stored; a failing trigger must not fail the upload
+    let upload = FileUpload {
+        key: object_name,
+        size,
+        content_type,
+        uploader: req
+            .headers()
+            .get(UPLOADER_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string()),
+    };
+    if let Err(e) = automation.into_inner().run_file_uploaded(&upload, false).await {
+        eprintln!("Failed to run upload automations for {}: {}", upload.key, e);
+    }
+
     Ok(HttpResponse::Ok().body(format!(
         "Uploaded file '{}' to folder '{}'",
         file_name, folder_path
@@ -106,6 +132,62 @@ pub async fn upload_file(
 
 
 
+/// Receives MinIO bucket notifications (webhook target with `auth_token` set to
+/// `DRIVE_NOTIFY_TOKEN`) and runs the file-upload automations that listen to them
+#[post("/files/events")]
+pub async fn file_events(
+    req: HttpRequest,
+    event: web::Json<serde_json::Value>,
+    state: web::Data<AppState>,
+    automation: web::Data<AutomationService>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let token = state
+        .config
+        .as_ref()
+        .and_then(|config| config.minio.notify_token.clone())
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Bucket notifications are not enabled"))?;
+
+    let authorized = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value == format!("Bearer {}", token));
+    if !authorized {
+        return Err(actix_web::error::ErrorUnauthorized("Invalid notification token"));
+    }
+
+    let automation = automation.into_inner();
+    let records = event["Records"].as_array().cloned().unwrap_or_default();
+
+    for record in records {
+        let event_name = record["eventName"].as_str().unwrap_or_default();
+        if !event_name.starts_with("s3:ObjectCreated:") {
+            continue;
+        }
+
+        let object = &record["s3"]["object"];
+        // Keys arrive URL-encoded in S3 events
+        let key = object["key"].as_str().unwrap_or_default();
+        let key = urlencoding::decode(key)
+            .map(|key| key.into_owned())
+            .unwrap_or_else(|_| key.to_string());
+
+        let upload = FileUpload {
+            key,
+            size: object["size"].as_i64().unwrap_or(0),
+            content_type: object["contentType"].as_str().map(|s| s.to_string()),
+            uploader: record["userIdentity"]["principalId"]
+                .as_str()
+                .map(|s| s.to_string()),
+        };
+        if let Err(e) = automation.run_file_uploaded(&upload, true).await {
+            eprintln!("Failed to run upload automations for {}: {}", upload.key, e);
+        }
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
 #[post("/files/list/{folder_path}")]
 pub async fn list_file(
     folder_path: web::Path<String>,
@@ -0,0 +1,228 @@
+use crate::services::config::{EmailConfig, ImapTls};
+use async_imap::{Client, Session};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+// Idle sessions older than this are checked with a NOOP before reuse
+const CHECK_AFTER: Duration = Duration::from_secs(30);
+// How often idle sessions are kept alive, well below the usual 30 minute
+// server timeout
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(120);
+// Idle sessions unused for this long are logged out
+const IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const MAX_IDLE_PER_ACCOUNT: usize = 4;
+
+/// Any connection an IMAP session can run over: plain TCP or TLS
+pub trait ImapStream: AsyncRead + AsyncWrite + Unpin + Send + Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Debug> ImapStream for T {}
+
+pub type ImapSession = Session<Box<dyn ImapStream>>;
+
+/// The mailbox sessions are pooled for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImapAccount {
+    pub server: String,
+    pub port: u16,
+    pub tls: ImapTls,
+    pub username: String,
+}
+
+impl ImapAccount {
+    pub fn of(config: &EmailConfig) -> Self {
+        Self {
+            server: config.imap_server.clone(),
+            port: config.imap_port,
+            tls: config.imap_tls,
+            username: config.username.clone(),
+        }
+    }
+}
+
+struct IdleSession {
+    session: ImapSession,
+    since: Instant,
+}
+
+/// Logged-in IMAP sessions shared by the email endpoints, keywords and
+/// triggers, so that each of them doesn't connect and log in on every call
+#[derive(Default)]
+pub struct ImapPool {
+    idle: Mutex<HashMap<ImapAccount, Vec<IdleSession>>>,
+}
+
+impl ImapPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A logged-in session for the account of `config`: an idle one that
+    /// still answers, or a new connection
+    pub async fn get(self: &Arc<Self>, config: &EmailConfig) -> Result<PooledSession, String> {
+        let account = ImapAccount::of(config);
+
+        while let Some(mut idle) = self.take_idle(&account) {
+            if idle.since.elapsed() < CHECK_AFTER || idle.session.noop().await.is_ok() {
+                return Ok(PooledSession {
+                    pool: self.clone(),
+                    account,
+                    session: Some(idle.session),
+                });
+            }
+            log::debug!("Dropping stale IMAP session of {}", account.username);
+        }
+
+        let session = connect(config).await?;
+        Ok(PooledSession {
+            pool: self.clone(),
+            account,
+            session: Some(session),
+        })
+    }
+
+    fn take_idle(&self, account: &ImapAccount) -> Option<IdleSession> {
+        self.idle
+            .lock()
+            .unwrap()
+            .get_mut(account)
+            .and_then(|sessions| sessions.pop())
+    }
+
+    fn put(&self, account: ImapAccount, session: ImapSession) {
+        let mut idle = self.idle.lock().unwrap();
+        let sessions = idle.entry(account).or_default();
+        // Beyond the limit the session is closed by dropping it
+        if sessions.len() < MAX_IDLE_PER_ACCOUNT {
+            sessions.push(IdleSession {
+                session,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    /// Periodically sends NOOP on idle sessions so the server keeps them open,
+    /// and logs out those unused for too long
+    pub fn spawn_keep_alive(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+            loop {
+                interval.tick().await;
+
+                let idle: Vec<(ImapAccount, Vec<IdleSession>)> =
+                    self.idle.lock().unwrap().drain().collect();
+                for (account, sessions) in idle {
+                    for mut idle in sessions {
+                        if idle.since.elapsed() > IDLE_TIMEOUT {
+                            let _ = idle.session.logout().await;
+                        } else if idle.session.noop().await.is_ok() {
+                            // Put back without refreshing `since`, so unused
+                            // sessions still time out
+                            self.idle
+                                .lock()
+                                .unwrap()
+                                .entry(account.clone())
+                                .or_default()
+                                .push(idle);
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// A session taken from the pool. `release` hands it back once a command
+/// sequence has completed; a session dropped without it (e.g. after an error,
+/// possibly mid-command) is closed instead.
+pub struct PooledSession {
+    pool: Arc<ImapPool>,
+    account: ImapAccount,
+    session: Option<ImapSession>,
+}
+
+impl PooledSession {
+    pub fn release(mut self) {
+        if let Some(session) = self.session.take() {
+            self.pool.put(self.account.clone(), session);
+        }
+    }
+}
+
+impl Deref for PooledSession {
+    type Target = ImapSession;
+
+    fn deref(&self) -> &ImapSession {
+        self.session
+            .as_ref()
+            .expect("IMAP session already released")
+    }
+}
+
+impl DerefMut for PooledSession {
+    fn deref_mut(&mut self) -> &mut ImapSession {
+        self.session
+            .as_mut()
+            .expect("IMAP session already released")
+    }
+}
+
+/// Connects and logs in with the port and TLS mode of `config`
+async fn connect(config: &EmailConfig) -> Result<ImapSession, String> {
+    let server = config.imap_server.as_str();
+    let tcp = TcpStream::connect((server, config.imap_port))
+        .await
+        .map_err(|e| {
+            format!(
+                "Failed to connect to IMAP {}:{}: {}",
+                server, config.imap_port, e
+            )
+        })?;
+
+    let stream: Box<dyn ImapStream> = match config.imap_tls {
+        ImapTls::Implicit => Box::new(tls_connect(server, tcp).await?),
+        ImapTls::StartTls => {
+            let mut client = Client::new(tcp);
+            read_greeting(&mut client).await?;
+            client
+                .run_command_and_check_ok("STARTTLS", None)
+                .await
+                .map_err(|e| format!("STARTTLS failed: {:?}", e))?;
+            Box::new(tls_connect(server, client.into_inner()).await?)
+        }
+        ImapTls::Plain => Box::new(tcp),
+    };
+
+    let mut client = Client::new(stream);
+    // After STARTTLS the server doesn't greet again
+    if config.imap_tls != ImapTls::StartTls {
+        read_greeting(&mut client).await?;
+    }
+    client
+        .login(&config.username, &config.password)
+        .await
+        .map_err(|(e, _)| format!("Login failed: {:?}", e))
+}
+
+async fn tls_connect(
+    server: &str,
+    stream: TcpStream,
+) -> Result<async_native_tls::TlsStream<TcpStream>, String> {
+    async_native_tls::TlsConnector::new()
+        .connect(server, stream)
+        .await
+        .map_err(|e| format!("TLS handshake with {} failed: {}", server, e))
+}
+
+async fn read_greeting<T: ImapStream>(client: &mut Client<T>) -> Result<(), String> {
+    match client.read_response().await {
+        Some(Ok(_)) => Ok(()),
+        Some(Err(e)) => Err(format!("Failed to read IMAP greeting: {}", e)),
+        None => Err("IMAP server closed the connection".to_string()),
+    }
+}
@@ -0,0 +1,40 @@
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
+use crate::services::utils::{dynamic_to_json, json_value_to_dynamic};
+use rhai::{Dynamic, Engine, ImmutableString};
+use std::sync::Arc;
+
+/// `CALL <expr>` followed by any number of `, <expr>` arguments
+pub const CALL_SYNTAX: &[&str] = &["CALL", "$expr$"];
+
+pub fn call_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
+
+    // The argument count varies, so the syntax is parsed symbol by symbol
+    engine.register_custom_syntax_with_state_raw(
+        CALL_SYNTAX[0],
+        |symbols: &[ImmutableString], look_ahead: &str, _state: &mut Dynamic| {
+            let last = symbols.last().map(|symbol| symbol.as_str());
+            Ok(match last {
+                Some("$expr$") if look_ahead == "," => Some(",".into()),
+                Some("$expr$") => None,
+                _ => Some("$expr$".into()),
+            })
+        },
+        false, // Expression, not statement
+        move |context, inputs, _state| {
+            charge(ScriptCall::Http)?;
+            let path = context.eval_expression_tree(&inputs[0])?.to_string();
+            let mut args = Vec::new();
+            for input in &inputs[1..] {
+                args.push(dynamic_to_json(&context.eval_expression_tree(input)?));
+            }
+
+            let result = backend
+                .call(&path, args)
+                .map_err(|e| format!("CALL {} failed: {}", path, e))?;
+
+            Ok(json_value_to_dynamic(&result))
+        },
+    );
+}
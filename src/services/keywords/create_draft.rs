@@ -1,27 +1,33 @@
 use crate::services::email::save_email_draft;
 use crate::services::email::{fetch_latest_sent_to, SaveDraftRequest};
+use crate::services::email_template::render_template;
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
 use crate::services::state::AppState;
 use rhai::Dynamic;
 use rhai::Engine;
+use serde_json::json;
+use std::sync::Arc;
 
-pub fn create_draft_keyword(state: &AppState, engine: &mut Engine) {
-    let state_clone = state.clone();
+pub const CREATE_DRAFT_SYNTAX: &[&str] = &["CREATE_DRAFT", "$expr$", ",", "$expr$", ",", "$expr$"];
+
+pub fn create_draft_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
 
     engine
         .register_custom_syntax(
-            &["CREATE_DRAFT", "$expr$", ",", "$expr$", ",", "$expr$"],
+            CREATE_DRAFT_SYNTAX,
             true, // Statement
             move |context, inputs| {
+                charge(ScriptCall::Http)?;
                 // Extract arguments
                 let to = context.eval_expression_tree(&inputs[0])?.to_string();
                 let subject = context.eval_expression_tree(&inputs[1])?.to_string();
                 let reply_text = context.eval_expression_tree(&inputs[2])?.to_string();
 
-                // Execute async operations using the same pattern as FIND
-                let fut = execute_create_draft(&state_clone, &to, &subject, &reply_text);
-                let result =
-                    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(fut))
-                        .map_err(|e| format!("Draft creation error: {}", e))?;
+                let result = backend
+                    .create_draft(&to, &subject, &reply_text)
+                    .map_err(|e| format!("Draft creation error: {}", e))?;
 
                 Ok(Dynamic::from(result))
             },
@@ -29,29 +35,26 @@ pub fn create_draft_keyword(state: &AppState, engine: &mut Engine) {
         .unwrap();
 }
 
-async fn execute_create_draft(
+pub async fn execute_create_draft(
     state: &AppState,
     to: &str,
     subject: &str,
     reply_text: &str,
 ) -> Result<String, String> {
-    let get_result = fetch_latest_sent_to(&state.config.clone().unwrap().email, to).await;
-    let email_body = if let Ok(get_result_str) = get_result {
-        if !get_result_str.is_empty() {
-            let email_separator = "<br><hr><br>"; // Horizontal rule in HTML
-            let formatted_reply_text = reply_text.to_string();
-            let formatted_old_text = get_result_str.replace("\n", "<br>");
-            let fixed_reply_text = formatted_reply_text.replace("FIX", "Fixed");
-            format!(
-                "{}{}{}",
-                fixed_reply_text, email_separator, formatted_old_text
-            )
-        } else {
-            reply_text.to_string()
-        }
-    } else {
-        reply_text.to_string()
+    let get_result =
+        fetch_latest_sent_to(&state.imap, &state.config.clone().unwrap().email, to).await;
+    // The reply quotes the last message sent to `to`, if there is one
+    let data = match get_result {
+        Ok(quoted) if !quoted.is_empty() => json!({
+            "reply": reply_text.replace("FIX", "Fixed"),
+            "quoted": quoted,
+        }),
+        _ => json!({ "reply": reply_text }),
     };
+    let email_body = render_template(state, "reply", None, &data)
+        .await
+        .map_err(|e| e.to_string())?
+        .html;
 
     // Create and save draft
     let draft_request = SaveDraftRequest {
@@ -61,7 +64,12 @@ async fn execute_create_draft(
         text: email_body,
     };
 
-    let save_result = save_email_draft(&state.config.clone().unwrap().email, &draft_request).await;
+    let save_result = save_email_draft(
+        &state.imap,
+        &state.config.clone().unwrap().email,
+        &draft_request,
+    )
+    .await;
     match save_result {
         Ok(_) => Ok("Draft saved successfully".to_string()),
         Err(e) => Err(e.to_string()),
@@ -4,34 +4,34 @@ use std::error::Error;
 use std::fs;
 use std::path::{ PathBuf};
 use std::io::Read;
+use std::sync::Arc;
 
-use crate::services::state::AppState;
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
 use crate::services::utils;
 
-pub fn create_site_keyword(state: &AppState, engine: &mut Engine) {
-    let state_clone = state.clone();
+pub const CREATE_SITE_SYNTAX: &[&str] =
+    &["CREATE_SITE", "$expr$", ",", "$expr$", ",", "$expr$"];
+
+pub fn create_site_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
     engine
         .register_custom_syntax(
-            &[
-                "CREATE_SITE", "$expr$", ",", "$expr$", ",", "$expr$",
-                
-            ],
+            CREATE_SITE_SYNTAX,
             true,
             move |context, inputs| {
+                charge(ScriptCall::Llm)?;
                 if inputs.len() < 3 {
                     return Err("Not enough arguments for CREATE SITE".into());
                 }
 
-                let alias = context.eval_expression_tree(&inputs[0])?;
-                let template_dir = context.eval_expression_tree(&inputs[1])?;
-                let prompt = context.eval_expression_tree(&inputs[2])?;
-                
-                let config = state_clone.config.as_ref().expect("Config must be initialized").clone();
+                let alias = context.eval_expression_tree(&inputs[0])?.to_string();
+                let template_dir = context.eval_expression_tree(&inputs[1])?.to_string();
+                let prompt = context.eval_expression_tree(&inputs[2])?.to_string();
                 
-                let fut = create_site(&config, alias, template_dir, prompt);
-                let result =
-                    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(fut))
-                        .map_err(|e| format!("Site creation failed: {}", e))?;
+                let result = backend
+                    .create_site(&alias, &template_dir, &prompt)
+                    .map_err(|e| format!("Site creation failed: {}", e))?;
 
                 Ok(Dynamic::from(result))
             },
@@ -39,11 +39,11 @@ pub fn create_site_keyword(state: &AppState, engine: &mut Engine) {
         .unwrap();
 }
 
-async fn create_site(
+pub async fn create_site(
     config: &crate::services::config::AppConfig,
-    alias: Dynamic,
-    template_dir: Dynamic,
-    prompt: Dynamic,
+    alias: &str,
+    template_dir: &str,
+    prompt: &str,
 ) -> Result<String, Box<dyn Error + Send + Sync>> {
     // Convert paths to platform-specific format
     let base_path = PathBuf::from(&config.site_path);
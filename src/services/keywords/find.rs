@@ -2,41 +2,33 @@ use rhai::Dynamic;
 use rhai::Engine;
 use serde_json::{json, Value};
 use sqlx::{PgPool};
+use std::sync::Arc;
 
-use crate::services::state::AppState;
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
 use crate::services::utils;
 use crate::services::utils::row_to_json;
 use crate::services::utils::to_array;
 
 
-pub fn find_keyword(state: &AppState, engine: &mut Engine) {
-    let db = state.db_custom.clone();
+pub const FIND_SYNTAX: &[&str] = &["FIND", "$expr$", ",", "$expr$"];
 
-    engine
-        .register_custom_syntax(&["FIND", "$expr$", ",", "$expr$"], false, {
-            let db = db.clone();
+pub fn find_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
 
+    engine
+        .register_custom_syntax(FIND_SYNTAX, false, {
             move |context, inputs| {
+                charge(ScriptCall::Db)?;
                 let table_name = context.eval_expression_tree(&inputs[0])?;
                 let filter = context.eval_expression_tree(&inputs[1])?;
-                let binding = db.as_ref().unwrap();
-
-                // Use the current async context instead of creating a new runtime
-                let binding2 = table_name.to_string();
-                let binding3 = filter.to_string();
-                let fut = execute_find(binding, &binding2, &binding3);
 
-                // Use tokio::task::block_in_place + tokio::runtime::Handle::current().block_on
-                let result =
-                    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(fut))
-                        .map_err(|e| format!("DB error: {}", e))?;
+                let results = backend
+                    .find(&table_name.to_string(), &filter.to_string())
+                    .map_err(|e| format!("DB error: {}", e))?;
 
-                if let Some(results) = result.get("results") {
-                    let array = to_array(utils::json_value_to_dynamic(results));
-                    Ok(Dynamic::from(array))
-                } else {
-                    Err("No results".into())
-                }
+                let array = to_array(utils::json_value_to_dynamic(&results));
+                Ok(Dynamic::from(array))
             }
         })
         .unwrap();
@@ -1,9 +1,11 @@
 use rhai::Dynamic;
 use rhai::Engine;
 
+pub const FIRST_SYNTAX: &[&str] = &["FIRST", "$expr$"];
+
 pub fn first_keyword(engine: &mut Engine) {
     engine
-        .register_custom_syntax(&["FIRST", "$expr$"], false, {
+        .register_custom_syntax(FIRST_SYNTAX, false, {
             move |context, inputs| {
                 let input_string = context.eval_expression_tree(&inputs[0])?;
                 let input_str = input_string.to_string();
@@ -1,20 +1,22 @@
 use rhai::Dynamic;
 use rhai::Engine;
-use crate::services::state::AppState;
 
-pub fn for_keyword(_state: &AppState, engine: &mut Engine) {
+pub const EXIT_FOR_SYNTAX: &[&str] = &["EXIT", "FOR"];
+pub const FOR_EACH_SYNTAX: &[&str] = &[
+    "FOR", "EACH", "$ident$", "IN", "$expr$", "$block$", "NEXT", "$ident$",
+];
+
+pub fn for_keyword(engine: &mut Engine) {
 
     engine
-        .register_custom_syntax(&["EXIT", "FOR"], false, |_context, _inputs| {
+        .register_custom_syntax(EXIT_FOR_SYNTAX, false, |_context, _inputs| {
             Err("EXIT FOR".into())
         })
         .unwrap();
 
     engine
         .register_custom_syntax(
-            &[
-                "FOR", "EACH", "$ident$", "IN", "$expr$", "$block$", "NEXT", "$ident$",
-            ],
+            FOR_EACH_SYNTAX,
             true, // We're modifying the scope by adding the loop variable
             |context, inputs| {
                 // Get the iterator variable names
@@ -1,12 +1,18 @@
-use crate::services::state::AppState;
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
 use reqwest::{self, Client};
 use rhai::{Dynamic, Engine};
 use scraper::{Html, Selector};
 use std::error::Error;
+use std::sync::Arc;
+
+pub const GET_SYNTAX: &[&str] = &["GET", "$expr$"];
+
+pub fn get_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
 
-pub fn get_keyword(_state: &AppState, engine: &mut Engine) {
     let _ = engine.register_custom_syntax(
-        &["GET", "$expr$"],
+        GET_SYNTAX,
         false, // Expression, not statement
         move |context, inputs| {
             let url = context.eval_expression_tree(&inputs[0])?;
@@ -17,41 +23,17 @@ pub fn get_keyword(_state: &AppState, engine: &mut Engine) {
                 return Err("URL contains invalid path traversal sequences like '..'.".into());
             }
 
-            let modified_url = if url_str.starts_with("/") {
-                let work_root = std::env::var("WORK_ROOT").unwrap_or_else(|_| "./work".to_string());
-                let full_path = std::path::Path::new(&work_root)
-                    .join(url_str.trim_start_matches('/'))
-                    .to_string_lossy()
-                    .into_owned();
-
-                let base_url = "file://";
-                format!("{}{}", base_url, full_path)
-            } else {
-                url_str.to_string()
-            };
-
-            if modified_url.starts_with("https://") {
-                println!("HTTPS GET request: {}", modified_url);
+            // Paths starting with / are read from WORK_ROOT
+            if url_str.starts_with("https://") {
+                println!("HTTPS GET request: {}", url_str);
+                charge(ScriptCall::Http)?;
+            }
 
-                let fut = execute_get(&modified_url);
-                let result =
-                    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(fut))
-                        .map_err(|e| format!("HTTP request failed: {}", e))?;
+            let result = backend
+                .get(&url_str)
+                .map_err(|e| format!("GET request failed: {}", e))?;
 
-                Ok(Dynamic::from(result))
-            } else if modified_url.starts_with("file://") {
-                // Handle file:// URLs
-                let file_path = modified_url.trim_start_matches("file://");
-                match std::fs::read_to_string(file_path) {
-                    Ok(content) => Ok(Dynamic::from(content)),
-                    Err(e) => Err(format!("Failed to read file: {}", e).into()),
-                }
-            } else {
-                Err(
-                    format!("GET request failed: URL must begin with 'https://' or 'file://'")
-                        .into(),
-                )
-            }
+            Ok(Dynamic::from(result))
         },
     );
 }
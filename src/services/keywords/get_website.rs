@@ -1,4 +1,6 @@
-use crate::services::{state::AppState, web_automation::BrowserPool};
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
+use crate::services::web_automation::BrowserPool;
 use rhai::{Dynamic, Engine};
 use std::error::Error;
 use std::sync::Arc;
@@ -6,14 +8,17 @@ use std::time::Duration;
 use thirtyfour::{By, WebDriver};
 use tokio::time::sleep;
 
-pub fn get_website_keyword(state: &AppState, engine: &mut Engine) {
-    let browser_pool = state.browser_pool.clone(); // Assuming AppState has browser_pool field
+pub const WEBSITE_SYNTAX: &[&str] = &["WEBSITE", "OF", "$expr$"];
+
+pub fn get_website_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
 
     engine
         .register_custom_syntax(
-            &["WEBSITE", "OF", "$expr$"],
+            WEBSITE_SYNTAX,
             false,
             move |context, inputs| {
+                charge(ScriptCall::Http)?;
                 let search_term = context.eval_expression_tree(&inputs[0])?.to_string();
                 
 
@@ -22,15 +27,9 @@ pub fn get_website_keyword(state: &AppState, engine: &mut Engine) {
                     search_term
                 );
 
-                let browser_pool_clone = browser_pool.clone();
-                let fut = execute_headless_browser_search(
-                    browser_pool_clone,
-                    &search_term
-                );
-
-                let result =
-                    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(fut))
-                        .map_err(|e| format!("Headless browser search failed: {}", e))?;
+                let result = backend
+                    .website_search(&search_term)
+                    .map_err(|e| format!("Headless browser search failed: {}", e))?;
 
                 Ok(Dynamic::from(result))
             },
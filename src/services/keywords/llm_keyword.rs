@@ -1,26 +1,26 @@
 use rhai::{Dynamic, Engine};
-use crate::services::{state::AppState, utils::call_llm};
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
+use std::sync::Arc;
 
-pub fn llm_keyword(state: &AppState, engine: &mut Engine) {
+pub const LLM_SYNTAX: &[&str] = &["LLM", "$expr$"];
 
-    let ai_config = state.config.clone().unwrap().ai.clone();
+pub fn llm_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
 
     engine.register_custom_syntax(
-        &["LLM", "$expr$"],  // Syntax: LLM "text to process"
+        LLM_SYNTAX,  // Syntax: LLM "text to process"
         false, // Expression, not statement
         move |context, inputs| {
+            charge(ScriptCall::Llm)?;
             let text = context.eval_expression_tree(&inputs[0])?;
             let text_str = text.to_string();
 
             println!("LLM processing text: {}", text_str);
             
-            // Use the same pattern as GET
-
-            let fut = call_llm(
-                &text_str, &ai_config);
-            let result = tokio::task::block_in_place(|| {
-                tokio::runtime::Handle::current().block_on(fut)
-            }).map_err(|e| format!("LLM call failed: {}", e))?;
+            let result = backend
+                .llm(&text_str)
+                .map_err(|e| format!("LLM call failed: {}", e))?;
             
             Ok(Dynamic::from(result))
         }
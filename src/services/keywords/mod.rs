@@ -1,3 +1,4 @@
+pub mod call;
 pub mod create_draft;
 pub mod create_site;
 pub mod find;
@@ -8,6 +9,132 @@ pub mod get_website;
 pub mod llm_keyword;
 pub mod on;
 pub mod print;
+pub mod send_mail;
 pub mod set;
 pub mod set_schedule;
+pub mod template;
 pub mod wait;
+
+/// A keyword as registered with the script engine, documented for the editor
+/// tools
+pub struct KeywordInfo {
+    pub syntax: &'static [&'static str],
+    pub doc: &'static str,
+}
+
+impl KeywordInfo {
+    /// The words the keyword starts with, e.g. `WEBSITE OF`
+    pub fn name(&self) -> String {
+        self.syntax
+            .iter()
+            .take_while(|segment| !segment.starts_with('$'))
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// How the keyword is written, e.g. `FIND <expr>, <expr>`
+    pub fn usage(&self) -> String {
+        let mut usage = String::new();
+        for segment in self.syntax {
+            let text = match *segment {
+                "$expr$" => "<expr>",
+                "$ident$" => "<name>",
+                "$string$" => "\"<text>\"",
+                "$block$" => "...",
+                "," => {
+                    usage.push(',');
+                    continue;
+                }
+                literal => literal,
+            };
+            if !usage.is_empty() {
+                usage.push(' ');
+            }
+            usage.push_str(text);
+        }
+        usage
+    }
+}
+
+/// Every keyword `ScriptService` registers
+pub const KEYWORDS: &[KeywordInfo] = &[
+    KeywordInfo {
+        syntax: call::CALL_SYNTAX,
+        doc: "Calls a server endpoint with the values after the path as its arguments, e.g. \
+              `CALL \"/comm/email/send\", to, subject, body, attachments`. Returns the \
+              endpoint's result.",
+    },
+    KeywordInfo {
+        syntax: create_draft::CREATE_DRAFT_SYNTAX,
+        doc: "Saves a reply draft to `to` with `subject` and the HTML `body`, quoting the last \
+              message sent to that address.",
+    },
+    KeywordInfo {
+        syntax: create_site::CREATE_SITE_SYNTAX,
+        doc: "Generates `<alias>/index.html` below the sites folder from the HTML files of a \
+              template folder and a prompt. Returns the site folder.",
+    },
+    KeywordInfo {
+        syntax: find::FIND_SYNTAX,
+        doc: "Returns the rows of a table matching a `column=value` filter, as an array of maps.",
+    },
+    KeywordInfo {
+        syntax: first::FIRST_SYNTAX,
+        doc: "Returns the first word of a text.",
+    },
+    KeywordInfo {
+        syntax: for_next::FOR_EACH_SYNTAX,
+        doc: "Runs the lines up to `NEXT <name>` once for every item of an array.",
+    },
+    KeywordInfo {
+        syntax: for_next::EXIT_FOR_SYNTAX,
+        doc: "Leaves the innermost FOR EACH loop.",
+    },
+    KeywordInfo {
+        syntax: get::GET_SYNTAX,
+        doc: "Returns the text of an `https://` URL, or of a `/path` below WORK_ROOT.",
+    },
+    KeywordInfo {
+        syntax: get_website::WEBSITE_SYNTAX,
+        doc: "Searches the web with the headless browser and returns the first result's URL.",
+    },
+    KeywordInfo {
+        syntax: llm_keyword::LLM_SYNTAX,
+        doc: "Sends a prompt to the configured LLM and returns its answer.",
+    },
+    KeywordInfo {
+        syntax: on::ON_SYNTAX,
+        doc: "Registers this script as a trigger: `ON INSERT|UPDATE|DELETE OF \"table\"`, \
+              `ON EMAIL OF \"folder\"` or `ON UPLOAD OF \"folder\"`.",
+    },
+    KeywordInfo {
+        syntax: print::PRINT_SYNTAX,
+        doc: "Writes a value to the log.",
+    },
+    KeywordInfo {
+        syntax: send_mail::SEND_MAIL_SYNTAX,
+        doc: "Sends an email to one or more comma-separated addresses and returns its \
+              Message-ID. A body with HTML tags, e.g. from a template, is sent as HTML with a \
+              text alternative. An optional fourth argument attaches drive files by path. \
+              Every recipient counts against the script's email quota.",
+    },
+    KeywordInfo {
+        syntax: set::SET_SYNTAX,
+        doc: "Updates the rows of a table matching a filter with `column=value` pairs. Returns \
+              the number of rows updated.",
+    },
+    KeywordInfo {
+        syntax: set_schedule::SET_SCHEDULE_SYNTAX,
+        doc: "Schedules this script with a five-field cron expression.",
+    },
+    KeywordInfo {
+        syntax: template::TEMPLATE_SYNTAX,
+        doc: "Renders an email template with the values of a map, HTML-escaped, and returns \
+              the HTML. A `language` entry picks the variant, e.g. `reply.pt-BR.html`.",
+    },
+    KeywordInfo {
+        syntax: wait::WAIT_SYNTAX,
+        doc: "Pauses the script for a number of seconds, at most 300.",
+    },
+];
@@ -1,47 +1,51 @@
 use rhai::Dynamic;
 use rhai::Engine;
 use serde_json::{json, Value};
-use sqlx::PgPool;
+use sqlx::PgExecutor;
+use std::sync::Arc;
+use uuid::Uuid;
 
 use crate::models::automation_model::TriggerKind;
-use crate::services::state::AppState;
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
 
-pub fn on_keyword(state: &AppState, engine: &mut Engine) {
-    let db = state.db_custom.clone();
+pub const ON_SYNTAX: &[&str] = &["ON", "$ident$", "OF", "$string$"];
+
+pub fn on_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
 
     engine
         .register_custom_syntax(
-            ["ON", "$ident$", "OF", "$string$"], // Changed $string$ to $ident$ for operation
+            ON_SYNTAX, // Changed $string$ to $ident$ for operation
             true,
             {
-                let db = db.clone();
-
                 move |context, inputs| {
+                    charge(ScriptCall::Db)?;
                     let trigger_type = context.eval_expression_tree(&inputs[0])?.to_string();
                     let table = context.eval_expression_tree(&inputs[1])?.to_string();
-                    let script_name = format!("{}_{}.rhai", table, trigger_type.to_lowercase());
+                    // The script registers itself to run on the trigger
+                    let script_name = context
+                        .source()
+                        .ok_or("ON can only be used in a script file")?
+                        .to_string();
 
                     // Determine the trigger kind based on the trigger type
                     let kind = match trigger_type.to_uppercase().as_str() {
                         "UPDATE" => TriggerKind::TableUpdate,
                         "INSERT" => TriggerKind::TableInsert,
                         "DELETE" => TriggerKind::TableDelete,
+                        // ON EMAIL OF "folder" watches a mail folder instead of a table
+                        "EMAIL" => TriggerKind::EmailReceived,
+                        // ON UPLOAD OF "folder" reacts to files stored in a drive folder
+                        "UPLOAD" => TriggerKind::FileUploaded,
                         _ => return Err(format!("Invalid trigger type: {}", trigger_type).into()),
                     };
 
-                    let binding = db.as_ref().unwrap();
-                    let fut = execute_on_trigger(binding, kind, &table, &script_name);
-
-                    let result = tokio::task::block_in_place(|| {
-                        tokio::runtime::Handle::current().block_on(fut)
-                    })
-                    .map_err(|e| format!("DB error: {}", e))?;
+                    let rows_affected = backend
+                        .add_trigger(kind, &table, &script_name)
+                        .map_err(|e| format!("DB error: {}", e))?;
 
-                    if let Some(rows_affected) = result.get("rows_affected") {
-                        Ok(Dynamic::from(rows_affected.as_i64().unwrap_or(0)))
-                    } else {
-                        Err("No rows affected".into())
-                    }
+                    Ok(Dynamic::from(rows_affected))
                 }
             },
         )
@@ -49,7 +53,7 @@ pub fn on_keyword(state: &AppState, engine: &mut Engine) {
 }
 
 pub async fn execute_on_trigger(
-    pool: &PgPool,
+    db: impl PgExecutor<'_>,
     kind: TriggerKind,
     table: &str,
     script_name: &str,
@@ -59,16 +63,20 @@ pub async fn execute_on_trigger(
         kind, table, script_name
     );
 
-    // Option 1: Use query_with macro if you need to pass enum values
-    let result = sqlx::query(
+    // Scripts run ON on every run; the trigger is only added the first time
+    let inserted = sqlx::query_scalar::<_, Uuid>(
         "INSERT INTO system_automations
-        (kind, target, script_name)
-        VALUES ($1, $2, $3)",
+        (id, kind, target, param)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (kind, (COALESCE(target, '')), (COALESCE(schedule, '')), param)
+        DO NOTHING
+        RETURNING id",
     )
+    .bind(Uuid::new_v4())
     .bind(kind.clone() as i32) // Assuming TriggerKind is #[repr(i32)]
     .bind(table)
     .bind(script_name)
-    .execute(pool)
+    .fetch_optional(db)
     .await
     .map_err(|e| {
         eprintln!("SQL execution error: {}", e);
@@ -80,6 +88,7 @@ pub async fn execute_on_trigger(
         "trigger_type": format!("{:?}", kind),
         "table": table,
         "script_name": script_name,
-        "rows_affected": result.rows_affected()
+        "id": inserted,
+        "rows_affected": inserted.map_or(0, |_| 1)
     }))
 }
@@ -1,18 +1,22 @@
 use rhai::Dynamic;
 use rhai::Engine;
 
-use crate::services::state::AppState;
+use crate::services::script_backend::ScriptBackend;
+use std::sync::Arc;
 
-pub fn print_keyword(_state: &AppState, engine: &mut Engine) {
+pub const PRINT_SYNTAX: &[&str] = &["PRINT", "$expr$"];
+
+pub fn print_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
 
     // PRINT command
     engine
         .register_custom_syntax(
-            &["PRINT", "$expr$"],
+            PRINT_SYNTAX,
             true, // Statement
-            |context, inputs| {
+            move |context, inputs| {
                 let value = context.eval_expression_tree(&inputs[0])?;
-                println!("{}", value);
+                backend.print(&value.to_string());
                 Ok(Dynamic::UNIT)
             },
         )
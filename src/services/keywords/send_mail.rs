@@ -0,0 +1,50 @@
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
+use crate::services::utils::dynamic_to_json;
+use rhai::{Dynamic, Engine, ImmutableString};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// `SEND MAIL to, subject, body` with an optional `, attachments`
+pub const SEND_MAIL_SYNTAX: &[&str] = &["SEND", "MAIL", "$expr$", ",", "$expr$", ",", "$expr$"];
+
+pub fn send_mail_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
+
+    // The attachments are optional, so the syntax is parsed symbol by symbol
+    engine.register_custom_syntax_with_state_raw(
+        SEND_MAIL_SYNTAX[0],
+        |symbols: &[ImmutableString], look_ahead: &str, _state: &mut Dynamic| {
+            let parsed = symbols.len();
+            if parsed < SEND_MAIL_SYNTAX.len() {
+                return Ok(Some(SEND_MAIL_SYNTAX[parsed].into()));
+            }
+            Ok(match parsed - SEND_MAIL_SYNTAX.len() {
+                0 if look_ahead == "," => Some(",".into()),
+                1 => Some("$expr$".into()),
+                _ => None,
+            })
+        },
+        false, // Expression returning the Message-ID
+        move |context, inputs, _state| {
+            let to = context.eval_expression_tree(&inputs[0])?.to_string();
+            let subject = context.eval_expression_tree(&inputs[1])?.to_string();
+            let body = context.eval_expression_tree(&inputs[2])?.to_string();
+            let attachments = match inputs.get(3) {
+                Some(input) => dynamic_to_json(&context.eval_expression_tree(input)?),
+                None => Value::Null,
+            };
+
+            // Every recipient counts against the script's email quota
+            for _ in to.split([',', ';']).filter(|to| !to.trim().is_empty()) {
+                charge(ScriptCall::Email)?;
+            }
+
+            let message_id = backend
+                .send_mail(&to, &subject, &body, attachments)
+                .map_err(|e| format!("SEND MAIL failed: {}", e))?;
+
+            Ok(Dynamic::from(message_id))
+        },
+    );
+}
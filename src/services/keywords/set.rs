@@ -3,39 +3,34 @@ use rhai::Engine;
 use serde_json::{json, Value};
 use sqlx::PgPool;
 use std::error::Error;
+use std::sync::Arc;
 
-use crate::services::state::AppState;
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
 use crate::services::utils;
 
-pub fn set_keyword(state: &AppState, engine: &mut Engine) {
-    let db = state.db_custom.clone();
+pub const SET_SYNTAX: &[&str] = &["SET", "$expr$", ",", "$expr$", ",", "$expr$"];
 
-    engine
-        .register_custom_syntax(&["SET", "$expr$", ",", "$expr$", ",", "$expr$"], false, {
-            let db = db.clone();
+pub fn set_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
 
+    engine
+        .register_custom_syntax(SET_SYNTAX, false, {
             move |context, inputs| {
+                charge(ScriptCall::Db)?;
                 let table_name = context.eval_expression_tree(&inputs[0])?;
                 let filter = context.eval_expression_tree(&inputs[1])?;
                 let updates = context.eval_expression_tree(&inputs[2])?;
-                let binding = db.as_ref().unwrap();
-
-                // Use the current async context instead of creating a new runtime
-                let binding2 = table_name.to_string();
-                let binding3 = filter.to_string();
-                let binding4 = updates.to_string();
-                let fut = execute_set(binding, &binding2, &binding3, &binding4);
-
-                // Use tokio::task::block_in_place + tokio::runtime::Handle::current().block_on
-                let result =
-                    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(fut))
-                        .map_err(|e| format!("DB error: {}", e))?;
-
-                if let Some(rows_affected) = result.get("rows_affected") {
-                    Ok(Dynamic::from(rows_affected.as_i64().unwrap_or(0)))
-                } else {
-                    Err("No rows affected".into())
-                }
+
+                let rows_affected = backend
+                    .set(
+                        &table_name.to_string(),
+                        &filter.to_string(),
+                        &updates.to_string(),
+                    )
+                    .map_err(|e| format!("DB error: {}", e))?;
+
+                Ok(Dynamic::from(rows_affected))
             }
         })
         .unwrap();
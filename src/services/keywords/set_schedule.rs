@@ -2,33 +2,36 @@ use rhai::Dynamic;
 use rhai::Engine;
 use serde_json::{json, Value};
 use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
 
 use crate::models::automation_model::TriggerKind;
-use crate::services::state::AppState;
+use crate::services::automation::AutomationService;
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
 
-pub fn set_schedule_keyword(state: &AppState, engine: &mut Engine) {
-    let db = state.db_custom.clone();
+pub const SET_SCHEDULE_SYNTAX: &[&str] = &["SET_SCHEDULE", "$string$"];
 
-    engine
-        .register_custom_syntax(["SET_SCHEDULE", "$string$"], true, {
-            let db = db.clone();
+pub fn set_schedule_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
 
+    engine
+        .register_custom_syntax(SET_SCHEDULE_SYNTAX, true, {
             move |context, inputs| {
+                charge(ScriptCall::Db)?;
                 let cron = context.eval_expression_tree(&inputs[0])?.to_string();
-                let script_name = format!("cron_{}.rhai", cron.replace(' ', "_"));
-
-                let binding = db.as_ref().unwrap();
-                let fut = execute_set_schedule(binding, &cron, &script_name);
+                AutomationService::validate_cron(&cron)?;
+                // The script registers itself to run on the schedule
+                let script_name = context
+                    .source()
+                    .ok_or("SET_SCHEDULE can only be used in a script file")?
+                    .to_string();
 
-                let result =
-                    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(fut))
-                        .map_err(|e| format!("DB error: {}", e))?;
+                let rows_affected = backend
+                    .add_schedule(&cron, &script_name)
+                    .map_err(|e| format!("DB error: {}", e))?;
 
-                if let Some(rows_affected) = result.get("rows_affected") {
-                    Ok(Dynamic::from(rows_affected.as_i64().unwrap_or(0)))
-                } else {
-                    Err("No rows affected".into())
-                }
+                Ok(Dynamic::from(rows_affected))
             }
         })
         .unwrap();
@@ -47,10 +50,13 @@ pub async fn execute_set_schedule(
     let result = sqlx::query(
         r#"
         INSERT INTO system_automations
-        (kind, schedule, script_name)
-        VALUES ($1, $2, $3)
+        (id, kind, schedule, param)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (kind, (COALESCE(target, '')), (COALESCE(schedule, '')), param)
+        DO NOTHING
         "#,
     )
+    .bind(Uuid::new_v4())
     .bind(TriggerKind::Scheduled as i32) // Cast to i32
     .bind(cron)
     .bind(script_name)
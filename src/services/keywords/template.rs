@@ -0,0 +1,29 @@
+use crate::services::script::{charge, ScriptCall};
+use crate::services::script_backend::ScriptBackend;
+use crate::services::utils::dynamic_to_json;
+use rhai::{Dynamic, Engine};
+use std::sync::Arc;
+
+pub const TEMPLATE_SYNTAX: &[&str] = &["TEMPLATE", "$expr$", ",", "$expr$"];
+
+pub fn template_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
+
+    engine
+        .register_custom_syntax(
+            TEMPLATE_SYNTAX,
+            false, // Expression returning the HTML
+            move |context, inputs| {
+                charge(ScriptCall::Db)?;
+                let name = context.eval_expression_tree(&inputs[0])?.to_string();
+                let data = dynamic_to_json(&context.eval_expression_tree(&inputs[1])?);
+
+                let html = backend
+                    .template(&name, data)
+                    .map_err(|e| format!("TEMPLATE {} failed: {}", name, e))?;
+
+                Ok(Dynamic::from(html))
+            },
+        )
+        .unwrap();
+}
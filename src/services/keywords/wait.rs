@@ -1,39 +1,49 @@
+use crate::services::script_backend::ScriptBackend;
 use rhai::{Dynamic, Engine};
-use crate::services::state::AppState;
-use std::thread;
+use std::sync::Arc;
 use std::time::Duration;
 
-pub fn wait_keyword(_state: &AppState, engine: &mut Engine) {
-    engine.register_custom_syntax(
-        &["WAIT", "$expr$"],
-        false, // Expression, not statement
-        move |context, inputs| {
-            let seconds = context.eval_expression_tree(&inputs[0])?;
-            
-            // Convert to number (handle both int and float)
-            let duration_secs = if seconds.is::<i64>() {
-                seconds.cast::<i64>() as f64
-            } else if seconds.is::<f64>() {
-                seconds.cast::<f64>()
-            } else {
-                return Err(format!("WAIT expects a number, got: {}", seconds).into());
-            };
-            
-            if duration_secs < 0.0 {
-                return Err("WAIT duration cannot be negative".into());
-            }
-            
-            // Cap maximum wait time to prevent abuse (e.g., 5 minutes max)
-            let capped_duration = if duration_secs > 300.0 { 300.0 } else { duration_secs };
-            
-            println!("WAIT {} seconds (thread sleep)", capped_duration);
-            
-            // Use thread::sleep to block only the current thread, not the entire server
-            let duration = Duration::from_secs_f64(capped_duration);
-            thread::sleep(duration);
-            
-            println!("WAIT completed after {} seconds", capped_duration);
-            Ok(Dynamic::from(format!("Waited {} seconds", capped_duration)))
-        }
-    ).unwrap();
-}
\ No newline at end of file
+pub const WAIT_SYNTAX: &[&str] = &["WAIT", "$expr$"];
+
+pub fn wait_keyword(backend: &Arc<dyn ScriptBackend>, engine: &mut Engine) {
+    let backend = backend.clone();
+
+    engine
+        .register_custom_syntax(
+            WAIT_SYNTAX,
+            false, // Expression, not statement
+            move |context, inputs| {
+                let seconds = context.eval_expression_tree(&inputs[0])?;
+
+                // Convert to number (handle both int and float)
+                let duration_secs = if seconds.is::<i64>() {
+                    seconds.cast::<i64>() as f64
+                } else if seconds.is::<f64>() {
+                    seconds.cast::<f64>()
+                } else {
+                    return Err(format!("WAIT expects a number, got: {}", seconds).into());
+                };
+
+                if duration_secs < 0.0 {
+                    return Err("WAIT duration cannot be negative".into());
+                }
+
+                // Cap maximum wait time to prevent abuse (e.g., 5 minutes max)
+                let capped_duration = if duration_secs > 300.0 {
+                    300.0
+                } else {
+                    duration_secs
+                };
+
+                log::debug!("WAIT {} seconds", capped_duration);
+
+                // Stops early with an error when the script's deadline comes first
+                let duration = Duration::from_secs_f64(capped_duration);
+                backend.wait(duration)?;
+
+                log::debug!("WAIT completed after {} seconds", capped_duration);
+                Ok(Dynamic::from(format!("Waited {} seconds", capped_duration)))
+            },
+        )
+        .unwrap();
+}
@@ -17,6 +17,7 @@ use langchain_rust::{
 };
 
 use crate::services::{ state::AppState, utils::azure_from_config};
+use crate::services::email_template::render_template;
 
 #[derive(serde::Deserialize)]
 struct ChatRequest {
@@ -33,7 +34,12 @@ struct ChatResponse {
 #[derive(serde::Serialize)]
 #[serde(tag = "type", content = "content")]
 enum ChatAction {
-    ReplyEmail { content: String },
+    ReplyEmail {
+        content: String,
+        /// The reply rendered with the `reply` email template
+        #[serde(skip_serializing_if = "Option::is_none")]
+        html: Option<String>,
+    },
     // Add other action variants here as needed
 }
 
@@ -86,8 +92,18 @@ pub async fn chat(
 
     // If in email view and the response looks like an email reply, add action
     if might_trigger_action && view_type == "email" {
+        let language = context.get("language").and_then(|v| v.as_str());
+        let data = serde_json::json!({ "reply_text": response_text });
+        let html = match render_template(&state, "reply", language, &data).await {
+            Ok(rendered) => Some(rendered.html),
+            Err(e) => {
+                log::warn!("Failed to render the email reply: {}", e);
+                None
+            }
+        };
         chat_response.action = Some(ChatAction::ReplyEmail {
             content: response_text,
+            html,
         });
     }
 
@@ -0,0 +1,441 @@
+use crate::models::outbox_model::{
+    OutboxEntry, OUTBOX_CANCELLED, OUTBOX_FAILED, OUTBOX_QUEUED, OUTBOX_SENDING, OUTBOX_SENT,
+};
+use crate::services::email::{deliver_email, parse_recipients, SendEmailRequest, SendError};
+use crate::services::state::AppState;
+use actix_web::error::{ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorNotFound};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{PgExecutor, PgPool};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+const OUTBOX_COLUMNS: &str = "id, message, recipient_domain, status, send_at, attempts, \
+                              last_error, message_id, created_at, sent_at, claimed_by, claimed_at";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+// Messages claimed per poll
+const BATCH_SIZE: i64 = 20;
+// How long a claimed message stays with its sender. Well above the time a
+// batch takes, so only messages of a server that stopped mid-send expire.
+const CLAIM_LEASE_SECS: f64 = 1800.0;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+// Retries wait 1, 2, 4... minutes, at most an hour
+const RETRY_BASE_SECS: i64 = 60;
+const RETRY_MAX_SECS: i64 = 3600;
+
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+
+/// Domain the rate limit of a message is counted against: that of its first
+/// recipient
+fn recipient_domain(request: &SendEmailRequest) -> String {
+    request
+        .to
+        .first()
+        .and_then(|address| address.rsplit_once('@'))
+        .map(|(_, domain)| domain.trim_end_matches('>').trim().to_lowercase())
+        .unwrap_or_default()
+}
+
+// Seconds until the next try of a message that failed `attempts` times
+fn retry_delay_secs(attempts: i32) -> i64 {
+    (RETRY_BASE_SECS << (attempts.max(1) - 1).min(16)).min(RETRY_MAX_SECS)
+}
+
+/// Queues `request` to be sent at `send_at`, or as soon as possible. The
+/// recipients should have been validated with `parse_recipients`.
+pub async fn enqueue_email(
+    db: impl PgExecutor<'_>,
+    request: &SendEmailRequest,
+    send_at: Option<DateTime<Utc>>,
+) -> Result<OutboxEntry, sqlx::Error> {
+    let message =
+        serde_json::to_value(request).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    sqlx::query_as::<_, OutboxEntry>(&format!(
+        r#"
+        INSERT INTO system_email_outbox (id, message, recipient_domain, send_at)
+        VALUES ($1, $2, $3, COALESCE($4, NOW()))
+        RETURNING {}
+        "#,
+        OUTBOX_COLUMNS
+    ))
+    .bind(Uuid::new_v4())
+    .bind(message)
+    .bind(recipient_domain(request))
+    .bind(send_at)
+    .fetch_one(db)
+    .await
+}
+
+/// Cancels a message that is still queued. Returns `None` when there is no
+/// such message or it is already being sent.
+pub async fn cancel_email(db: &PgPool, id: Uuid) -> Result<Option<OutboxEntry>, sqlx::Error> {
+    sqlx::query_as::<_, OutboxEntry>(&format!(
+        "UPDATE system_email_outbox SET status = $2 WHERE id = $1 AND status = $3 RETURNING {}",
+        OUTBOX_COLUMNS
+    ))
+    .bind(id)
+    .bind(OUTBOX_CANCELLED)
+    .bind(OUTBOX_QUEUED)
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn get_outbox_entry(db: &PgPool, id: Uuid) -> Result<Option<OutboxEntry>, sqlx::Error> {
+    sqlx::query_as::<_, OutboxEntry>(&format!(
+        "SELECT {} FROM system_email_outbox WHERE id = $1",
+        OUTBOX_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(db)
+    .await
+}
+
+/// Sends the outbox messages once they are due, retrying transient SMTP
+/// failures with backoff and spacing messages to the same domain
+pub struct OutboxSender {
+    state: AppState,
+    // Recorded on the messages this server claims
+    name: String,
+    // When messages were last sent, per recipient domain
+    sent: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl OutboxSender {
+    pub fn new(state: AppState) -> Self {
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "gbserver".to_string());
+        Self {
+            state,
+            name: format!("{}:{}", host, std::process::id()),
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+
+                // Messages whose sender stopped mid-send go out again; the
+                // SMTP server may have accepted them already
+                match self.requeue_expired().await {
+                    Ok(0) => {}
+                    Ok(count) => log::warn!("Requeued {} interrupted outbox messages", count),
+                    Err(e) => log::error!("Failed to requeue outbox messages: {}", e),
+                }
+
+                if let Err(e) = self.send_due().await {
+                    log::error!("Failed to send outbox messages: {}", e);
+                }
+            }
+        })
+    }
+
+    fn pool(&self) -> Result<&PgPool, sqlx::Error> {
+        self.state.db.as_ref().ok_or(sqlx::Error::PoolClosed)
+    }
+
+    // Only messages whose lease ran out: others are still being sent by the
+    // server that claimed them
+    async fn requeue_expired(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE system_email_outbox SET status = $1, claimed_by = NULL, claimed_at = NULL
+            WHERE status = $2 AND claimed_at < NOW() - make_interval(secs => $3)
+            "#,
+        )
+        .bind(OUTBOX_QUEUED)
+        .bind(OUTBOX_SENDING)
+        .bind(CLAIM_LEASE_SECS)
+        .execute(self.pool()?)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn send_due(&self) -> Result<(), sqlx::Error> {
+        // SKIP LOCKED keeps several servers from claiming the same messages
+        let due = sqlx::query_as::<_, OutboxEntry>(&format!(
+            r#"
+            UPDATE system_email_outbox SET status = $1, claimed_by = $4, claimed_at = NOW()
+            WHERE id IN (
+                SELECT id FROM system_email_outbox
+                WHERE status = $2 AND send_at <= NOW()
+                ORDER BY send_at
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING {}
+            "#,
+            OUTBOX_COLUMNS
+        ))
+        .bind(OUTBOX_SENDING)
+        .bind(OUTBOX_QUEUED)
+        .bind(BATCH_SIZE)
+        .bind(&self.name)
+        .fetch_all(self.pool()?)
+        .await?;
+
+        // A failing update leaves its message claimed until the lease runs
+        // out; the rest of the batch still goes out
+        for entry in due {
+            let id = entry.id;
+            let result = match self.take_slot(&entry.recipient_domain) {
+                Ok(()) => self.send(entry).await,
+                Err(wait) => self.defer(id, wait).await,
+            };
+            if let Err(e) = result {
+                log::error!("Failed to update outbox message {}: {}", id, e);
+            }
+        }
+        Ok(())
+    }
+
+    // Counts a message against its domain's rate, or returns how long until
+    // the domain has room again
+    fn take_slot(&self, domain: &str) -> Result<(), Duration> {
+        let rate = self
+            .state
+            .config
+            .as_ref()
+            .map_or(0, |config| config.email.outbox_domain_rate) as usize;
+        if rate == 0 {
+            return Ok(());
+        }
+
+        let mut sent = self.sent.lock().unwrap();
+        let times = sent.entry(domain.to_string()).or_default();
+        while times
+            .front()
+            .map_or(false, |time| time.elapsed() >= RATE_WINDOW)
+        {
+            times.pop_front();
+        }
+        if times.len() >= rate {
+            let oldest = times.front().copied().unwrap_or_else(Instant::now);
+            return Err(RATE_WINDOW.saturating_sub(oldest.elapsed()));
+        }
+        times.push_back(Instant::now());
+        Ok(())
+    }
+
+    async fn defer(&self, id: Uuid, wait: Duration) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE system_email_outbox
+            SET status = $2, send_at = NOW() + make_interval(secs => $3)
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(OUTBOX_QUEUED)
+        .bind(wait.as_secs_f64().max(1.0))
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn send(&self, entry: OutboxEntry) -> Result<(), sqlx::Error> {
+        let attempts = entry.attempts + 1;
+        let result = match serde_json::from_value::<SendEmailRequest>(entry.message) {
+            Ok(request) => deliver_email(&self.state, &request, Vec::new()).await,
+            Err(e) => Err(SendError::Message(format!("Invalid outbox message: {}", e))),
+        };
+
+        match result {
+            Ok(message_id) => {
+                sqlx::query(
+                    r#"
+                    UPDATE system_email_outbox
+                    SET status = $2, attempts = $3, message_id = $4, last_error = NULL,
+                        sent_at = NOW()
+                    WHERE id = $1
+                    "#,
+                )
+                .bind(entry.id)
+                .bind(OUTBOX_SENT)
+                .bind(attempts)
+                .bind(message_id)
+                .execute(self.pool()?)
+                .await?;
+            }
+            Err(e) => {
+                let max_attempts = self
+                    .state
+                    .config
+                    .as_ref()
+                    .map_or(1, |config| config.email.outbox_max_attempts)
+                    as i32;
+                let retry = e.is_transient() && attempts < max_attempts;
+                if retry {
+                    log::warn!("Outbox message {} failed, retrying: {}", entry.id, e);
+                } else {
+                    log::error!("Outbox message {} failed: {}", entry.id, e);
+                }
+
+                sqlx::query(
+                    r#"
+                    UPDATE system_email_outbox
+                    SET status = $2, attempts = $3, last_error = $4,
+                        send_at = NOW() + make_interval(secs => $5)
+                    WHERE id = $1
+                    "#,
+                )
+                .bind(entry.id)
+                .bind(if retry { OUTBOX_QUEUED } else { OUTBOX_FAILED })
+                .bind(attempts)
+                .bind(e.to_string())
+                .bind(if retry {
+                    retry_delay_secs(attempts) as f64
+                } else {
+                    0.0
+                })
+                .execute(self.pool()?)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn database(state: &AppState) -> Result<&PgPool, actix_web::Error> {
+    state
+        .db
+        .as_ref()
+        .ok_or_else(|| ErrorInternalServerError("Database not available"))
+}
+
+/// The `/emails/send` body with the time to send it at; without `send_at` the
+/// message goes out with the next poll
+#[derive(Debug, Deserialize)]
+pub struct ScheduleEmailRequest {
+    #[serde(flatten)]
+    pub message: SendEmailRequest,
+    pub send_at: Option<DateTime<Utc>>,
+}
+
+#[actix_web::post("/comm/email/schedule")]
+pub async fn schedule_email(
+    state: web::Data<AppState>,
+    request: web::Json<ScheduleEmailRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Invalid recipients are reported now rather than when sending
+    parse_recipients(&request.message).map_err(|e| ErrorBadRequest(e.to_string()))?;
+
+    let entry = enqueue_email(database(&state)?, &request.message, request.send_at)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Failed to queue email: {}", e)))?;
+
+    Ok(HttpResponse::Accepted().json(entry))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelEmailRequest {
+    pub id: Uuid,
+}
+
+/// Cancels a queued message; one already sent or being sent can't be
+#[actix_web::post("/comm/email/cancel")]
+pub async fn cancel_scheduled_email(
+    state: web::Data<AppState>,
+    request: web::Json<CancelEmailRequest>,
+) -> Result<web::Json<OutboxEntry>, actix_web::Error> {
+    let db = database(&state)?;
+    let failed = |e: sqlx::Error| ErrorInternalServerError(format!("Failed to cancel: {}", e));
+
+    if let Some(entry) = cancel_email(db, request.id).await.map_err(failed)? {
+        return Ok(web::Json(entry));
+    }
+    match get_outbox_entry(db, request.id).await.map_err(failed)? {
+        Some(entry) => Err(ErrorConflict(format!("Email is already {}", entry.status))),
+        None => Err(ErrorNotFound("Email not found")),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OutboxQuery {
+    pub status: Option<String>,
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+}
+
+/// A page of the outbox, latest send time first, optionally of one status; the number
+/// of matching messages is returned in `X-Total-Count`
+#[actix_web::get("/emails/outbox")]
+pub async fn list_outbox(
+    state: web::Data<AppState>,
+    query: web::Query<OutboxQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let db = database(&state)?;
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let failed = |e: sqlx::Error| ErrorInternalServerError(format!("Failed to list: {}", e));
+
+    let total: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM system_email_outbox WHERE $1::text IS NULL OR status = $1",
+    )
+    .bind(&query.status)
+    .fetch_one(db)
+    .await
+    .map_err(failed)?;
+
+    let entries = sqlx::query_as::<_, OutboxEntry>(&format!(
+        r#"
+        SELECT {} FROM system_email_outbox
+        WHERE $1::text IS NULL OR status = $1
+        ORDER BY send_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        OUTBOX_COLUMNS
+    ))
+    .bind(&query.status)
+    .bind(page_size as i64)
+    .bind((i64::from(page) - 1).saturating_mul(i64::from(page_size)))
+    .fetch_all(db)
+    .await
+    .map_err(failed)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Total-Count", total.to_string()))
+        .json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(to: &str) -> SendEmailRequest {
+        serde_json::from_value(serde_json::json!({ "to": to })).unwrap()
+    }
+
+    #[test]
+    fn recipient_domain_is_that_of_the_first_recipient() {
+        assert_eq!(
+            recipient_domain(&request("Ana <ana@Example.COM>, bo@other.org")),
+            "example.com"
+        );
+        assert_eq!(
+            recipient_domain(&request("bo@mail.other.org")),
+            "mail.other.org"
+        );
+        assert_eq!(recipient_domain(&request("nobody")), "");
+    }
+
+    #[test]
+    fn retries_back_off_up_to_an_hour() {
+        assert_eq!(retry_delay_secs(1), 60);
+        assert_eq!(retry_delay_secs(2), 120);
+        assert_eq!(retry_delay_secs(4), 480);
+        assert_eq!(retry_delay_secs(7), RETRY_MAX_SECS);
+        assert_eq!(retry_delay_secs(100), RETRY_MAX_SECS);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::services::config::ScriptLimits;
+use crate::services::keywords::call::call_keyword;
 use crate::services::keywords::create_draft::create_draft_keyword;
 use crate::services::keywords::create_site::create_site_keyword;
 use crate::services::keywords::find::find_keyword;
@@ -8,140 +10,488 @@ use crate::services::keywords::get_website::get_website_keyword;
 use crate::services::keywords::llm_keyword::llm_keyword;
 use crate::services::keywords::on::on_keyword;
 use crate::services::keywords::print::print_keyword;
+use crate::services::keywords::send_mail::send_mail_keyword;
 use crate::services::keywords::set::set_keyword;
 use crate::services::keywords::set_schedule::set_schedule_keyword;
+use crate::services::keywords::template::template_keyword;
 use crate::services::keywords::wait::wait_keyword;
+use crate::services::script_backend::{LiveBackend, ScriptBackend};
+use crate::services::script_debug::ScriptDebugger;
+use crate::services::script_pool::block_on;
+use crate::services::script_syntax::{
+    is_keyword_like, statements, tokenize_line, Statement, Token, TokenKind,
+};
 use crate::services::state::AppState;
-use rhai::{Dynamic, Engine, EvalAltResult};
+use rhai::{Dynamic, Engine, EvalAltResult, LexError, ParseErrorType, Position, Scope};
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Keyword calls that count against a run's limits
+#[derive(Debug, Clone, Copy)]
+pub enum ScriptCall {
+    Llm,
+    Http,
+    Db,
+    Email,
+}
+
+// What is left of the limits of one run
+struct RunBudget {
+    deadline: Instant,
+    llm_calls: u32,
+    http_calls: u32,
+    db_calls: u32,
+    emails: u32,
+}
+
+thread_local! {
+    // Budget of the script running on this thread. The engine is shared by
+    // all runs, so per-run limits can't be captured by its callbacks.
+    static BUDGET: RefCell<Option<RunBudget>> = RefCell::new(None);
+}
+
+/// Counts a keyword call against the running script's budget, failing the
+/// script once the limit for that kind of call is used up
+pub fn charge(call: ScriptCall) -> Result<(), Box<EvalAltResult>> {
+    BUDGET.with(|budget| {
+        let mut budget = budget.borrow_mut();
+        let budget = match budget.as_mut() {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+
+        let remaining = match call {
+            ScriptCall::Llm => &mut budget.llm_calls,
+            ScriptCall::Http => &mut budget.http_calls,
+            ScriptCall::Db => &mut budget.db_calls,
+            ScriptCall::Email => &mut budget.emails,
+        };
+        if *remaining == 0 {
+            return Err(format!("Script exceeded its {:?} call limit", call).into());
+        }
+        *remaining -= 1;
+        Ok(())
+    })
+}
+
+/// Time left before the deadline of the script running on this thread
+pub fn time_left() -> Option<Duration> {
+    BUDGET.with(|budget| {
+        budget
+            .borrow()
+            .as_ref()
+            .map(|budget| budget.deadline.saturating_duration_since(Instant::now()))
+    })
+}
+
+/// Sleeps for `duration` without outliving the running script's deadline
+pub fn sleep_within_budget(duration: Duration) -> Result<(), Box<EvalAltResult>> {
+    let sleep_for = time_left().map_or(duration, |remaining| remaining.min(duration));
+
+    block_on(tokio::time::sleep(sleep_for))?;
+
+    if sleep_for < duration {
+        return Err("Script timed out during WAIT".into());
+    }
+    Ok(())
+}
+
+/// Line of the .bas source each line of the preprocessed script came from
+#[derive(Debug, Clone, Default)]
+pub struct LineMap(Vec<usize>);
+
+impl LineMap {
+    /// .bas line (1-based) of a line (1-based) of the compiled script
+    pub fn bas_line(&self, line: usize) -> usize {
+        line.checked_sub(1)
+            .and_then(|index| self.0.get(index))
+            .copied()
+            .unwrap_or(line)
+    }
+}
+
+// Position in the .bas source; columns are those of the compiled line
+fn bas_position(line: usize, column: usize) -> Position {
+    let line = u16::try_from(line).unwrap_or(u16::MAX).max(1);
+    Position::new(line, u16::try_from(column).unwrap_or(0))
+}
+
+/// A .bas construct the preprocessor can't translate
+#[derive(Debug, Clone)]
+pub struct PreprocessError {
+    /// .bas line (1-based) the error was found on
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (line {})", self.message, self.line)
+    }
+}
 
 pub struct ScriptService {
     engine: Engine,
+    limits: ScriptLimits,
 }
 
 impl ScriptService {
-    pub fn new(state: &AppState) -> Self {
+    /// Engine whose keywords use the live services, for the scripts of
+    /// `scripts_dir`
+    pub fn new(state: &AppState, scripts_dir: &str) -> Self {
+        let limits = state
+            .config
+            .as_ref()
+            .map(|config| config.script_limits.clone())
+            .unwrap_or_default();
+        Self::with_backend(
+            Arc::new(LiveBackend::new(state.clone(), scripts_dir)),
+            limits,
+        )
+    }
+
+    /// Engine whose keywords go through `backend` instead of the live services
+    pub fn with_backend(backend: Arc<dyn ScriptBackend>, limits: ScriptLimits) -> Self {
         let mut engine = Engine::new();
 
         // Configure engine for BASIC-like syntax
         engine.set_allow_anonymous_fn(true);
         engine.set_allow_looping(true);
 
-        create_draft_keyword(state, &mut engine);
-        create_site_keyword(state, &mut engine);
-        find_keyword(state, &mut engine);
-        for_keyword(state, &mut engine);
+        engine.set_max_call_levels(limits.max_call_depth);
+        engine.set_max_string_size(limits.max_string_size);
+        engine.set_max_array_size(limits.max_array_size);
+        engine.set_max_map_size(limits.max_array_size);
+
+        call_keyword(&backend, &mut engine);
+        create_draft_keyword(&backend, &mut engine);
+        create_site_keyword(&backend, &mut engine);
+        find_keyword(&backend, &mut engine);
+        for_keyword(&mut engine);
         first_keyword(&mut engine);
-        llm_keyword(state, &mut engine);
-        get_website_keyword(state, &mut engine);
-        get_keyword(state, &mut engine);
-        set_keyword(state, &mut engine);
-        wait_keyword(state, &mut engine);
-        print_keyword(state, &mut engine);
-        on_keyword(state, &mut engine);
-        set_schedule_keyword(state, &mut engine);
+        llm_keyword(&backend, &mut engine);
+        get_website_keyword(&backend, &mut engine);
+        get_keyword(&backend, &mut engine);
+        set_keyword(&backend, &mut engine);
+        wait_keyword(&backend, &mut engine);
+        print_keyword(&backend, &mut engine);
+        send_mail_keyword(&backend, &mut engine);
+        template_keyword(&backend, &mut engine);
+        on_keyword(&backend, &mut engine);
+        set_schedule_keyword(&backend, &mut engine);
+
+        // Checked between operations, so a keyword already waiting on I/O
+        // finishes before the script is aborted
+        let max_operations = limits.max_operations;
+        engine.on_progress(move |operations| {
+            if operations > max_operations {
+                return Some(Dynamic::from(format!(
+                    "Script exceeded {} operations",
+                    max_operations
+                )));
+            }
+            BUDGET.with(|budget| match budget.borrow().as_ref() {
+                Some(budget) if Instant::now() > budget.deadline => {
+                    Some(Dynamic::from("Script timed out"))
+                }
+                _ => None,
+            })
+        });
 
-        ScriptService { engine }
+        ScriptService { engine, limits }
     }
 
-    fn preprocess_basic_script(&self, script: &str) -> String {
+    /// Translates the BASIC dialect into Rhai code, keeping track of which
+    /// .bas line every line of the result came from. Statements are read as
+    /// tokens, so comments and keywords inside strings are never translated.
+    pub fn preprocess_basic_script(
+        &self,
+        script: &str,
+    ) -> Result<(String, LineMap), PreprocessError> {
         let mut result = String::new();
-        let mut for_stack: Vec<usize> = Vec::new();
+        // Indent and .bas line of every open FOR EACH
+        let mut for_stack: Vec<(usize, usize)> = Vec::new();
         let mut current_indent = 0;
+        let mut lines = Vec::new();
+        let mut mapped = 0;
 
-        for line in script.lines() {
-            let trimmed = line.trim();
+        for statement in statements(script) {
+            let code = Self::verbatim_strings(statement.code.trim());
+            let tokens = tokenize_line(&code);
+            let is_word = |index: usize, word: &str| {
+                tokens.get(index).map_or(false, |token| token.is_word(word))
+            };
 
-            // Skip empty lines and comments
-            if trimmed.is_empty() || trimmed.starts_with("//") || trimmed.starts_with("REM") {
-                result.push_str(line);
+            if tokens.is_empty() {
+                // Empty lines and comments
                 result.push('\n');
-                continue;
-            }
-
-            // Handle FOR EACH start
-            if trimmed.starts_with("FOR EACH") {
-                for_stack.push(current_indent);
+            } else if is_word(0, "FOR") && is_word(1, "EACH") {
+                // Handle FOR EACH start
+                for_stack.push((current_indent, statement.line));
                 result.push_str(&" ".repeat(current_indent));
-                result.push_str(trimmed);
+                result.push_str(&code);
                 result.push_str("{\n");
                 current_indent += 4;
                 result.push_str(&" ".repeat(current_indent));
                 result.push('\n');
-                continue;
-            }
-
-            // Handle NEXT
-            if trimmed.starts_with("NEXT") {
-                if let Some(expected_indent) = for_stack.pop() {
-                    if (current_indent - 4) != expected_indent {
-                        panic!("NEXT without matching FOR EACH");
-                    }
-                    current_indent = current_indent - 4;
-                    result.push_str(&" ".repeat(current_indent));
-                    result.push_str("}\n");
-                    result.push_str(&" ".repeat(current_indent));
-                    result.push_str(trimmed);
+            } else if is_word(0, "NEXT") {
+                // Handle NEXT
+                if for_stack.pop().is_none() {
+                    return Err(PreprocessError {
+                        line: statement.line,
+                        message: "NEXT without matching FOR EACH".to_string(),
+                    });
+                }
+                current_indent -= 4;
+                result.push_str(&" ".repeat(current_indent));
+                result.push_str("}\n");
+                result.push_str(&" ".repeat(current_indent));
+                result.push_str(&code);
+                result.push_str(";\n");
+            } else if is_word(0, "PARAM") {
+                // Handle PARAM declarations - values are pushed into the scope by the caller
+                result.push_str(&" ".repeat(current_indent));
+                result.push_str(&Self::param_declaration(&code, &tokens));
+                result.push('\n');
+            } else if is_word(0, "RETURN") {
+                // Handle RETURN - the value becomes the script's result
+                result.push_str(&" ".repeat(current_indent));
+                result.push_str("return");
+                result.push_str(&code["RETURN".len()..]);
+                if !code.ends_with('{') && !code.ends_with(';') {
                     result.push(';');
-                    result.push('\n');
-                    continue;
-                } else {
-                    panic!("NEXT without matching FOR EACH");
                 }
-            }
-
-            // Handle EXIT FOR
-            if trimmed == "EXIT FOR" {
+                result.push('\n');
+            } else if is_word(0, "EXIT") && is_word(1, "FOR") {
+                // Handle EXIT FOR
                 result.push_str(&" ".repeat(current_indent));
-                result.push_str(trimmed);
+                result.push_str(&code);
+                result.push('\n');
+            } else {
+                // Handle regular statements
+                result.push_str(&" ".repeat(current_indent));
+                result.push_str(&code);
+                if Self::needs_semicolon(&tokens) {
+                    result.push(';');
+                }
                 result.push('\n');
-                continue;
             }
 
-            // Handle regular lines - no semicolons added for BASIC-style commands
-            result.push_str(&" ".repeat(current_indent));
+            Self::map_statement(&result, &mut mapped, &mut lines, &statement);
+        }
 
-            let basic_commands = [
-                "SET", "CREATE", "PRINT", "FOR", "FIND", "GET", "EXIT", "IF", "THEN", "ELSE",
-                "END IF", "WHILE", "WEND", "DO", "LOOP",
-            ];
+        if let Some(&(_, line)) = for_stack.last() {
+            return Err(PreprocessError {
+                line,
+                message: "Unclosed FOR EACH loop".to_string(),
+            });
+        }
 
-            let is_basic_command = basic_commands.iter().any(|&cmd| trimmed.starts_with(cmd));
-            let is_control_flow = trimmed.starts_with("IF")
-                || trimmed.starts_with("ELSE")
-                || trimmed.starts_with("END IF");
+        Ok((result, LineMap(lines)))
+    }
 
-            if is_basic_command || !for_stack.is_empty() || is_control_flow {
-                // Don'ta add semicolons for BASIC-style commands or inside blocks
-                result.push_str(trimmed);
-                result.push(';');
-            } else {
-                // Add semicolons only for BASIC statements
-                result.push_str(trimmed);
-                if !trimmed.ends_with(';') && !trimmed.ends_with('{') && !trimmed.ends_with('}') {
-                    result.push(';');
+    // Attributes the lines emitted since `mapped` to the lines of `statement`;
+    // lines the translation adds belong to its last line
+    fn map_statement(
+        result: &str,
+        mapped: &mut usize,
+        lines: &mut Vec<usize>,
+        statement: &Statement,
+    ) {
+        let emitted = result[*mapped..].matches('\n').count();
+        lines.extend((0..emitted).map(|i| statement.line + i.min(statement.lines - 1)));
+        *mapped = result.len();
+    }
+
+    // BASIC keywords always end with a `;`. Other statements do unless they
+    // already end one or open or close a block; a map literal at the end
+    // still needs one.
+    fn needs_semicolon(tokens: &[Token]) -> bool {
+        let (first, last) = match (tokens.first(), tokens.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return false,
+        };
+        if first.kind == TokenKind::Word && is_keyword_like(first.text) {
+            return true;
+        }
+        if last.kind != TokenKind::Symbol {
+            return true;
+        }
+        match last.text {
+            ";" | "{" => false,
+            "}" => {
+                // What the last `}` closes
+                let mut open = Vec::new();
+                let mut closed = None;
+                for token in tokens
+                    .iter()
+                    .filter(|token| token.kind == TokenKind::Symbol)
+                {
+                    match token.text {
+                        "(" | "[" | "{" | "#{" => open.push(token.text),
+                        ")" | "]" | "}" => closed = open.pop(),
+                        _ => {}
+                    }
                 }
+                closed == Some("#{")
             }
-            result.push('\n');
+            _ => true,
         }
+    }
 
-        if !for_stack.is_empty() {
-            panic!("Unclosed FOR EACH loop");
+    // Rhai's `"` strings can't hold line breaks, so a `"` string spanning
+    // lines becomes a verbatim backtick string with the same text
+    fn verbatim_strings(code: &str) -> String {
+        let mut result = String::new();
+        let mut copied = 0;
+
+        for token in tokenize_line(code) {
+            if token.kind != TokenKind::Str
+                || !token.text.starts_with('"')
+                || !token.text.contains('\n')
+            {
+                continue;
+            }
+            result.push_str(&code[copied..token.start]);
+            result.push('`');
+            let text = &token.text[1..];
+            let mut chars = text.strip_suffix('"').unwrap_or(text).chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => match chars.next() {
+                        Some('n') => result.push('\n'),
+                        Some('t') => result.push('\t'),
+                        Some('r') => result.push('\r'),
+                        Some(escaped) => result.push(escaped),
+                        None => {}
+                    },
+                    '`' => result.push_str("``"),
+                    c => result.push(c),
+                }
+            }
+            result.push('`');
+            copied = token.end();
         }
 
+        result.push_str(&code[copied..]);
         result
     }
 
+    /// Turns `PARAM name AS TYPE [DEFAULT value] [OPTIONAL]` into a binding that
+    /// keeps the caller-supplied value or falls back to the default
+    fn param_declaration(code: &str, tokens: &[Token]) -> String {
+        let name = tokens.get(1).map_or("", |token| token.text);
+        let is_word = |token: &Token, word: &str| {
+            token.kind == TokenKind::Word && token.text.eq_ignore_ascii_case(word)
+        };
+        let default = tokens.iter().position(|token| is_word(token, "DEFAULT"));
+        let optional = tokens
+            .last()
+            .map_or(false, |token| is_word(token, "OPTIONAL"));
+
+        let fallback = if let Some(index) = default {
+            // The value runs up to a trailing OPTIONAL
+            let end = match tokens.last() {
+                Some(last) if optional && tokens.len() > index + 2 => last.start,
+                _ => code.len(),
+            };
+            match code[tokens[index].end()..end].trim() {
+                "TRUE" => "true".to_string(),
+                "FALSE" => "false".to_string(),
+                value => value.to_string(),
+            }
+        } else if optional {
+            "()".to_string()
+        } else {
+            format!("throw \"Missing PARAM {}\"", name)
+        };
+
+        format!(
+            "let {name} = if is_def_var(\"{name}\") {{ {name} }} else {{ {fallback} }};",
+            name = name,
+            fallback = fallback
+        )
+    }
+
     /// Preprocesses BASIC-style script to handle semicolon-free syntax
     pub fn compile(&self, script: &str) -> Result<rhai::AST, Box<EvalAltResult>> {
-        let processed_script = self.preprocess_basic_script(script);
-        println!("Processed Script:\n{}", processed_script);
+        self.compile_with_line_map(script).map(|(ast, _)| ast)
+    }
+
+    /// Compiles the script, also returning where each compiled line came from
+    /// in the .bas source. Errors are positioned on .bas lines.
+    pub fn compile_with_line_map(
+        &self,
+        script: &str,
+    ) -> Result<(rhai::AST, LineMap), Box<EvalAltResult>> {
+        let (processed_script, line_map) = self.preprocess_basic_script(script).map_err(|e| {
+            Box::new(EvalAltResult::ErrorParsing(
+                ParseErrorType::BadInput(LexError::Runtime(e.message)),
+                bas_position(e.line, 0),
+            ))
+        })?;
+        log::debug!("Processed Script:\n{}", processed_script);
         match self.engine.compile(&processed_script) {
-            Ok(ast) => Ok(ast),
-            Err(parse_error) => Err(Box::new(EvalAltResult::from(parse_error))),
+            Ok(ast) => Ok((ast, line_map)),
+            Err(parse_error) => {
+                let mut error = EvalAltResult::from(parse_error);
+                let pos = error.position();
+                if let Some(line) = pos.line() {
+                    error.set_position(bas_position(
+                        line_map.bas_line(line),
+                        pos.position().unwrap_or(0),
+                    ));
+                }
+                Err(Box::new(error))
+            }
         }
     }
 
+    /// Hands every step of the runs of this engine to `debugger`, which may
+    /// pause the script until its client says to go on
+    pub fn enable_debugging(&mut self, debugger: ScriptDebugger) {
+        // Rhai marks its debugging interface as volatile with #[deprecated]
+        #[allow(deprecated)]
+        self.engine.register_debugger(
+            |_, dbg| dbg,
+            move |context, event, _node, _source, pos| debugger.on_event(context, event, pos),
+        );
+    }
+
     pub fn run(&self, ast: &rhai::AST) -> Result<Dynamic, Box<EvalAltResult>> {
         self.engine.eval_ast(ast)
     }
+
+    /// Runs the script with pre-populated variables (e.g. PARAM values)
+    pub fn run_with_scope(
+        &self,
+        ast: &rhai::AST,
+        scope: &mut Scope,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        self.engine.eval_ast_with_scope(scope, ast)
+    }
+
+    /// Runs the script with pre-populated variables inside the sandbox limits,
+    /// aborting it after `timeout`
+    pub fn run_with_timeout(
+        &self,
+        ast: &rhai::AST,
+        scope: &mut Scope,
+        timeout: Duration,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        BUDGET.with(|budget| {
+            *budget.borrow_mut() = Some(RunBudget {
+                deadline: Instant::now() + timeout,
+                llm_calls: self.limits.max_llm_calls,
+                http_calls: self.limits.max_http_calls,
+                db_calls: self.limits.max_db_calls,
+                emails: self.limits.max_emails,
+            })
+        });
+        let result = self.engine.eval_ast_with_scope(scope, ast);
+        BUDGET.with(|budget| *budget.borrow_mut() = None);
+        result
+    }
 }
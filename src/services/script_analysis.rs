@@ -0,0 +1,300 @@
+use crate::services::keywords::{KeywordInfo, KEYWORDS};
+use crate::services::script::ScriptService;
+use crate::services::script_syntax::{
+    is_keyword_like, tokenize_line, tokenize_lines, Token, TokenKind, LINE_CONTINUATION,
+};
+use std::collections::HashSet;
+
+/// Statement words the preprocessor handles itself, with their docs
+pub const STATEMENTS: &[(&str, &str)] = &[
+    (
+        "PARAM",
+        "`PARAM name AS TYPE [DEFAULT value] [OPTIONAL]` declares an input of the script. \
+         Without DEFAULT or OPTIONAL the caller must supply it.",
+    ),
+    (
+        "RETURN",
+        "`RETURN value` ends the script with `value` as its result.",
+    ),
+    (
+        "REM",
+        "`REM text` is a comment, as are lines starting with `'`, `//` or `#`.",
+    ),
+    ("NEXT", "`NEXT name` closes the FOR EACH loop over `name`."),
+];
+
+/// Words that only appear as part of a keyword or statement
+pub const CONNECTIVES: &[&str] = &[
+    "AS", "DEFAULT", "OPTIONAL", "AND", "OR", "NOT", "TO", "STEP", "IN", "OF", "EACH",
+];
+
+/// Rhai keywords and literals that can appear in scripts without parentheses
+pub const RHAI_WORDS: &[&str] = &[
+    "let", "const", "if", "else", "switch", "do", "while", "until", "loop", "for", "in",
+    "continue", "break", "return", "throw", "try", "catch", "fn", "private", "import", "export",
+    "as", "true", "false", "this", "global", "is",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a script. Lines are 1-based, columns are byte offsets.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Param,
+    Variable,
+    Function,
+}
+
+/// A name defined by the script
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    /// The defining line, trimmed
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    pub symbols: Vec<Symbol>,
+}
+
+impl Analysis {
+    /// The first definition of `name`
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+}
+
+/// The registered keyword whose words include `word`
+pub fn keyword_info(word: &str) -> Option<&'static KeywordInfo> {
+    KEYWORDS.iter().find(|keyword| {
+        keyword
+            .syntax
+            .iter()
+            .take_while(|segment| !segment.starts_with('$'))
+            .any(|segment| *segment == word)
+    })
+}
+
+pub fn statement_doc(word: &str) -> Option<&'static str> {
+    STATEMENTS
+        .iter()
+        .find(|(statement, _)| *statement == word)
+        .map(|(_, doc)| *doc)
+}
+
+/// The word under byte offset `column` of `line`
+pub fn word_at(line: &str, column: usize) -> Option<Token<'_>> {
+    tokenize_line(line)
+        .into_iter()
+        .find(|token| token.start <= column && column <= token.end())
+        .filter(|token| token.kind == TokenKind::Word || token.kind == TokenKind::Str)
+}
+
+/// Checks a script the way `ScriptService` would compile it, and collects the
+/// names it defines
+pub fn analyze(script_service: &ScriptService, text: &str) -> Analysis {
+    let mut analysis = collect_symbols(text);
+    let lines: Vec<&str> = text.lines().collect();
+    let tokens = tokenize_lines(text);
+
+    check_keywords_and_variables(&tokens, &mut analysis);
+    check_for_next(&tokens, &mut analysis);
+
+    // Anything the preprocessor or the engine rejects, on its .bas line
+    if let Err(e) = script_service.compile(text) {
+        let line = e.position().line().unwrap_or(1);
+        let end = lines.get(line - 1).map_or(0, |text| text.len());
+        analysis.diagnostics.push(Diagnostic {
+            line,
+            start: 0,
+            end,
+            severity: Severity::Error,
+            message: e.to_string(),
+        });
+    }
+
+    analysis
+}
+
+/// The names a script defines, without checking it
+pub fn symbols(text: &str) -> Vec<Symbol> {
+    collect_symbols(text).symbols
+}
+
+// Definitions of a script
+fn collect_symbols(text: &str) -> Analysis {
+    let mut analysis = Analysis::default();
+    for (index, (line, tokens)) in text.lines().zip(tokenize_lines(text)).enumerate() {
+        let tokens = code_tokens(&tokens);
+        let line_number = index + 1;
+        let mut define = |token: &Token, kind: SymbolKind| {
+            analysis.symbols.push(Symbol {
+                name: token.text.to_string(),
+                kind,
+                line: line_number,
+                start: token.start,
+                end: token.end(),
+                detail: line.trim().to_string(),
+            })
+        };
+
+        match tokens.as_slice() {
+            [first, name, ..] if first.is_word("PARAM") && name.kind == TokenKind::Word => {
+                define(name, SymbolKind::Param)
+            }
+            [first, each, name, ..]
+                if first.is_word("FOR") && each.is_word("EACH") && name.kind == TokenKind::Word =>
+            {
+                define(name, SymbolKind::Variable)
+            }
+            // Scripts are Rhai underneath, so functions are Rhai `fn`s
+            [first, name, rest @ ..] if first.is_word("fn") && name.kind == TokenKind::Word => {
+                define(name, SymbolKind::Function);
+                // The arguments are variables of the body
+                for arg in rest.iter().filter(|token| token.kind == TokenKind::Word) {
+                    if !is_keyword_like(arg.text) {
+                        define(arg, SymbolKind::Variable);
+                    }
+                }
+            }
+            [first, name, ..]
+                if (first.is_word("let") || first.is_word("const"))
+                    && name.kind == TokenKind::Word =>
+            {
+                define(name, SymbolKind::Variable)
+            }
+            [name, eq, ..]
+                if name.kind == TokenKind::Word
+                    && eq.is_symbol("=")
+                    && !is_keyword_like(name.text) =>
+            {
+                define(name, SymbolKind::Variable)
+            }
+            _ => {}
+        }
+    }
+
+    analysis
+}
+
+fn check_keywords_and_variables(lines: &[Vec<Token>], analysis: &mut Analysis) {
+    let defined: HashSet<&str> = analysis
+        .symbols
+        .iter()
+        .map(|symbol| symbol.name.as_str())
+        .collect();
+    let mut diagnostics = Vec::new();
+
+    for (index, tokens) in lines.iter().enumerate() {
+        let tokens = code_tokens(tokens);
+        let mut previous: Option<&Token> = None;
+
+        for (i, token) in tokens.iter().enumerate() {
+            let next = tokens.get(i + 1);
+            let statement_start = previous.map_or(true, |previous| previous.is_symbol("="));
+            // Properties and PARAM types aren't variables
+            let after_dot_or_as = previous.map_or(false, |previous| {
+                previous.is_symbol(".") || previous.is_word("AS")
+            });
+            previous = Some(token);
+
+            if token.kind != TokenKind::Word
+                || token.text == LINE_CONTINUATION
+                || after_dot_or_as
+                || next.map_or(false, |next| next.is_symbol("(") || next.is_symbol(":"))
+            {
+                continue;
+            }
+            let word = token.text;
+
+            if is_keyword_like(word) {
+                let known = keyword_info(word).is_some()
+                    || statement_doc(word).is_some()
+                    || CONNECTIVES.contains(&word)
+                    || defined.contains(word);
+                if statement_start && !known {
+                    diagnostics.push(Diagnostic {
+                        line: index + 1,
+                        start: token.start,
+                        end: token.end(),
+                        severity: Severity::Error,
+                        message: format!("Unknown keyword {}", word),
+                    });
+                }
+                continue;
+            }
+
+            if !defined.contains(word) && !RHAI_WORDS.contains(&word) {
+                diagnostics.push(Diagnostic {
+                    line: index + 1,
+                    start: token.start,
+                    end: token.end(),
+                    severity: Severity::Warning,
+                    message: format!("Undefined variable {}", word),
+                });
+            }
+        }
+    }
+
+    analysis.diagnostics.extend(diagnostics);
+}
+
+// NEXT must name the variable of the FOR EACH it closes
+fn check_for_next(lines: &[Vec<Token>], analysis: &mut Analysis) {
+    let mut open: Vec<&str> = Vec::new();
+
+    for (index, tokens) in lines.iter().enumerate() {
+        match code_tokens(tokens).as_slice() {
+            [first, each, name, ..] if first.is_word("FOR") && each.is_word("EACH") => {
+                open.push(name.text)
+            }
+            [first, rest @ ..] if first.is_word("NEXT") => {
+                let expected = open.pop();
+                let name = rest.first().filter(|token| token.kind == TokenKind::Word);
+                if let (Some(expected), Some(name)) = (expected, name) {
+                    if name.text != expected {
+                        analysis.diagnostics.push(Diagnostic {
+                            line: index + 1,
+                            start: name.start,
+                            end: name.end(),
+                            severity: Severity::Error,
+                            message: format!(
+                                "NEXT {} doesn't match FOR EACH {}",
+                                name.text, expected
+                            ),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Tokens of a line other than comments
+fn code_tokens<'a>(tokens: &[Token<'a>]) -> Vec<Token<'a>> {
+    tokens
+        .iter()
+        .filter(|token| token.kind != TokenKind::Comment)
+        .copied()
+        .collect()
+}
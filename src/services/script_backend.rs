@@ -0,0 +1,653 @@
+use crate::models::automation_model::{TableTriggerOptions, TriggerKind};
+use crate::services::automation::{drop_table_trigger, install_table_trigger, validate_automation};
+use crate::services::email::{deliver_email, parse_recipients, SendEmailRequest};
+use crate::services::email_template::{render_template, EmailTemplate};
+use crate::services::keywords::create_draft::execute_create_draft;
+use crate::services::keywords::create_site::create_site;
+use crate::services::keywords::find::execute_find;
+use crate::services::keywords::get::execute_get;
+use crate::services::keywords::get_website::execute_headless_browser_search;
+use crate::services::keywords::on::execute_on_trigger;
+use crate::services::keywords::set::execute_set;
+use crate::services::keywords::set_schedule::execute_set_schedule;
+use crate::services::outbox::{cancel_email, enqueue_email};
+use crate::services::script::sleep_within_budget;
+use crate::services::script_pool::block_on;
+use crate::services::state::AppState;
+use crate::services::utils::call_llm;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Everything the keywords do outside of the script. Keywords run on the
+/// script pool, so implementations may block.
+pub trait ScriptBackend: Send + Sync {
+    /// Rows of `table` matching `filter`, as a JSON array
+    fn find(&self, table: &str, filter: &str) -> Result<Value, String>;
+    /// Number of rows updated
+    fn set(&self, table: &str, filter: &str, updates: &str) -> Result<i64, String>;
+    fn add_trigger(
+        &self,
+        kind: TriggerKind,
+        target: &str,
+        script_name: &str,
+    ) -> Result<i64, String>;
+    fn add_schedule(&self, cron: &str, script_name: &str) -> Result<i64, String>;
+    fn llm(&self, prompt: &str) -> Result<String, String>;
+    /// Contents of an `https://` URL or of a `/path` below WORK_ROOT
+    fn get(&self, url: &str) -> Result<String, String>;
+    fn website_search(&self, search_term: &str) -> Result<String, String>;
+    fn create_draft(&self, to: &str, subject: &str, text: &str) -> Result<String, String>;
+    fn create_site(&self, alias: &str, template_dir: &str, prompt: &str) -> Result<String, String>;
+    fn print(&self, text: &str);
+    fn wait(&self, duration: Duration) -> Result<(), String>;
+    /// Sends an email through the `/emails/send` pipeline and returns its
+    /// Message-ID; `attachments` are drive paths
+    fn send_mail(
+        &self,
+        to: &str,
+        subject: &str,
+        body: &str,
+        attachments: Value,
+    ) -> Result<String, String>;
+    /// HTML of email template `name` rendered with `data`, in the variant of
+    /// its `language` entry
+    fn template(&self, name: &str, data: Value) -> Result<String, String>;
+    /// Result of the server endpoint `path` called by CALL
+    fn call(&self, path: &str, args: Vec<Value>) -> Result<Value, String>;
+}
+
+fn bridge<F: Future>(fut: F) -> Result<F::Output, String> {
+    block_on(fut).map_err(|e| e.to_string())
+}
+
+/// Backend used by the server: the databases, Azure OpenAI, IMAP, the web and
+/// the browser pool
+pub struct LiveBackend {
+    state: AppState,
+    // Where the scripts that ON and SET_SCHEDULE register live
+    scripts_dir: String,
+}
+
+impl LiveBackend {
+    pub fn new(state: AppState, scripts_dir: &str) -> Self {
+        Self {
+            state,
+            scripts_dir: scripts_dir.to_string(),
+        }
+    }
+}
+
+impl ScriptBackend for LiveBackend {
+    fn find(&self, table: &str, filter: &str) -> Result<Value, String> {
+        let pool = self
+            .state
+            .db_custom
+            .as_ref()
+            .ok_or("Database not available")?;
+        let result = bridge(execute_find(pool, table, filter))??;
+        Ok(result.get("results").cloned().unwrap_or(Value::Null))
+    }
+
+    fn set(&self, table: &str, filter: &str, updates: &str) -> Result<i64, String> {
+        let pool = self
+            .state
+            .db_custom
+            .as_ref()
+            .ok_or("Database not available")?;
+        let result = bridge(execute_set(pool, table, filter, updates))??;
+        Ok(result["rows_affected"].as_i64().unwrap_or(0))
+    }
+
+    fn add_trigger(
+        &self,
+        kind: TriggerKind,
+        target: &str,
+        script_name: &str,
+    ) -> Result<i64, String> {
+        let pool = self.state.db.as_ref().ok_or("Database not available")?;
+        bridge(validate_automation(
+            &self.state,
+            &self.scripts_dir,
+            kind as i32,
+            Some(target),
+            None,
+            script_name,
+            &json!({}),
+        ))??;
+        let custom = self
+            .state
+            .db_custom
+            .as_ref()
+            .filter(|_| kind.is_table_trigger());
+
+        // Changes are delivered through a notify trigger on the watched table,
+        // installed along with a new automation. The automation is only saved
+        // once its trigger is in place.
+        let result = bridge(async {
+            let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+            let result = execute_on_trigger(&mut *tx, kind, target, script_name).await?;
+            let created = result["id"].as_str().and_then(|id| id.parse().ok());
+            if let (Some(custom), Some(automation_id)) = (custom, created) {
+                let soft_delete_column = TableTriggerOptions::default().soft_delete_column;
+                install_table_trigger(custom, automation_id, kind, target, &soft_delete_column)
+                    .await?;
+                if let Err(e) = tx.commit().await {
+                    if let Err(e) = drop_table_trigger(custom, automation_id, target).await {
+                        log::error!(
+                            "Failed to drop trigger of automation {}: {}",
+                            automation_id,
+                            e
+                        );
+                    }
+                    return Err(e.to_string());
+                }
+            } else {
+                tx.commit().await.map_err(|e| e.to_string())?;
+            }
+            Ok::<_, String>(result)
+        })??;
+
+        Ok(result["rows_affected"].as_i64().unwrap_or(0))
+    }
+
+    fn add_schedule(&self, cron: &str, script_name: &str) -> Result<i64, String> {
+        let pool = self.state.db.as_ref().ok_or("Database not available")?;
+        bridge(validate_automation(
+            &self.state,
+            &self.scripts_dir,
+            TriggerKind::Scheduled as i32,
+            None,
+            Some(cron),
+            script_name,
+            &json!({}),
+        ))??;
+        let result =
+            bridge(execute_set_schedule(pool, cron, script_name))?.map_err(|e| e.to_string())?;
+        Ok(result["rows_affected"].as_i64().unwrap_or(0))
+    }
+
+    fn llm(&self, prompt: &str) -> Result<String, String> {
+        let config = self
+            .state
+            .config
+            .as_ref()
+            .ok_or("Configuration not available")?;
+        bridge(call_llm(prompt, &config.ai))?.map_err(|e| e.to_string())
+    }
+
+    fn get(&self, url: &str) -> Result<String, String> {
+        if url.starts_with("https://") {
+            return bridge(execute_get(url))?.map_err(|e| e.to_string());
+        }
+        if let Some(path) = url.strip_prefix('/') {
+            let work_root = std::env::var("WORK_ROOT").unwrap_or_else(|_| "./work".to_string());
+            let full_path = std::path::Path::new(&work_root).join(path);
+            return std::fs::read_to_string(&full_path)
+                .map_err(|e| format!("Failed to read file: {}", e));
+        }
+        Err("URL must begin with 'https://' or '/'".to_string())
+    }
+
+    fn website_search(&self, search_term: &str) -> Result<String, String> {
+        let browser_pool = self.state.browser_pool.clone();
+        bridge(execute_headless_browser_search(browser_pool, search_term))?
+            .map_err(|e| e.to_string())
+    }
+
+    fn create_draft(&self, to: &str, subject: &str, text: &str) -> Result<String, String> {
+        bridge(execute_create_draft(&self.state, to, subject, text))?
+    }
+
+    fn create_site(&self, alias: &str, template_dir: &str, prompt: &str) -> Result<String, String> {
+        let config = self
+            .state
+            .config
+            .as_ref()
+            .ok_or("Configuration not available")?;
+        bridge(create_site(config, alias, template_dir, prompt))?.map_err(|e| e.to_string())
+    }
+
+    fn print(&self, text: &str) {
+        println!("{}", text);
+    }
+
+    fn wait(&self, duration: Duration) -> Result<(), String> {
+        sleep_within_budget(duration).map_err(|e| e.to_string())
+    }
+
+    fn send_mail(
+        &self,
+        to: &str,
+        subject: &str,
+        body: &str,
+        attachments: Value,
+    ) -> Result<String, String> {
+        let request =
+            SendEmailRequest::from_args(&[json!(to), json!(subject), json!(body), attachments])?;
+        bridge(deliver_email(&self.state, &request, Vec::new()))?.map_err(|e| e.to_string())
+    }
+
+    fn template(&self, name: &str, data: Value) -> Result<String, String> {
+        let language = data["language"].as_str();
+        let rendered = bridge(render_template(&self.state, name, language, &data))?
+            .map_err(|e| e.to_string())?;
+        Ok(rendered.html)
+    }
+
+    fn call(&self, path: &str, args: Vec<Value>) -> Result<Value, String> {
+        match path {
+            "/comm/email/send" => {
+                let request = SendEmailRequest::from_args(&args)?;
+                let message_id = bridge(deliver_email(&self.state, &request, Vec::new()))?
+                    .map_err(|e| e.to_string())?;
+                Ok(Value::String(message_id))
+            }
+            // to, subject, body, attachments [, send_at]: the queued message
+            "/comm/email/schedule" => {
+                let request = SendEmailRequest::from_args(&args)?;
+                parse_recipients(&request).map_err(|e| e.to_string())?;
+                let send_at = match args.get(4).and_then(Value::as_str) {
+                    Some(time) => Some(
+                        chrono::DateTime::parse_from_rfc3339(time)
+                            .map_err(|e| format!("Invalid send time {}: {}", time, e))?
+                            .with_timezone(&chrono::Utc),
+                    ),
+                    None => None,
+                };
+                let pool = self.state.db.as_ref().ok_or("Database not available")?;
+                let entry =
+                    bridge(enqueue_email(pool, &request, send_at))?.map_err(|e| e.to_string())?;
+                serde_json::to_value(entry).map_err(|e| e.to_string())
+            }
+            // id: whether the message was still queued and is now cancelled
+            "/comm/email/cancel" => {
+                let id = args
+                    .first()
+                    .and_then(Value::as_str)
+                    .and_then(|id| uuid::Uuid::parse_str(id).ok())
+                    .ok_or("Expected the id of a scheduled email")?;
+                let pool = self.state.db.as_ref().ok_or("Database not available")?;
+                let cancelled = bridge(cancel_email(pool, id))?.map_err(|e| e.to_string())?;
+                Ok(Value::Bool(cancelled.is_some()))
+            }
+            // name, data [, language]: the subject, HTML and text
+            "/comm/email/template" => {
+                let name = args.first().and_then(Value::as_str).unwrap_or_default();
+                let data = args.get(1).cloned().unwrap_or(Value::Null);
+                let language = args.get(2).and_then(Value::as_str);
+                let rendered = bridge(render_template(&self.state, name, language, &data))?
+                    .map_err(|e| e.to_string())?;
+                serde_json::to_value(rendered).map_err(|e| e.to_string())
+            }
+            _ => Err(format!("No endpoint {}", path)),
+        }
+    }
+}
+
+/// Canned keyword results for a script test
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockResponses {
+    /// Rows returned by FIND, per table, whatever the filter
+    #[serde(default)]
+    pub find: HashMap<String, Vec<Value>>,
+    /// LLM answers, handed out in order; the last one repeats
+    #[serde(default)]
+    pub llm: Vec<String>,
+    /// GET responses by URL or path
+    #[serde(default)]
+    pub get: HashMap<String, String>,
+    /// WEBSITE OF results by search term
+    #[serde(default)]
+    pub website: HashMap<String, String>,
+    /// CALL results by endpoint path; unlisted endpoints return nothing
+    #[serde(default)]
+    pub call: HashMap<String, Value>,
+    /// Sources of the templates TEMPLATE renders, by name
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+}
+
+/// A keyword call seen by the `MockBackend`
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordedCall {
+    pub keyword: String,
+    pub args: Vec<Value>,
+}
+
+/// Backend for script tests: answers from `MockResponses` and records every
+/// call and PRINT line instead of touching the outside world
+#[derive(Default)]
+pub struct MockBackend {
+    responses: MockResponses,
+    llm_calls: Mutex<usize>,
+    calls: Mutex<Vec<RecordedCall>>,
+    output: Mutex<Vec<String>>,
+}
+
+impl MockBackend {
+    pub fn new(responses: MockResponses) -> Self {
+        Self {
+            responses,
+            ..Default::default()
+        }
+    }
+
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn output(&self) -> Vec<String> {
+        self.output.lock().unwrap().clone()
+    }
+
+    fn record(&self, keyword: &str, args: Vec<Value>) {
+        self.calls.lock().unwrap().push(RecordedCall {
+            keyword: keyword.to_string(),
+            args,
+        });
+    }
+}
+
+impl ScriptBackend for MockBackend {
+    fn find(&self, table: &str, filter: &str) -> Result<Value, String> {
+        self.record("FIND", vec![json!(table), json!(filter)]);
+        let rows = self.responses.find.get(table).cloned().unwrap_or_default();
+        Ok(Value::Array(rows))
+    }
+
+    fn set(&self, table: &str, filter: &str, updates: &str) -> Result<i64, String> {
+        self.record("SET", vec![json!(table), json!(filter), json!(updates)]);
+        Ok(1)
+    }
+
+    fn add_trigger(
+        &self,
+        kind: TriggerKind,
+        target: &str,
+        script_name: &str,
+    ) -> Result<i64, String> {
+        self.record(
+            "ON",
+            vec![
+                json!(format!("{:?}", kind)),
+                json!(target),
+                json!(script_name),
+            ],
+        );
+        Ok(1)
+    }
+
+    fn add_schedule(&self, cron: &str, script_name: &str) -> Result<i64, String> {
+        self.record("SET_SCHEDULE", vec![json!(cron), json!(script_name)]);
+        Ok(1)
+    }
+
+    fn llm(&self, prompt: &str) -> Result<String, String> {
+        self.record("LLM", vec![json!(prompt)]);
+        let mut llm_calls = self.llm_calls.lock().unwrap();
+        let answer = self
+            .responses
+            .llm
+            .get(*llm_calls)
+            .or_else(|| self.responses.llm.last())
+            .cloned()
+            .ok_or("No mocked LLM response")?;
+        *llm_calls += 1;
+        Ok(answer)
+    }
+
+    fn get(&self, url: &str) -> Result<String, String> {
+        self.record("GET", vec![json!(url)]);
+        self.responses
+            .get
+            .get(url)
+            .cloned()
+            .ok_or_else(|| format!("No mocked response for GET {}", url))
+    }
+
+    fn website_search(&self, search_term: &str) -> Result<String, String> {
+        self.record("WEBSITE OF", vec![json!(search_term)]);
+        self.responses
+            .website
+            .get(search_term)
+            .cloned()
+            .ok_or_else(|| format!("No mocked result for WEBSITE OF {}", search_term))
+    }
+
+    fn create_draft(&self, to: &str, subject: &str, text: &str) -> Result<String, String> {
+        self.record("CREATE_DRAFT", vec![json!(to), json!(subject), json!(text)]);
+        Ok("Draft saved successfully".to_string())
+    }
+
+    fn create_site(&self, alias: &str, template_dir: &str, prompt: &str) -> Result<String, String> {
+        self.record(
+            "CREATE_SITE",
+            vec![json!(alias), json!(template_dir), json!(prompt)],
+        );
+        Ok(alias.to_string())
+    }
+
+    fn print(&self, text: &str) {
+        self.output.lock().unwrap().push(text.to_string());
+    }
+
+    fn wait(&self, duration: Duration) -> Result<(), String> {
+        self.record("WAIT", vec![json!(duration.as_secs_f64())]);
+        Ok(())
+    }
+
+    fn send_mail(
+        &self,
+        to: &str,
+        subject: &str,
+        body: &str,
+        attachments: Value,
+    ) -> Result<String, String> {
+        self.record(
+            "SEND MAIL",
+            vec![json!(to), json!(subject), json!(body), attachments],
+        );
+        Ok(format!(
+            "<mock-{}@localhost>",
+            self.calls.lock().unwrap().len()
+        ))
+    }
+
+    fn template(&self, name: &str, data: Value) -> Result<String, String> {
+        self.record("TEMPLATE", vec![json!(name), data.clone()]);
+        let source = self
+            .responses
+            .templates
+            .get(name)
+            .ok_or_else(|| format!("No mocked template {}", name))?;
+        let template = EmailTemplate::parse(source).map_err(|e| e.to_string())?;
+        Ok(template.render(&data).html)
+    }
+
+    fn call(&self, path: &str, args: Vec<Value>) -> Result<Value, String> {
+        let mut recorded = vec![json!(path)];
+        recorded.extend(args);
+        self.record("CALL", recorded);
+        Ok(self
+            .responses
+            .call
+            .get(path)
+            .cloned()
+            .unwrap_or(Value::Null))
+    }
+}
+
+/// A keyword call recorded by the `TracingBackend`
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    pub keyword: String,
+    pub args: Vec<Value>,
+    /// Milliseconds from the start of the run to the call
+    pub started_ms: u64,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// Passes every keyword call on to another backend, recording its arguments,
+/// timing and failure
+pub struct TracingBackend {
+    inner: Arc<dyn ScriptBackend>,
+    started: Instant,
+    entries: Mutex<Vec<TraceEntry>>,
+}
+
+impl TracingBackend {
+    pub fn new(inner: Arc<dyn ScriptBackend>) -> Self {
+        Self {
+            inner,
+            started: Instant::now(),
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn entries(&self) -> Vec<TraceEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Entries recorded after the first `from` ones
+    pub fn entries_since(&self, from: usize) -> Vec<TraceEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(from..)
+            .map(<[TraceEntry]>::to_vec)
+            .unwrap_or_default()
+    }
+
+    fn trace<T>(
+        &self,
+        keyword: &str,
+        args: Vec<Value>,
+        call: impl FnOnce() -> Result<T, String>,
+    ) -> Result<T, String> {
+        let start = Instant::now();
+        let result = call();
+        self.entries.lock().unwrap().push(TraceEntry {
+            keyword: keyword.to_string(),
+            args,
+            started_ms: start.duration_since(self.started).as_millis() as u64,
+            duration_ms: start.elapsed().as_millis() as u64,
+            error: result.as_ref().err().cloned(),
+        });
+        result
+    }
+}
+
+impl ScriptBackend for TracingBackend {
+    fn find(&self, table: &str, filter: &str) -> Result<Value, String> {
+        self.trace("FIND", vec![json!(table), json!(filter)], || {
+            self.inner.find(table, filter)
+        })
+    }
+
+    fn set(&self, table: &str, filter: &str, updates: &str) -> Result<i64, String> {
+        self.trace(
+            "SET",
+            vec![json!(table), json!(filter), json!(updates)],
+            || self.inner.set(table, filter, updates),
+        )
+    }
+
+    fn add_trigger(
+        &self,
+        kind: TriggerKind,
+        target: &str,
+        script_name: &str,
+    ) -> Result<i64, String> {
+        self.trace(
+            "ON",
+            vec![
+                json!(format!("{:?}", kind)),
+                json!(target),
+                json!(script_name),
+            ],
+            || self.inner.add_trigger(kind, target, script_name),
+        )
+    }
+
+    fn add_schedule(&self, cron: &str, script_name: &str) -> Result<i64, String> {
+        self.trace(
+            "SET_SCHEDULE",
+            vec![json!(cron), json!(script_name)],
+            || self.inner.add_schedule(cron, script_name),
+        )
+    }
+
+    fn llm(&self, prompt: &str) -> Result<String, String> {
+        self.trace("LLM", vec![json!(prompt)], || self.inner.llm(prompt))
+    }
+
+    fn get(&self, url: &str) -> Result<String, String> {
+        self.trace("GET", vec![json!(url)], || self.inner.get(url))
+    }
+
+    fn website_search(&self, search_term: &str) -> Result<String, String> {
+        self.trace("WEBSITE OF", vec![json!(search_term)], || {
+            self.inner.website_search(search_term)
+        })
+    }
+
+    fn create_draft(&self, to: &str, subject: &str, text: &str) -> Result<String, String> {
+        self.trace(
+            "CREATE_DRAFT",
+            vec![json!(to), json!(subject), json!(text)],
+            || self.inner.create_draft(to, subject, text),
+        )
+    }
+
+    fn create_site(&self, alias: &str, template_dir: &str, prompt: &str) -> Result<String, String> {
+        self.trace(
+            "CREATE_SITE",
+            vec![json!(alias), json!(template_dir), json!(prompt)],
+            || self.inner.create_site(alias, template_dir, prompt),
+        )
+    }
+
+    fn print(&self, text: &str) {
+        let _ = self.trace("PRINT", vec![json!(text)], || {
+            self.inner.print(text);
+            Ok(())
+        });
+    }
+
+    fn wait(&self, duration: Duration) -> Result<(), String> {
+        self.trace("WAIT", vec![json!(duration.as_secs_f64())], || {
+            self.inner.wait(duration)
+        })
+    }
+
+    fn send_mail(
+        &self,
+        to: &str,
+        subject: &str,
+        body: &str,
+        attachments: Value,
+    ) -> Result<String, String> {
+        self.trace(
+            "SEND MAIL",
+            vec![json!(to), json!(subject), json!(body), attachments.clone()],
+            || self.inner.send_mail(to, subject, body, attachments),
+        )
+    }
+
+    fn template(&self, name: &str, data: Value) -> Result<String, String> {
+        self.trace("TEMPLATE", vec![json!(name), data.clone()], || {
+            self.inner.template(name, data)
+        })
+    }
+
+    fn call(&self, path: &str, args: Vec<Value>) -> Result<Value, String> {
+        let mut traced = vec![json!(path)];
+        traced.extend(args.iter().cloned());
+        self.trace("CALL", traced, || self.inner.call(path, args))
+    }
+}
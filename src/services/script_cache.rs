@@ -0,0 +1,148 @@
+use crate::services::script::ScriptService;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rhai::AST;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+
+// Extensions of the files in scripts_dir that hold scripts
+const SCRIPT_EXTENSIONS: [&str; 2] = ["bas", "rhai"];
+
+struct CachedScript {
+    modified: SystemTime,
+    ast: Arc<AST>,
+}
+
+/// Outcome of recompiling a script after it changed on disk
+pub struct ScriptReload {
+    pub param: String,
+    pub result: Result<(), String>,
+}
+
+/// Compiled scripts of `scripts_dir`, keyed by path and invalidated by mtime.
+/// All scripts share one keyword-registered engine.
+pub struct ScriptCache {
+    scripts_dir: PathBuf,
+    script_service: Arc<ScriptService>,
+    entries: RwLock<HashMap<PathBuf, CachedScript>>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl ScriptCache {
+    pub fn new(scripts_dir: &str, script_service: Arc<ScriptService>) -> Self {
+        Self {
+            scripts_dir: PathBuf::from(scripts_dir),
+            script_service,
+            entries: RwLock::new(HashMap::new()),
+            watcher: Mutex::new(None),
+        }
+    }
+
+    pub fn script_service(&self) -> &Arc<ScriptService> {
+        &self.script_service
+    }
+
+    /// Returns the compiled script, recompiling it if the file changed since it
+    /// was cached
+    pub fn get(&self, param: &str) -> Result<Arc<AST>, String> {
+        let path = self.scripts_dir.join(param);
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("Failed to execute action {}: {}", path.display(), e))?;
+
+        if let Some(cached) = self.entries.read().unwrap().get(&path) {
+            if cached.modified == modified {
+                return Ok(cached.ast.clone());
+            }
+        }
+
+        self.compile(&path, modified)
+    }
+
+    fn compile(&self, path: &Path, modified: SystemTime) -> Result<Arc<AST>, String> {
+        let script = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to execute action {}: {}", path.display(), e))?;
+
+        let mut ast = self
+            .script_service
+            .compile(&script)
+            .map_err(|e| format!("Error compiling script: {}", e))?;
+        // Tells ON and SET_SCHEDULE which script registers itself
+        ast.set_source(
+            path.strip_prefix(&self.scripts_dir)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .as_ref(),
+        );
+        let ast = Arc::new(ast);
+
+        self.entries.write().unwrap().insert(
+            path.to_path_buf(),
+            CachedScript {
+                modified,
+                ast: ast.clone(),
+            },
+        );
+        Ok(ast)
+    }
+
+    /// Watches `scripts_dir` and recompiles scripts as soon as they change, so
+    /// that compile errors surface right away instead of at the next trigger
+    pub fn watch(self: &Arc<Self>) -> notify::Result<mpsc::UnboundedReceiver<ScriptReload>> {
+        let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+        let cache: Weak<Self> = Arc::downgrade(self);
+
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            let (cache, event) = match (cache.upgrade(), res) {
+                (Some(cache), Ok(event)) => (cache, event),
+                (_, Err(e)) => {
+                    eprintln!("Script watcher error: {}", e);
+                    return;
+                }
+                _ => return,
+            };
+
+            for path in event.paths.iter().filter(|path| is_script(path)) {
+                if let Some(reload) = cache.reload(&event.kind, path) {
+                    let _ = reload_tx.send(reload);
+                }
+            }
+        })?;
+        watcher.watch(&self.scripts_dir, RecursiveMode::Recursive)?;
+
+        *self.watcher.lock().unwrap() = Some(watcher);
+        Ok(reload_rx)
+    }
+
+    fn reload(&self, kind: &EventKind, path: &Path) -> Option<ScriptReload> {
+        let param = path
+            .strip_prefix(&self.scripts_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned();
+
+        if matches!(kind, EventKind::Remove(_)) || !path.exists() {
+            self.entries.write().unwrap().remove(path);
+            return None;
+        }
+        if !matches!(kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            return None;
+        }
+
+        let result = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+            .and_then(|modified| self.compile(path, modified))
+            .map(|_| ());
+
+        Some(ScriptReload { param, result })
+    }
+}
+
+fn is_script(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| SCRIPT_EXTENSIONS.contains(&extension))
+}
@@ -0,0 +1,348 @@
+use crate::services::automation::AutomationService;
+use crate::services::script::LineMap;
+use crate::services::script_backend::{TraceEntry, TracingBackend};
+use crate::services::utils::dynamic_to_json;
+use actix_web::web;
+use actix_ws::{Message, MessageStream, Session};
+use rhai::debugger::{DebuggerCommand, DebuggerEvent};
+use rhai::{EvalAltResult, EvalContext, Position, Scope};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::sync::mpsc::{self as std_mpsc, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+// A paused script whose client sends nothing for this long is stopped, which
+// frees its thread for other debug sessions
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Messages a debugging client sends over the WebSocket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum DebugCommand {
+    /// Starts the automation's script with these PARAM values
+    Run {
+        #[serde(default)]
+        params: Map<String, Value>,
+        #[serde(default)]
+        stop_on_entry: bool,
+    },
+    /// Replaces the breakpoints with these .bas lines
+    Breakpoints {
+        lines: Vec<usize>,
+    },
+    /// Runs to the next line
+    Step,
+    /// Runs to the next breakpoint
+    Continue,
+    /// Pauses a running script at the next line
+    Pause,
+    Variables,
+    /// Aborts the script
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseReason {
+    Breakpoint,
+    Step,
+    Pause,
+}
+
+/// Messages sent back to the debugging client
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DebugEvent {
+    /// The script stopped before running `line`. `calls` are the keyword calls
+    /// made since the previous pause.
+    Paused {
+        line: usize,
+        reason: PauseReason,
+        variables: Map<String, Value>,
+        calls: Vec<TraceEntry>,
+    },
+    Variables {
+        variables: Map<String, Value>,
+    },
+    Finished {
+        result: Option<Value>,
+        error: Option<String>,
+        trace: Vec<TraceEntry>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+// Where the script is, to tell when it reaches another line
+struct StepState {
+    stepping: bool,
+    line: usize,
+    // First step taken on `line`; seeing it again means a loop came back
+    entry: Position,
+    reported_calls: usize,
+}
+
+/// Debugger of one run, driven by the engine's debugging interface on the
+/// script thread. Pausing blocks that thread until the client sends a command.
+pub struct ScriptDebugger {
+    breakpoints: Arc<RwLock<HashSet<usize>>>,
+    commands: Mutex<Receiver<DebugCommand>>,
+    events: mpsc::UnboundedSender<DebugEvent>,
+    line_map: LineMap,
+    trace: Option<Arc<TracingBackend>>,
+    step: Mutex<StepState>,
+}
+
+impl ScriptDebugger {
+    pub fn new(
+        breakpoints: Arc<RwLock<HashSet<usize>>>,
+        commands: Receiver<DebugCommand>,
+        events: mpsc::UnboundedSender<DebugEvent>,
+        stop_on_entry: bool,
+    ) -> Self {
+        Self {
+            breakpoints,
+            commands: Mutex::new(commands),
+            events,
+            line_map: LineMap::default(),
+            trace: None,
+            step: Mutex::new(StepState {
+                stepping: stop_on_entry,
+                line: 0,
+                entry: Position::NONE,
+                reported_calls: 0,
+            }),
+        }
+    }
+
+    /// Sets where the lines of the compiled script come from and where its
+    /// keyword calls are recorded
+    pub fn attach(mut self, line_map: LineMap, trace: Arc<TracingBackend>) -> Self {
+        self.line_map = line_map;
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Called by the engine on every step; keeps it stepping so that
+    /// breakpoints can change while the script runs
+    pub fn on_event(
+        &self,
+        context: EvalContext,
+        event: DebuggerEvent,
+        pos: Position,
+    ) -> Result<DebuggerCommand, Box<EvalAltResult>> {
+        match event {
+            DebuggerEvent::Start | DebuggerEvent::Step | DebuggerEvent::BreakPoint(_) => {}
+            DebuggerEvent::End => return Ok(DebuggerCommand::Continue),
+            _ => return Ok(DebuggerCommand::StepInto),
+        }
+        let line = match pos.line() {
+            Some(line) => self.line_map.bas_line(line),
+            None => return Ok(DebuggerCommand::StepInto),
+        };
+
+        let mut reason = {
+            let mut step = self.step.lock().unwrap();
+            if line == step.line && pos != step.entry {
+                return Ok(DebuggerCommand::StepInto);
+            }
+            step.line = line;
+            step.entry = pos;
+
+            if step.stepping {
+                Some(PauseReason::Step)
+            } else if self.breakpoints.read().unwrap().contains(&line) {
+                Some(PauseReason::Breakpoint)
+            } else {
+                None
+            }
+        };
+
+        // Commands sent while the script runs are picked up line by line
+        match self.commands.lock().unwrap().try_recv() {
+            Ok(DebugCommand::Stop) | Err(TryRecvError::Disconnected) => {
+                return Err("Debug session stopped".into())
+            }
+            Ok(DebugCommand::Pause) | Ok(DebugCommand::Step) => {
+                reason = reason.or(Some(PauseReason::Pause))
+            }
+            Ok(_) | Err(TryRecvError::Empty) => {}
+        }
+
+        match reason {
+            Some(reason) => self.pause(context.scope(), line, reason),
+            None => Ok(DebuggerCommand::StepInto),
+        }
+    }
+
+    fn pause(
+        &self,
+        scope: &Scope,
+        line: usize,
+        reason: PauseReason,
+    ) -> Result<DebuggerCommand, Box<EvalAltResult>> {
+        let calls = self.new_calls();
+        self.send(DebugEvent::Paused {
+            line,
+            reason,
+            variables: variables(scope),
+            calls,
+        });
+
+        let commands = self.commands.lock().unwrap();
+        loop {
+            let stepping = match commands.recv_timeout(IDLE_TIMEOUT) {
+                Ok(DebugCommand::Step) => true,
+                Ok(DebugCommand::Continue) => false,
+                Ok(DebugCommand::Variables) => {
+                    self.send(DebugEvent::Variables {
+                        variables: variables(scope),
+                    });
+                    continue;
+                }
+                Ok(DebugCommand::Pause) => continue,
+                Ok(DebugCommand::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                    return Err("Debug session stopped".into())
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(format!(
+                        "Debug session idle for {} seconds",
+                        IDLE_TIMEOUT.as_secs()
+                    )
+                    .into())
+                }
+                Ok(command) => {
+                    self.send(DebugEvent::Error {
+                        message: format!("{:?} is not allowed while paused", command),
+                    });
+                    continue;
+                }
+            };
+            self.step.lock().unwrap().stepping = stepping;
+            return Ok(DebuggerCommand::StepInto);
+        }
+    }
+
+    fn new_calls(&self) -> Vec<TraceEntry> {
+        let trace = match &self.trace {
+            Some(trace) => trace,
+            None => return Vec::new(),
+        };
+        let mut step = self.step.lock().unwrap();
+        let calls = trace.entries_since(step.reported_calls);
+        step.reported_calls += calls.len();
+        calls
+    }
+
+    fn send(&self, event: DebugEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+// Variables in scope, the innermost one winning when names are shadowed
+fn variables(scope: &Scope) -> Map<String, Value> {
+    scope
+        .iter()
+        .map(|(name, _, value)| (name.to_string(), dynamic_to_json(&value)))
+        .collect()
+}
+
+/// Serves one debugging client of the script `param` until the socket closes.
+/// Closing the socket aborts a script that is still running.
+pub async fn serve_debug_session(
+    automation: web::Data<AutomationService>,
+    param: String,
+    mut session: Session,
+    mut messages: MessageStream,
+) {
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let breakpoints = Arc::new(RwLock::new(HashSet::new()));
+    // Commands for the running script, if any
+    let mut running: Option<std_mpsc::Sender<DebugCommand>> = None;
+
+    loop {
+        tokio::select! {
+            message = messages.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Ping(bytes))) => {
+                        let _ = session.pong(&bytes).await;
+                        continue;
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+
+                match serde_json::from_str::<DebugCommand>(&text) {
+                    Ok(DebugCommand::Breakpoints { lines }) => {
+                        *breakpoints.write().unwrap() = lines.into_iter().collect();
+                    }
+                    Ok(DebugCommand::Run { .. }) if running.is_some() => {
+                        let _ = event_tx.send(DebugEvent::Error {
+                            message: "The script is already running".to_string(),
+                        });
+                    }
+                    Ok(DebugCommand::Run { params, stop_on_entry }) => {
+                        let (command_tx, command_rx) = std_mpsc::channel();
+                        running = Some(command_tx);
+
+                        let debugger = ScriptDebugger::new(
+                            breakpoints.clone(),
+                            command_rx,
+                            event_tx.clone(),
+                            stop_on_entry,
+                        );
+                        let automation = automation.clone();
+                        let param = param.clone();
+                        let events = event_tx.clone();
+                        actix_web::rt::spawn(async move {
+                            let (result, trace) =
+                                automation.debug_script(&param, &params, debugger).await;
+                            let (result, error) = match result {
+                                Ok(result) => (Some(result), None),
+                                Err(e) => (None, Some(e)),
+                            };
+                            let _ = events.send(DebugEvent::Finished { result, error, trace });
+                        });
+                    }
+                    Ok(command) => {
+                        let sent = running.as_ref().map_or(false, |tx| tx.send(command).is_ok());
+                        if !sent {
+                            let _ = event_tx.send(DebugEvent::Error {
+                                message: "No script is running".to_string(),
+                            });
+                        }
+                    }
+                    Err(e) => {
+                        let _ = event_tx.send(DebugEvent::Error {
+                            message: format!("Invalid command: {}", e),
+                        });
+                    }
+                }
+            }
+            Some(event) = event_rx.recv() => {
+                if matches!(event, DebugEvent::Finished { .. }) {
+                    running = None;
+                }
+                let text = match serde_json::to_string(&event) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        eprintln!("Failed to serialize debug event: {}", e);
+                        continue;
+                    }
+                };
+                if session.text(text).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Dropping `running` ends a paused script
+    drop(running);
+    let _ = session.close(None).await;
+}
@@ -0,0 +1,247 @@
+use crate::services::config::ScriptLimits;
+use crate::services::script::{PreprocessError, ScriptService};
+use crate::services::script_analysis::{
+    keyword_info, statement_doc, symbols, CONNECTIVES, RHAI_WORDS,
+};
+use crate::services::script_backend::MockBackend;
+use crate::services::script_syntax::{continues_line, Blocks, Token, TokenKind, Tokenizer};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Indentation of one block level, as in the scripts under src/prompts
+const INDENT: &str = "  ";
+
+/// A script service that only compiles, for the command line tools
+pub fn offline_script_service() -> ScriptService {
+    ScriptService::with_backend(Arc::new(MockBackend::default()), ScriptLimits::default())
+}
+
+/// Finds the .bas scripts at or below `path`
+pub fn find_scripts(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            found.extend(find_scripts(&path)?);
+        } else if path
+            .extension()
+            .map_or(false, |extension| extension == "bas")
+        {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Formats a script: keywords in upper case, two spaces per FOR EACH, IF or
+/// bracket level, one more for lines continuing a statement, and every
+/// comment written with `'`. Scripts the preprocessor rejects are left alone.
+pub fn format_script(
+    script_service: &ScriptService,
+    text: &str,
+) -> Result<String, PreprocessError> {
+    script_service.preprocess_basic_script(text)?;
+
+    let defined: HashSet<String> = symbols(text)
+        .into_iter()
+        .map(|symbol| symbol.name)
+        .collect();
+    let mut tokenizer = Tokenizer::default();
+    let mut blocks = Blocks::default();
+    let mut formatted = String::new();
+    let mut blank = false;
+    let mut continued = false;
+
+    for line in text.lines() {
+        // Lines that start inside a string are part of its text
+        if tokenizer.in_string() {
+            let tokens = tokenizer.line(line);
+            blocks.push_line(&tokens);
+            formatted.push_str(line);
+            formatted.push('\n');
+            continued = !blocks.in_brackets() && continues_line(&tokens);
+            continue;
+        }
+
+        let trimmed = line.trim_start();
+        // Runs of blank lines become one, and none are kept at the ends
+        if trimmed.trim_end().is_empty() {
+            blank = !formatted.is_empty();
+            continue;
+        }
+        if blank {
+            formatted.push('\n');
+            blank = false;
+        }
+
+        let tokens = tokenizer.line(trimmed);
+        let depth = blocks.push_line(&tokens);
+        let line = format_line(trimmed, &tokens, &defined);
+        formatted.push_str(&INDENT.repeat(depth + usize::from(continued)));
+        // Trailing spaces of a string that goes on on the next line are text
+        if tokenizer.in_string() {
+            formatted.push_str(&line);
+        } else {
+            formatted.push_str(line.trim_end());
+        }
+        formatted.push('\n');
+
+        // Commas inside brackets separate items rather than continue a line
+        continued = !blocks.in_brackets() && continues_line(&tokens);
+    }
+
+    Ok(formatted)
+}
+
+// Recases the keywords of a trimmed line and rewrites its comment
+fn format_line(line: &str, tokens: &[Token], defined: &HashSet<String>) -> String {
+    let mut edits: Vec<(&Token, String)> = Vec::new();
+    let code: Vec<&Token> = tokens
+        .iter()
+        .filter(|token| token.kind != TokenKind::Comment)
+        .collect();
+
+    // The statement starts after `name =`
+    let start = if code.len() > 2 && code[1].is_symbol("=") {
+        2
+    } else {
+        0
+    };
+    let recasable = |index: usize| {
+        let token = code[index];
+        token.kind == TokenKind::Word
+            && !defined.contains(token.text)
+            && !code
+                .get(index + 1)
+                .map_or(false, |next| next.is_symbol("("))
+            && !(index > 0 && code[index - 1].is_symbol("."))
+    };
+
+    if let Some(statement) = code.get(start).filter(|_| recasable(start)) {
+        let upper = statement.text.to_ascii_uppercase();
+        let keyword = keyword_info(&upper).filter(|keyword| keyword.syntax[0] == upper);
+        let is_basic = keyword.is_some() || statement_doc(&upper).is_some();
+
+        // Lower case `if`, `for`, ... are Rhai's and stay as they are
+        if is_basic && !RHAI_WORDS.contains(&statement.text) {
+            let literals = keyword.map_or(&[][..], |keyword| keyword.syntax);
+            edits.push((*statement, upper));
+
+            let mut after_as = false;
+            for (index, token) in code.iter().enumerate().skip(start + 1) {
+                let word = token.text.to_ascii_uppercase();
+                // The type of a PARAM follows AS
+                let is_keyword_word = after_as
+                    || literals.contains(&word.as_str())
+                    || CONNECTIVES.contains(&word.as_str())
+                    || statement_doc(&word).is_some()
+                    || word == "FOR";
+                if is_keyword_word && recasable(index) {
+                    edits.push((*token, word.clone()));
+                }
+                after_as = word == "AS";
+            }
+        }
+    }
+
+    if let Some(comment) = tokens.iter().find(|token| token.kind == TokenKind::Comment) {
+        edits.push((comment, format_comment(comment.text)));
+    }
+
+    let mut formatted = String::new();
+    let mut copied = 0;
+    edits.sort_by_key(|(token, _)| token.start);
+    for (token, text) in edits {
+        formatted.push_str(&line[copied..token.start]);
+        formatted.push_str(&text);
+        copied = token.end();
+    }
+    formatted.push_str(&line[copied..]);
+    formatted
+}
+
+// Every comment style (`REM`, `#`, `//`) becomes `' text`
+fn format_comment(comment: &str) -> String {
+    let text = ["REM", "//", "'", "#"]
+        .iter()
+        .find_map(|marker| comment.strip_prefix(marker))
+        .unwrap_or(comment)
+        .trim();
+    if text.is_empty() {
+        "'".to_string()
+    } else {
+        format!("' {}", text)
+    }
+}
+
+/// `gbserver fmt [--check] [paths]`: formats the scripts in place, or with
+/// `--check` only lists those that would change, and returns the process exit
+/// code
+pub fn fmt_command(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let mut paths: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--check")
+        .collect();
+    if paths.is_empty() {
+        paths.push("src/prompts");
+    }
+
+    let script_service = offline_script_service();
+    let (mut unformatted, mut failed) = (0, 0);
+
+    for path in paths {
+        let scripts = match find_scripts(Path::new(path)) {
+            Ok(scripts) => scripts,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        for script in scripts {
+            let text = match std::fs::read_to_string(&script) {
+                Ok(text) => text,
+                Err(e) => {
+                    eprintln!("Failed to read {}: {}", script.display(), e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            let formatted = match format_script(&script_service, &text) {
+                Ok(formatted) => formatted,
+                Err(e) => {
+                    eprintln!("{}:{}: {}", script.display(), e.line, e.message);
+                    failed += 1;
+                    continue;
+                }
+            };
+            if formatted == text {
+                continue;
+            }
+
+            unformatted += 1;
+            if check {
+                println!("Would reformat {}", script.display());
+            } else if let Err(e) = std::fs::write(&script, formatted) {
+                eprintln!("Failed to write {}: {}", script.display(), e);
+                failed += 1;
+            } else {
+                println!("Formatted {}", script.display());
+            }
+        }
+    }
+
+    if failed > 0 || (check && unformatted > 0) {
+        1
+    } else {
+        0
+    }
+}
@@ -0,0 +1,190 @@
+use crate::services::script::ScriptService;
+use crate::services::script_analysis::{analyze, Diagnostic, Severity};
+use crate::services::script_fmt::{find_scripts, offline_script_service};
+use crate::services::script_syntax::{tokenize_lines, Blocks, Token, TokenKind};
+use std::path::Path;
+
+/// A risky pattern found in a script, with the rule that found it
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub rule: &'static str,
+    pub diagnostic: Diagnostic,
+}
+
+/// Checks a script for patterns that compile but are risky to run
+pub fn lint_script(text: &str) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let mut blocks = Blocks::default();
+
+    for (index, tokens) in tokenize_lines(text).iter().enumerate() {
+        // The loops around the line, before it opens any of its own
+        let in_loop = blocks.in_loop();
+        let in_unbounded_loop = blocks.in_unbounded_loop();
+        blocks.push_line(tokens);
+
+        let code: Vec<&Token> = tokens
+            .iter()
+            .filter(|token| token.kind != TokenKind::Comment)
+            .collect();
+        let (first, last) = match (code.first(), code.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => continue,
+        };
+        let mut lint = |rule: &'static str, from: &Token, message: &str| {
+            lints.push(Lint {
+                rule,
+                diagnostic: Diagnostic {
+                    line: index + 1,
+                    start: from.start,
+                    end: last.end(),
+                    severity: Severity::Warning,
+                    message: message.to_string(),
+                },
+            })
+        };
+
+        // The statement starts after `name =`
+        let statement = if code.len() > 2 && code[1].is_symbol("=") {
+            code[2]
+        } else {
+            first
+        };
+
+        if let Some(position) = code.iter().position(|token| token.is_word("QUERY")) {
+            if code[position..].iter().any(|token| token.is_symbol("+")) {
+                lint(
+                    "query-concat",
+                    code[position],
+                    "QUERY text built by concatenating values can be used for SQL injection",
+                );
+            }
+        }
+
+        if statement.is_word("DELETE") && !has_filter(&code) {
+            lint(
+                "delete-without-filter",
+                statement,
+                "DELETE without a filter removes every row of the table",
+            );
+        }
+        if let Some(sql) = code.iter().find(|token| {
+            let text = token.text.to_ascii_uppercase();
+            token.kind == TokenKind::Str && text.contains("DELETE FROM") && !text.contains("WHERE")
+        }) {
+            lint(
+                "delete-without-filter",
+                sql,
+                "DELETE FROM without WHERE removes every row of the table",
+            );
+        }
+
+        if in_loop && statement.is_word("WAIT") {
+            lint(
+                "wait-in-loop",
+                statement,
+                "WAIT inside a loop holds the script on every iteration",
+            );
+        }
+
+        if in_unbounded_loop {
+            if let Some(llm) = code.iter().find(|token| token.is_word("LLM")) {
+                lint(
+                    "llm-in-unbounded-loop",
+                    llm,
+                    "LLM call inside a loop that isn't bounded by a collection can use up the \
+                     script's LLM budget",
+                );
+            }
+        }
+    }
+
+    lints
+}
+
+// Whether a DELETE statement has a filter after its table, i.e. a second
+// argument outside any brackets
+fn has_filter(code: &[&Token]) -> bool {
+    let mut depth = 0;
+    for token in code {
+        match token.text {
+            "(" | "[" | "{" | "#{" if token.kind == TokenKind::Symbol => depth += 1,
+            ")" | "]" | "}" if token.kind == TokenKind::Symbol => depth -= 1,
+            "," if token.kind == TokenKind::Symbol && depth == 0 => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// `gbserver lint [paths]`: prints the diagnostics and risky patterns of the
+/// scripts and returns the process exit code
+pub fn lint_command(args: &[String]) -> i32 {
+    let paths: Vec<&str> = if args.is_empty() {
+        vec!["src/prompts"]
+    } else {
+        args.iter().map(String::as_str).collect()
+    };
+
+    let script_service = offline_script_service();
+    let (mut found, mut failed) = (0, 0);
+
+    for path in paths {
+        let scripts = match find_scripts(Path::new(path)) {
+            Ok(scripts) => scripts,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        for script in scripts {
+            match std::fs::read_to_string(&script) {
+                Ok(text) => found += report(&script, &script_service, &text),
+                Err(e) => {
+                    eprintln!("Failed to read {}: {}", script.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    println!("\n{} problems", found);
+    if found > 0 || failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+// Prints what the analysis and the lint rules found in one script
+fn report(script: &Path, script_service: &ScriptService, text: &str) -> usize {
+    let mut problems: Vec<(Diagnostic, Option<&str>)> = analyze(script_service, text)
+        .diagnostics
+        .into_iter()
+        .map(|diagnostic| (diagnostic, None))
+        .chain(
+            lint_script(text)
+                .into_iter()
+                .map(|lint| (lint.diagnostic, Some(lint.rule))),
+        )
+        .collect();
+    problems.sort_by_key(|(diagnostic, _)| (diagnostic.line, diagnostic.start));
+
+    for (diagnostic, rule) in &problems {
+        let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        println!(
+            "{}:{}:{}: {}: {}{}",
+            script.display(),
+            diagnostic.line,
+            diagnostic.start + 1,
+            severity,
+            diagnostic.message,
+            rule.map(|rule| format!(" [{}]", rule)).unwrap_or_default()
+        );
+    }
+    problems.len()
+}
@@ -0,0 +1,250 @@
+use crate::services::config::ScriptLimits;
+use crate::services::keywords::KEYWORDS;
+use crate::services::script::ScriptService;
+use crate::services::script_analysis::{
+    analyze, keyword_info, statement_doc, word_at, Analysis, Severity, Symbol, SymbolKind,
+    STATEMENTS,
+};
+use crate::services::script_backend::MockBackend;
+use crate::services::script_syntax::TokenKind;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use tower_lsp::jsonrpc::Result;
+use tower_lsp::lsp_types::*;
+use tower_lsp::{Client, LanguageServer, LspService, Server};
+
+/// Language server for .bas scripts. Diagnostics come from the same
+/// preprocessor and keyword registrations `ScriptService` runs scripts with.
+pub struct ScriptLanguageServer {
+    client: Client,
+    // Only used to compile, so its keywords never reach the outside world
+    script_service: ScriptService,
+    documents: RwLock<HashMap<Url, String>>,
+}
+
+/// `gbserver lsp`: serves the language server over stdin/stdout
+pub async fn serve_stdio() {
+    let (service, socket) = LspService::new(|client| ScriptLanguageServer {
+        client,
+        script_service: ScriptService::with_backend(
+            Arc::new(MockBackend::default()),
+            ScriptLimits::default(),
+        ),
+        documents: RwLock::new(HashMap::new()),
+    });
+    Server::new(tokio::io::stdin(), tokio::io::stdout(), socket)
+        .serve(service)
+        .await;
+}
+
+impl ScriptLanguageServer {
+    async fn update(&self, uri: Url, text: String, version: Option<i32>) {
+        let analysis = analyze(&self.script_service, &text);
+        let lines: Vec<&str> = text.lines().collect();
+
+        let diagnostics = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| Diagnostic {
+                range: range_of(&lines, diagnostic.line, diagnostic.start, diagnostic.end),
+                severity: Some(match diagnostic.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                source: Some("gbserver".to_string()),
+                message: diagnostic.message.clone(),
+                ..Default::default()
+            })
+            .collect();
+
+        self.documents.write().unwrap().insert(uri.clone(), text);
+        self.client
+            .publish_diagnostics(uri, diagnostics, version)
+            .await;
+    }
+
+    // The document text and the analysis of it
+    fn document(&self, uri: &Url) -> Option<(String, Analysis)> {
+        let text = self.documents.read().unwrap().get(uri)?.clone();
+        let analysis = analyze(&self.script_service, &text);
+        Some((text, analysis))
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for ScriptLanguageServer {
+    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::FULL,
+                )),
+                completion_provider: Some(CompletionOptions::default()),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                ..Default::default()
+            },
+            server_info: Some(ServerInfo {
+                name: "gbserver-basic".to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+        })
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let document = params.text_document;
+        self.update(document.uri, document.text, Some(document.version))
+            .await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        // Synced in full, so the last change is the whole document
+        if let Some(change) = params.content_changes.into_iter().last() {
+            let document = params.text_document;
+            self.update(document.uri, change.text, Some(document.version))
+                .await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.documents.write().unwrap().remove(&uri);
+        self.client.publish_diagnostics(uri, Vec::new(), None).await;
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let uri = params.text_document_position.text_document.uri;
+
+        let mut items: Vec<CompletionItem> = KEYWORDS
+            .iter()
+            .map(|keyword| CompletionItem {
+                label: keyword.name(),
+                kind: Some(CompletionItemKind::KEYWORD),
+                detail: Some(keyword.usage()),
+                documentation: Some(Documentation::String(keyword.doc.to_string())),
+                ..Default::default()
+            })
+            .collect();
+        items.extend(STATEMENTS.iter().map(|(statement, doc)| CompletionItem {
+            label: statement.to_string(),
+            kind: Some(CompletionItemKind::KEYWORD),
+            documentation: Some(Documentation::String(doc.to_string())),
+            ..Default::default()
+        }));
+
+        if let Some((_, analysis)) = self.document(&uri) {
+            let mut seen = HashSet::new();
+            for symbol in &analysis.symbols {
+                if !seen.insert(symbol.name.clone()) {
+                    continue;
+                }
+                items.push(CompletionItem {
+                    label: symbol.name.clone(),
+                    kind: Some(match symbol.kind {
+                        SymbolKind::Param => CompletionItemKind::FIELD,
+                        SymbolKind::Variable => CompletionItemKind::VARIABLE,
+                        SymbolKind::Function => CompletionItemKind::FUNCTION,
+                    }),
+                    detail: Some(symbol.detail.clone()),
+                    ..Default::default()
+                });
+            }
+        }
+
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let position = params.text_document_position_params;
+        let (text, analysis) = match self.document(&position.text_document.uri) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+        let line = match text.lines().nth(position.position.line as usize) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let word = match word_at(line, byte_column(line, position.position.character)) {
+            Some(word) if word.kind == TokenKind::Word => word,
+            _ => return Ok(None),
+        };
+
+        let contents = if let Some(keyword) = keyword_info(word.text) {
+            format!("```basic\n{}\n```\n\n{}", keyword.usage(), keyword.doc)
+        } else if let Some(doc) = statement_doc(word.text) {
+            doc.to_string()
+        } else if let Some(symbol) = analysis.symbol(word.text) {
+            format!("```basic\n{}\n```", symbol.detail)
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: contents,
+            }),
+            range: None,
+        }))
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let (text, analysis) = match self.document(&uri) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+        let line = match text.lines().nth(position.position.line as usize) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let word = match word_at(line, byte_column(line, position.position.character)) {
+            Some(word) if word.kind == TokenKind::Word => word,
+            _ => return Ok(None),
+        };
+        Ok(analysis
+            .symbol(word.text)
+            .map(|symbol| GotoDefinitionResponse::Scalar(location_of(uri, &text, symbol))))
+    }
+}
+
+// LSP columns count UTF-16 code units, the analysis counts bytes
+fn byte_column(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (offset, c) in line.char_indices() {
+        if units >= character as usize {
+            return offset;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn utf16_column(line: &str, byte: usize) -> u32 {
+    line[..byte.min(line.len())]
+        .chars()
+        .map(char::len_utf16)
+        .sum::<usize>() as u32
+}
+
+fn location_of(uri: Url, text: &str, symbol: &Symbol) -> Location {
+    let lines: Vec<&str> = text.lines().collect();
+    Location::new(uri, range_of(&lines, symbol.line, symbol.start, symbol.end))
+}
+
+fn range_of(lines: &[&str], line: usize, start: usize, end: usize) -> Range {
+    let text = lines.get(line.saturating_sub(1)).copied().unwrap_or("");
+    let line = line.saturating_sub(1) as u32;
+    Range::new(
+        Position::new(line, utf16_column(text, start)),
+        Position::new(line, utf16_column(text, end)),
+    )
+}
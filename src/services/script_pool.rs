@@ -0,0 +1,96 @@
+use rhai::EvalAltResult;
+use std::cell::RefCell;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+
+use crate::services::script::time_left;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+thread_local! {
+    // Runtime that keywords on a script thread hand their async work to
+    static SCRIPT_RUNTIME: RefCell<Option<Handle>> = RefCell::new(None);
+}
+
+/// The async bridge shared by all keywords: blocks the script thread until
+/// `fut` completes on the server runtime. Script threads are never runtime
+/// workers, so this neither pins an async worker nor depends on the runtime
+/// flavor. `fut` is dropped when the running script reaches its deadline, so
+/// a keyword stuck on I/O can't keep the thread past it.
+pub fn block_on<F: Future>(fut: F) -> Result<F::Output, Box<EvalAltResult>> {
+    let handle = SCRIPT_RUNTIME
+        .with(|runtime| runtime.borrow().clone())
+        .ok_or("Keywords can only run on the script pool")?;
+
+    match time_left() {
+        Some(remaining) => handle
+            .block_on(tokio::time::timeout(remaining, fut))
+            .map_err(|_| "Script timed out".into()),
+        None => Ok(handle.block_on(fut)),
+    }
+}
+
+/// Dedicated threads that run scripts, so that any number of running scripts
+/// leaves the actix and tokio workers free
+pub struct ScriptPool {
+    jobs: Mutex<Sender<Job>>,
+}
+
+impl ScriptPool {
+    pub fn new(size: usize, handle: Handle) -> Self {
+        let (job_tx, job_rx) = mpsc::channel::<Job>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+
+        for i in 0..size.max(1) {
+            let job_rx = job_rx.clone();
+            let handle = handle.clone();
+            std::thread::Builder::new()
+                .name(format!("gb-script-{}", i))
+                .spawn(move || {
+                    SCRIPT_RUNTIME.with(|runtime| *runtime.borrow_mut() = Some(handle));
+                    while let Some(job) = next_job(&job_rx) {
+                        job();
+                    }
+                })
+                .expect("Failed to start script worker");
+        }
+
+        Self {
+            jobs: Mutex::new(job_tx),
+        }
+    }
+
+    /// Runs `f` on a script thread and waits for its result without blocking
+    /// the calling task. Returns only once `f` has, so callers holding a slot
+    /// for the thread keep it while the thread is busy.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let _ = result_tx.send(catch_unwind(AssertUnwindSafe(f)));
+        });
+
+        self.jobs
+            .lock()
+            .unwrap()
+            .send(job)
+            .map_err(|_| "Script pool is shut down".to_string())?;
+
+        match result_rx.await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(_)) => Err("Script panicked".to_string()),
+            Err(_) => Err("Script worker stopped".to_string()),
+        }
+    }
+}
+
+fn next_job(job_rx: &Mutex<Receiver<Job>>) -> Option<Job> {
+    job_rx.lock().unwrap().recv().ok()
+}
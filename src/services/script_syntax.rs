@@ -0,0 +1,494 @@
+/// Kinds of tokens on a line of a .bas script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Identifier or keyword
+    Word,
+    Number,
+    /// String literal, quotes included
+    Str,
+    /// Comment up to the end of the line, marker included
+    Comment,
+    Symbol,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// Byte offset of the token in the line
+    pub start: usize,
+}
+
+impl Token<'_> {
+    pub fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+
+    pub fn is_word(&self, word: &str) -> bool {
+        self.kind == TokenKind::Word && self.text == word
+    }
+
+    pub fn is_symbol(&self, symbol: &str) -> bool {
+        self.kind == TokenKind::Symbol && self.text == symbol
+    }
+}
+
+// Two-character operators, kept as one symbol token
+const OPERATORS: [&str; 10] = ["==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "::", "#{"];
+
+/// Symbols that leave a statement unfinished at the end of a line
+pub const CONTINUATIONS: &[&str] = &[",", "+", "-", "*", "/", "&", "&&", "||", "="];
+
+/// Ends a line that goes on on the next one, as in `total = a + b _`
+pub const LINE_CONTINUATION: &str = "_";
+
+// Rhai words whose `{` opens a block rather than a map
+const BLOCK_WORDS: &[&str] = &[
+    "if", "else", "while", "loop", "for", "fn", "do", "switch", "try", "catch",
+];
+
+/// Splits one line of a .bas script into tokens. Comments start with `REM`,
+/// `'`, `//` or `#` (but `#{` opens a map) and run to the end of the line.
+pub fn tokenize_line(line: &str) -> Vec<Token<'_>> {
+    Tokenizer::default().line(line)
+}
+
+/// Tokens of every line of a script, strings spanning lines included
+pub fn tokenize_lines(script: &str) -> Vec<Vec<Token<'_>>> {
+    let mut tokenizer = Tokenizer::default();
+    script.lines().map(|line| tokenizer.line(line)).collect()
+}
+
+/// Tokenizes a script line by line, carrying strings left open at the end of
+/// a line over to the next
+#[derive(Debug, Default)]
+pub struct Tokenizer {
+    // Quote of the string the last line ended in
+    open_string: Option<u8>,
+}
+
+impl Tokenizer {
+    /// Tokens of the next line. The rest of a string continued from the
+    /// previous line is a `Str` token at the start of this one.
+    pub fn line<'a>(&mut self, line: &'a str) -> Vec<Token<'a>> {
+        let mut tokens = Vec::new();
+        let bytes = line.as_bytes();
+        let mut i = 0;
+
+        if let Some(quote) = self.open_string {
+            i = self.string_end(bytes, 0, quote);
+            tokens.push(Token {
+                kind: TokenKind::Str,
+                text: &line[..i],
+                start: 0,
+            });
+        }
+
+        while i < bytes.len() {
+            let c = bytes[i];
+            let start = i;
+            let rest = &line[i..];
+
+            let kind = if c.is_ascii_whitespace() {
+                i += 1;
+                continue;
+            } else if c == b'\'' || rest.starts_with("//") || (c == b'#' && !rest.starts_with("#{"))
+            {
+                i = bytes.len();
+                TokenKind::Comment
+            } else if c == b'"' || c == b'`' {
+                i = self.string_end(bytes, i + 1, c);
+                TokenKind::Str
+            } else if c.is_ascii_digit() {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                    i += 1;
+                }
+                TokenKind::Number
+            } else if is_word_char(c) {
+                while i < bytes.len() && is_word_char(bytes[i]) {
+                    i += 1;
+                }
+                if &line[start..i] == "REM" {
+                    i = bytes.len();
+                    TokenKind::Comment
+                } else {
+                    TokenKind::Word
+                }
+            } else {
+                i += OPERATORS
+                    .iter()
+                    .find(|op| rest.starts_with(*op))
+                    .map_or(rest.chars().next().map_or(1, char::len_utf8), |op| op.len());
+                TokenKind::Symbol
+            };
+
+            tokens.push(Token {
+                kind,
+                text: &line[start..i],
+                start,
+            });
+        }
+
+        tokens
+    }
+
+    /// Whether the last line ended inside a string
+    pub fn in_string(&self) -> bool {
+        self.open_string.is_some()
+    }
+
+    // Offset just past the string closed after `from`, or the end of the line
+    // with the string left open
+    fn string_end(&mut self, bytes: &[u8], from: usize, quote: u8) -> usize {
+        let mut i = from;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 2,
+                c if c == quote => {
+                    self.open_string = None;
+                    return i + 1;
+                }
+                _ => i += 1,
+            }
+        }
+        self.open_string = Some(quote);
+        bytes.len()
+    }
+}
+
+/// A statement of a script, which may span several lines
+#[derive(Debug, Clone)]
+pub struct Statement {
+    /// Line (1-based) the statement starts on
+    pub line: usize,
+    /// Number of lines it spans
+    pub lines: usize,
+    /// The statement without comments or `_` markers, its lines joined by `\n`
+    pub code: String,
+}
+
+/// Splits a script into statements. A statement goes on on the next line
+/// when its line ends with `_`, with an operator or comma, inside brackets or
+/// inside a string.
+pub fn statements(script: &str) -> Vec<Statement> {
+    let mut statements = Vec::new();
+    let mut tokenizer = Tokenizer::default();
+    let mut current: Option<Statement> = None;
+    // Brackets left open, not counting the braces of blocks
+    let mut depth = 0usize;
+
+    for (index, line) in script.lines().enumerate() {
+        let tokens = tokenizer.line(line);
+        let code: Vec<&Token> = tokens
+            .iter()
+            .filter(|token| token.kind != TokenKind::Comment)
+            .collect();
+        let mut end = code.last().map_or(0, |token| token.end());
+
+        let statement = current.get_or_insert_with(|| Statement {
+            line: index + 1,
+            lines: 0,
+            code: String::new(),
+        });
+        let opens_block = statement.lines == 0
+            && code.first().map_or(false, |first| {
+                first.is_symbol("}") || BLOCK_WORDS.iter().any(|word| first.is_word(word))
+            });
+
+        for token in code.iter().filter(|token| token.kind == TokenKind::Symbol) {
+            match token.text {
+                "{" if opens_block => {}
+                "(" | "[" | "{" | "#{" => depth += 1,
+                ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+
+        let mut continues = tokenizer.in_string() || depth > 0;
+        match code.last() {
+            Some(last) if last.is_word(LINE_CONTINUATION) => {
+                end = last.start;
+                continues = true;
+            }
+            Some(last) if last.kind == TokenKind::Symbol && CONTINUATIONS.contains(&last.text) => {
+                continues = true
+            }
+            _ => {}
+        }
+
+        if statement.lines > 0 {
+            statement.code.push('\n');
+        }
+        // Text inside a string is kept as it is
+        if tokenizer.in_string() {
+            statement.code.push_str(line);
+        } else {
+            statement.code.push_str(line[..end].trim_end());
+        }
+        statement.lines += 1;
+
+        if !continues {
+            statements.extend(current.take());
+            depth = 0;
+        }
+    }
+
+    statements.extend(current);
+    statements
+}
+
+/// Whether a line's statement goes on on the next line, by its last token
+pub fn continues_line(tokens: &[Token]) -> bool {
+    tokens
+        .iter()
+        .rev()
+        .find(|token| token.kind != TokenKind::Comment)
+        .map_or(false, |last| {
+            last.is_word(LINE_CONTINUATION)
+                || (last.kind == TokenKind::Symbol && CONTINUATIONS.contains(&last.text))
+        })
+}
+
+/// The line without its comment, if it has one
+pub fn strip_comment(line: &str) -> &str {
+    match tokenize_line(line)
+        .iter()
+        .find(|token| token.kind == TokenKind::Comment)
+    {
+        Some(comment) => &line[..comment.start],
+        None => line,
+    }
+}
+
+/// Whether `word` is written like a keyword: upper case, at least two letters
+pub fn is_keyword_like(word: &str) -> bool {
+    word.len() > 1
+        && word.chars().any(|c| c.is_ascii_uppercase())
+        && word
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn is_word_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || !c.is_ascii()
+}
+
+/// Kinds of blocks the lines of a .bas script are nested in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    /// FOR EACH ... NEXT or a `for` loop, bounded by what it iterates
+    For,
+    /// WHILE ... WEND, DO ... LOOP or a `while`/`loop`/`do` loop, which only
+    /// ends when a condition changes
+    While,
+    If,
+    /// SUB or FUNCTION
+    Procedure,
+    /// Brackets left open at the end of a line
+    Brackets,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OpenBlock {
+    kind: BlockKind,
+    // Closed by a bracket rather than by NEXT, END IF, ...
+    bracket: bool,
+}
+
+/// Follows the blocks a script's lines are nested in, one line at a time
+#[derive(Debug, Default)]
+pub struct Blocks {
+    open: Vec<OpenBlock>,
+}
+
+impl Blocks {
+    /// Adds the next line and returns its nesting depth. Lines that close or
+    /// continue a block (`NEXT`, `ELSE`, `}`) are at the depth of the line
+    /// that opened it.
+    pub fn push_line(&mut self, tokens: &[Token]) -> usize {
+        let code: Vec<&Token> = tokens
+            .iter()
+            .filter(|token| token.kind != TokenKind::Comment)
+            .collect();
+        let (first, last) = match (code.first(), code.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return self.open.len(),
+        };
+        let upper = |token: Option<&&Token>| {
+            token
+                .filter(|token| token.kind == TokenKind::Word)
+                .map(|token| token.text.to_ascii_uppercase())
+                .unwrap_or_default()
+        };
+        let statement = upper(Some(&first));
+        let second = upper(code.get(1));
+        let ends_with_bracket = last.is_symbol("{") || last.is_symbol("#{");
+
+        let mut depth = self.open.len();
+        match statement.as_str() {
+            "NEXT" | "WEND" | "LOOP" => depth = self.close_statement(),
+            "END" if matches!(second.as_str(), "IF" | "SUB" | "FUNCTION" | "WHILE") => {
+                depth = self.close_statement()
+            }
+            "ELSE" | "ELSEIF" if !ends_with_bracket => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+
+        // The first bracket of a `while x {` line is the loop's block
+        let first_word = code
+            .iter()
+            .find(|token| token.kind == TokenKind::Word)
+            .map_or("", |token| token.text);
+        let mut bracket_kind = match first_word {
+            "for" => BlockKind::For,
+            "while" | "loop" | "do" => BlockKind::While,
+            "if" | "else" => BlockKind::If,
+            "fn" => BlockKind::Procedure,
+            _ => BlockKind::Brackets,
+        };
+        let mut leading = true;
+        for token in &code {
+            if token.kind != TokenKind::Symbol {
+                leading = false;
+                continue;
+            }
+            match token.text {
+                "(" | "[" | "{" | "#{" => {
+                    self.open.push(OpenBlock {
+                        kind: bracket_kind,
+                        bracket: true,
+                    });
+                    bracket_kind = BlockKind::Brackets;
+                    leading = false;
+                }
+                ")" | "]" | "}" => {
+                    if self.open.last().map_or(false, |block| block.bracket) {
+                        self.open.pop();
+                    }
+                    if leading {
+                        depth = depth.min(self.open.len());
+                    }
+                }
+                _ => leading = false,
+            }
+        }
+
+        let kind = match statement.as_str() {
+            _ if ends_with_bracket => None,
+            "FOR" => Some(BlockKind::For),
+            "WHILE" | "DO" => Some(BlockKind::While),
+            "IF" if upper(Some(&last)) == "THEN" => Some(BlockKind::If),
+            "SUB" | "FUNCTION" => Some(BlockKind::Procedure),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            self.open.push(OpenBlock {
+                kind,
+                bracket: false,
+            });
+        }
+
+        depth
+    }
+
+    /// Number of blocks open after the lines pushed so far
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn in_loop(&self) -> bool {
+        self.open
+            .iter()
+            .any(|block| matches!(block.kind, BlockKind::For | BlockKind::While))
+    }
+
+    /// Directly inside brackets, such as the items of a map or array
+    pub fn in_brackets(&self) -> bool {
+        self.open
+            .last()
+            .map_or(false, |block| block.kind == BlockKind::Brackets)
+    }
+
+    /// Inside a loop that isn't bounded by a collection
+    pub fn in_unbounded_loop(&self) -> bool {
+        self.open.iter().any(|block| block.kind == BlockKind::While)
+    }
+
+    // Closes the innermost block opened by a statement, with any brackets
+    // left open inside it
+    fn close_statement(&mut self) -> usize {
+        if let Some(index) = self.open.iter().rposition(|block| !block.bracket) {
+            self.open.truncate(index);
+        }
+        self.open.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds_and_texts(line: &str) -> Vec<(TokenKind, &str)> {
+        tokenize_line(line)
+            .into_iter()
+            .map(|token| (token.kind, token.text))
+            .collect()
+    }
+
+    #[test]
+    fn tokenizes_words_strings_and_operators() {
+        assert_eq!(
+            kinds_and_texts(r#"x = FIND "it's // here", 1.5 >= y ' note"#),
+            vec![
+                (TokenKind::Word, "x"),
+                (TokenKind::Symbol, "="),
+                (TokenKind::Word, "FIND"),
+                (TokenKind::Str, r#""it's // here""#),
+                (TokenKind::Symbol, ","),
+                (TokenKind::Number, "1.5"),
+                (TokenKind::Symbol, ">="),
+                (TokenKind::Word, "y"),
+                (TokenKind::Comment, "' note"),
+            ]
+        );
+    }
+
+    #[test]
+    fn recognizes_comments() {
+        assert_eq!(
+            kinds_and_texts("REM all"),
+            vec![(TokenKind::Comment, "REM all")]
+        );
+        assert_eq!(
+            kinds_and_texts("# all"),
+            vec![(TokenKind::Comment, "# all")]
+        );
+        assert_eq!(kinds_and_texts("REMARK")[0], (TokenKind::Word, "REMARK"));
+        assert_eq!(kinds_and_texts("#{a: 1}")[0], (TokenKind::Symbol, "#{"));
+    }
+
+    #[test]
+    fn statements_join_continued_lines() {
+        let script = "total = a + _\n  b\nitems = [1,\n2]\nTALK \"one\n two\"\nPRINT total ' done";
+        let statements = statements(script);
+        let summary: Vec<(usize, usize, &str)> = statements
+            .iter()
+            .map(|statement| (statement.line, statement.lines, statement.code.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, 2, "total = a +\n  b"),
+                (3, 2, "items = [1,\n2]"),
+                (5, 2, "TALK \"one\n two\""),
+                (7, 1, "PRINT total"),
+            ]
+        );
+    }
+
+    #[test]
+    fn block_braces_end_statements() {
+        let statements = statements("if x {\n  y = 1\n}");
+        assert_eq!(statements.len(), 3);
+    }
+}
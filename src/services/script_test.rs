@@ -0,0 +1,429 @@
+use crate::services::config::ScriptLimits;
+use crate::services::script::ScriptService;
+use crate::services::script_backend::{MockBackend, MockResponses, RecordedCall};
+use crate::services::utils::{dynamic_to_json, json_value_to_dynamic};
+use rhai::Scope;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Suffix of the expectation files kept next to the scripts they test
+const TEST_FILE_SUFFIX: &str = ".test.toml";
+const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Contents of a `<script>.test.toml` file
+#[derive(Debug, Deserialize)]
+pub struct ScriptTestFile {
+    #[serde(rename = "case", default)]
+    pub cases: Vec<ScriptTestCase>,
+}
+
+/// One run of the script with its PARAMs, mocks and expectations
+#[derive(Debug, Deserialize)]
+pub struct ScriptTestCase {
+    pub name: String,
+    #[serde(default)]
+    pub params: Map<String, Value>,
+    #[serde(default)]
+    pub mocks: MockResponses,
+    #[serde(default)]
+    pub expect: Expectations,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Expectations {
+    /// Value of RETURN (or of the last expression)
+    #[serde(rename = "return")]
+    pub return_value: Option<Value>,
+    /// Every PRINT line, in order
+    pub print: Option<Vec<String>>,
+    /// Keyword calls that must happen in this order; other calls may come in
+    /// between. Calls without `args` match any arguments.
+    #[serde(default)]
+    pub calls: Vec<ExpectedCall>,
+    /// Substring of the error the script must fail with
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExpectedCall {
+    pub keyword: String,
+    pub args: Option<Vec<Value>>,
+}
+
+/// Outcome of one test case
+#[derive(Debug)]
+pub struct ScriptTestResult {
+    pub script: PathBuf,
+    pub case: String,
+    pub failures: Vec<String>,
+}
+
+impl ScriptTestResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Finds the `*.test.toml` files below `dir`
+pub fn discover_tests(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            found.extend(discover_tests(&path)?);
+        } else if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.ends_with(TEST_FILE_SUFFIX))
+        {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// The script a test file belongs to: `name.test.toml` tests `name.bas`
+pub fn script_for(test_file: &Path) -> PathBuf {
+    let name = test_file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let stem = name.strip_suffix(TEST_FILE_SUFFIX).unwrap_or(name);
+    test_file.with_file_name(format!("{}.bas", stem))
+}
+
+/// Runs every case of a test file against its script
+pub fn run_test_file(test_file: &Path) -> Result<Vec<ScriptTestResult>, String> {
+    let contents = std::fs::read_to_string(test_file)
+        .map_err(|e| format!("Failed to read {}: {}", test_file.display(), e))?;
+    let tests: ScriptTestFile = toml::from_str(&contents)
+        .map_err(|e| format!("Invalid test file {}: {}", test_file.display(), e))?;
+
+    let script_path = script_for(test_file);
+    let script = std::fs::read_to_string(&script_path)
+        .map_err(|e| format!("Failed to read {}: {}", script_path.display(), e))?;
+
+    Ok(tests
+        .cases
+        .into_iter()
+        .map(|case| ScriptTestResult {
+            script: script_path.clone(),
+            failures: run_case(&script, &script_path.to_string_lossy(), &case),
+            case: case.name,
+        })
+        .collect())
+}
+
+/// Runs one case with a fresh `MockBackend` and returns what didn't match.
+/// `source` is the name ON and SET_SCHEDULE register the script under.
+pub fn run_case(script: &str, source: &str, case: &ScriptTestCase) -> Vec<String> {
+    let backend = Arc::new(MockBackend::new(case.mocks.clone()));
+    let script_service = ScriptService::with_backend(backend.clone(), ScriptLimits::default());
+
+    let mut ast = match script_service.compile(script) {
+        Ok(ast) => ast,
+        Err(e) => return vec![format!("Error compiling script: {}", e)],
+    };
+    ast.set_source(source);
+
+    let mut scope = Scope::new();
+    for (name, value) in &case.params {
+        scope.push(name.clone(), json_value_to_dynamic(value));
+    }
+    let result = script_service
+        .run_with_timeout(&ast, &mut scope, TEST_TIMEOUT)
+        .map(|value| dynamic_to_json(&value))
+        .map_err(|e| e.to_string());
+
+    check_expectations(&case.expect, result, &backend.output(), &backend.calls())
+}
+
+fn check_expectations(
+    expect: &Expectations,
+    result: Result<Value, String>,
+    output: &[String],
+    calls: &[RecordedCall],
+) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    match (&result, &expect.error) {
+        (Err(error), Some(expected)) if !error.contains(expected.as_str()) => failures.push(
+            format!("expected error containing {:?}, got {:?}", expected, error),
+        ),
+        (Err(error), None) => failures.push(format!("script failed: {}", error)),
+        (Ok(value), Some(expected)) => failures.push(format!(
+            "expected error containing {:?}, but script returned {}",
+            expected, value
+        )),
+        _ => {}
+    }
+
+    if let (Ok(value), Some(expected)) = (&result, &expect.return_value) {
+        if value != expected {
+            failures.push(format!("expected RETURN {}, got {}", expected, value));
+        }
+    }
+
+    if let Some(expected) = &expect.print {
+        if output != expected.as_slice() {
+            failures.push(format!("expected PRINT {:?}, got {:?}", expected, output));
+        }
+    }
+
+    // Expected calls must appear in order, possibly with other calls between
+    let mut remaining = calls.iter();
+    for expected in &expect.calls {
+        let matched = remaining.any(|call| {
+            call.keyword.eq_ignore_ascii_case(&expected.keyword)
+                && expected
+                    .args
+                    .as_ref()
+                    .map_or(true, |args| *args == call.args)
+        });
+        if !matched {
+            failures.push(format!(
+                "expected call {} {} not found in {}",
+                expected.keyword,
+                expected
+                    .args
+                    .as_ref()
+                    .map(|args| Value::from(args.clone()).to_string())
+                    .unwrap_or_default(),
+                describe_calls(calls)
+            ));
+            break;
+        }
+    }
+
+    failures
+}
+
+fn describe_calls(calls: &[RecordedCall]) -> String {
+    if calls.is_empty() {
+        return "no calls".to_string();
+    }
+    calls
+        .iter()
+        .map(|call| format!("{} {}", call.keyword, Value::from(call.args.clone())))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `gbserver test-scripts [dir]`: runs every script test below `dir` and
+/// returns the process exit code
+pub fn test_scripts_command(dir: &str) -> i32 {
+    let test_files = match discover_tests(Path::new(dir)) {
+        Ok(test_files) => test_files,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", dir, e);
+            return 2;
+        }
+    };
+
+    let (mut passed, mut failed) = (0, 0);
+    for test_file in &test_files {
+        let results = match run_test_file(test_file) {
+            Ok(results) => results,
+            Err(e) => {
+                eprintln!("ERROR {}", e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        for result in results {
+            if result.passed() {
+                println!("ok   {} :: {}", result.script.display(), result.case);
+                passed += 1;
+            } else {
+                println!("FAIL {} :: {}", result.script.display(), result.case);
+                for failure in &result.failures {
+                    println!("     {}", failure);
+                }
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "\n{} passed, {} failed, {} test files",
+        passed,
+        failed,
+        test_files.len()
+    );
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn expectations(toml: &str) -> Expectations {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn call(keyword: &str, args: Value) -> RecordedCall {
+        RecordedCall {
+            keyword: keyword.to_string(),
+            args: args.as_array().cloned().unwrap_or_default(),
+        }
+    }
+
+    fn recorded() -> Vec<RecordedCall> {
+        vec![
+            call("FIND", json!(["customers", "id=1"])),
+            call("GET", json!(["https://example.com"])),
+            call("SET", json!(["customers", "id=1", "status=done"])),
+        ]
+    }
+
+    #[test]
+    fn calls_match_in_order_with_others_between() {
+        let expect = expectations(
+            r#"
+            [[calls]]
+            keyword = "find"
+            args = ["customers", "id=1"]
+
+            [[calls]]
+            keyword = "SET"
+            args = ["customers", "id=1", "status=done"]
+            "#,
+        );
+        assert!(check_expectations(&expect, Ok(Value::Null), &[], &recorded()).is_empty());
+    }
+
+    #[test]
+    fn calls_out_of_order_or_with_other_args_fail() {
+        let out_of_order = expectations(
+            r#"
+            [[calls]]
+            keyword = "SET"
+
+            [[calls]]
+            keyword = "FIND"
+            "#,
+        );
+        let failures = check_expectations(&out_of_order, Ok(Value::Null), &[], &recorded());
+        assert_eq!(failures.len(), 1);
+        assert!(
+            failures[0].starts_with("expected call FIND"),
+            "{}",
+            failures[0]
+        );
+
+        let other_args = expectations(
+            r#"
+            [[calls]]
+            keyword = "FIND"
+            args = ["orders"]
+            "#,
+        );
+        assert_eq!(
+            check_expectations(&other_args, Ok(Value::Null), &[], &recorded()).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn calls_without_args_match_any_args() {
+        let expect = expectations(
+            r#"
+            [[calls]]
+            keyword = "GET"
+            "#,
+        );
+        assert!(check_expectations(&expect, Ok(Value::Null), &[], &recorded()).is_empty());
+        assert_eq!(
+            check_expectations(&expect, Ok(Value::Null), &[], &[]),
+            vec!["expected call GET  not found in no calls".to_string()]
+        );
+    }
+
+    #[test]
+    fn errors_match_by_substring() {
+        let expect = expectations(r#"error = "not found""#);
+        let failed = |error: &str| Err(error.to_string());
+
+        assert!(
+            check_expectations(&expect, failed("Table customers not found"), &[], &[]).is_empty()
+        );
+        assert_eq!(
+            check_expectations(&expect, failed("Permission denied"), &[], &[]).len(),
+            1
+        );
+        assert_eq!(
+            check_expectations(&expect, Ok(json!(1)), &[], &[]),
+            vec!["expected error containing \"not found\", but script returned 1".to_string()]
+        );
+        assert_eq!(
+            check_expectations(&Expectations::default(), failed("boom"), &[], &[]),
+            vec!["script failed: boom".to_string()]
+        );
+    }
+
+    #[test]
+    fn return_and_print_must_match() {
+        let expect = expectations(
+            r#"
+            return = 3
+            print = ["one", "two"]
+            "#,
+        );
+        let output = ["one".to_string(), "two".to_string()];
+        assert!(check_expectations(&expect, Ok(json!(3)), &output, &[]).is_empty());
+
+        let failures = check_expectations(&expect, Ok(json!(4)), &output[..1], &[]);
+        assert_eq!(
+            failures,
+            vec![
+                "expected RETURN 3, got 4".to_string(),
+                "expected PRINT [\"one\", \"two\"], got [\"one\"]".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn script_for_replaces_the_test_suffix() {
+        assert_eq!(
+            script_for(Path::new("bots/sales/welcome.test.toml")),
+            PathBuf::from("bots/sales/welcome.bas")
+        );
+        assert_eq!(
+            script_for(Path::new("a.b.test.toml")),
+            PathBuf::from("a.b.bas")
+        );
+    }
+
+    #[test]
+    fn discover_tests_finds_test_files_recursively_and_sorted() {
+        let dir = std::env::temp_dir().join(format!("gb-script-tests-{}", std::process::id()));
+        let nested = dir.join("nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        for file in [
+            dir.join("b.test.toml"),
+            dir.join("b.bas"),
+            dir.join("notes.toml"),
+            nested.join("a.test.toml"),
+        ] {
+            std::fs::write(file, "").unwrap();
+        }
+
+        let found = discover_tests(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            found.unwrap(),
+            vec![dir.join("b.test.toml"), nested.join("a.test.toml")]
+        );
+    }
+}
@@ -2,7 +2,9 @@ use std::sync::Arc;
 
 use minio::s3::Client;
 
-use crate::services::{config::AppConfig, web_automation::BrowserPool};
+use crate::services::{
+    config::AppConfig, email::Mailer, imap_pool::ImapPool, web_automation::BrowserPool,
+};
 
 
 #[derive(Clone)]
@@ -12,5 +14,7 @@ pub struct AppState {
     pub db:  Option<sqlx::PgPool>,
     pub db_custom:  Option<sqlx::PgPool>,
     pub browser_pool: Arc<BrowserPool>,
+    pub imap: Arc<ImapPool>,
+    pub mailer: Arc<Mailer>,
 }
 
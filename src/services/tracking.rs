@@ -0,0 +1,153 @@
+use crate::services::state::AppState;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// Signatures are truncated to 128 bits to keep tracked URLs short
+const SIGNATURE_BYTES: usize = 16;
+
+/// Key the tracked links and open pixels of campaigns are signed with
+pub fn tracking_secret(state: &AppState) -> Option<&str> {
+    state
+        .config
+        .as_ref()
+        .and_then(|config| config.email.tracking_secret.as_deref())
+}
+
+fn mac(secret: &str, campaign_id: &str, kind: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    // The campaign and kind are signed too, so a signature is only valid for
+    // the campaign and purpose it was made for
+    for part in [campaign_id, kind, payload] {
+        mac.update(part.as_bytes());
+        mac.update(b"\n");
+    }
+    mac
+}
+
+fn sign(secret: &str, campaign_id: &str, kind: &str, payload: &str) -> String {
+    let signature = mac(secret, campaign_id, kind, payload)
+        .finalize()
+        .into_bytes();
+    hex::encode(&signature[..SIGNATURE_BYTES])
+}
+
+fn verify(secret: &str, campaign_id: &str, kind: &str, payload: &str, signature: &str) -> bool {
+    match hex::decode(signature) {
+        Ok(signature) if signature.len() == SIGNATURE_BYTES => {
+            mac(secret, campaign_id, kind, payload)
+                .verify_truncated_left(&signature)
+                .is_ok()
+        }
+        _ => false,
+    }
+}
+
+/// Token of a tracked link: the recipient and destination URL, hex-encoded,
+/// and their signature
+pub fn link_token(secret: &str, campaign_id: &str, email: &str, url: &str) -> String {
+    let payload = format!("{}\n{}", email, url);
+    format!(
+        "{}.{}",
+        hex::encode(&payload),
+        sign(secret, campaign_id, "click", &payload)
+    )
+}
+
+/// The recipient and destination URL of a link token, if its signature holds
+pub fn verify_link_token(secret: &str, campaign_id: &str, token: &str) -> Option<(String, String)> {
+    let (payload, signature) = token.split_once('.')?;
+    let payload = String::from_utf8(hex::decode(payload).ok()?).ok()?;
+    if !verify(secret, campaign_id, "click", &payload, signature) {
+        return None;
+    }
+    let (email, url) = payload.split_once('\n')?;
+    Some((email.to_string(), url.to_string()))
+}
+
+/// Signature of the open pixel of `email`, passed as its `sig` parameter
+pub fn open_signature(secret: &str, campaign_id: &str, email: &str) -> String {
+    sign(secret, campaign_id, "open", email)
+}
+
+pub fn verify_open_signature(
+    secret: &str,
+    campaign_id: &str,
+    email: &str,
+    signature: &str,
+) -> bool {
+    verify(secret, campaign_id, "open", email, signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "secret";
+
+    #[test]
+    fn link_token_round_trips() {
+        let token = link_token(SECRET, "c1", "ana@example.com", "https://example.com/a?b=1");
+        assert_eq!(
+            verify_link_token(SECRET, "c1", &token),
+            Some((
+                "ana@example.com".to_string(),
+                "https://example.com/a?b=1".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn link_token_rejects_tampering() {
+        let token = link_token(SECRET, "c1", "ana@example.com", "https://example.com");
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            hex::encode("ana@example.com\nhttps://evil.example"),
+            signature
+        );
+
+        assert_eq!(verify_link_token(SECRET, "c1", &forged), None);
+        assert_eq!(verify_link_token("other", "c1", &token), None);
+        assert_eq!(verify_link_token(SECRET, "c1", "not a token"), None);
+        assert_eq!(
+            verify_link_token(SECRET, "c1", &token[..token.len() - 2]),
+            None
+        );
+    }
+
+    #[test]
+    fn signatures_only_hold_for_their_campaign_and_kind() {
+        let token = link_token(SECRET, "c1", "ana@example.com", "https://example.com");
+        assert_eq!(verify_link_token(SECRET, "c2", &token), None);
+
+        let signature = open_signature(SECRET, "c1", "ana@example.com");
+        assert!(verify_open_signature(
+            SECRET,
+            "c1",
+            "ana@example.com",
+            &signature
+        ));
+        assert!(!verify_open_signature(
+            SECRET,
+            "c2",
+            "ana@example.com",
+            &signature
+        ));
+        assert!(!verify_open_signature(
+            SECRET,
+            "c1",
+            "bo@example.com",
+            &signature
+        ));
+
+        // An open signature can't be passed off as a click, nor the reverse
+        let payload = "ana@example.com\nhttps://example.com";
+        let click = format!(
+            "{}.{}",
+            hex::encode(payload),
+            sign(SECRET, "c1", "open", payload)
+        );
+        assert_eq!(verify_link_token(SECRET, "c1", &click), None);
+    }
+}
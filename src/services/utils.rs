@@ -183,6 +183,38 @@ pub fn json_value_to_dynamic(value: &Value) -> Dynamic {
     }
 }
 
+pub fn dynamic_to_json(value: &Dynamic) -> Value {
+    if value.is_unit() {
+        Value::Null
+    } else if let Ok(b) = value.as_bool() {
+        json!(b)
+    } else if let Ok(i) = value.as_int() {
+        json!(i)
+    } else if let Ok(f) = value.as_float() {
+        json!(f)
+    } else if value.is_array() {
+        Value::Array(
+            value
+                .clone()
+                .cast::<Array>()
+                .iter()
+                .map(dynamic_to_json)
+                .collect(),
+        )
+    } else if value.is_map() {
+        Value::Object(
+            value
+                .clone()
+                .cast::<rhai::Map>()
+                .iter()
+                .map(|(k, v)| (k.to_string(), dynamic_to_json(v)))
+                .collect(),
+        )
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
 /// Converts any value to an array - single values become single-element arrays
 pub fn to_array(value: Dynamic) -> Array {
     if value.is_array() {